@@ -0,0 +1,444 @@
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD},
+    Engine,
+};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::{headers::Headers, prelude::*, url};
+
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+const HASH_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+
+/// What a client presented in its `Authorization` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    Basic { user: String, password: String },
+    Bearer(String),
+}
+
+impl TryFrom<&str> for Credentials {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        let (scheme, value) = s
+            .trim()
+            .split_once(' ')
+            .ok_or(Error::Generic("Failed to parse credentials.".into()))?;
+
+        let value = value.trim();
+
+        if scheme.eq_ignore_ascii_case("Basic") {
+            let decoded = STANDARD
+                .decode(value)
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or(Error::Generic("Failed to decode credentials.".into()))?;
+
+            let (user, password) = decoded
+                .split_once(':')
+                .ok_or(Error::Generic("Failed to parse credentials.".into()))?;
+
+            Ok(Self::Basic {
+                user: user.into(),
+                password: password.into(),
+            })
+        } else if scheme.eq_ignore_ascii_case("Bearer") {
+            Ok(Self::Bearer(value.into()))
+        } else {
+            Err(Error::Generic("Unknown authentication scheme.".into()))
+        }
+    }
+}
+
+// Comparing digests rather than the secrets themselves keeps the comparison time independent of
+// both the contents and the length of the secret.
+fn digest_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn pbkdf2(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mac = <Hmac<Sha256> as Mac>::new_from_slice(password).expect("HMAC accepts any key");
+
+    let mut block = mac.clone();
+    block.update(salt);
+    block.update(&1u32.to_be_bytes());
+    let mut u: [u8; 32] = block.finalize().into_bytes().into();
+    let mut output = u;
+
+    for _ in 1..iterations {
+        let mut round = mac.clone();
+        round.update(&u);
+        u = round.finalize().into_bytes().into();
+        output.iter_mut().zip(u).for_each(|(o, x)| *o ^= x);
+    }
+
+    output
+}
+
+/// A password hash in the form `$pbkdf2-sha256$ITERATIONS$SALT$HASH`, with the salt and hash in
+/// unpadded base64.
+#[derive(Debug, Clone)]
+pub struct PasswordHash {
+    iterations: u32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl PasswordHash {
+    pub fn new(password: &str) -> Result<Self> {
+        let mut salt = vec![0; SALT_LEN];
+        getrandom::getrandom(&mut salt)
+            .map_err(|e| Error::Generic(format!("Failed to generate salt: {e}.")))?;
+
+        let hash = pbkdf2(password.as_bytes(), &salt, HASH_ITERATIONS).to_vec();
+
+        Ok(Self {
+            iterations: HASH_ITERATIONS,
+            salt,
+            hash,
+        })
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        let hash = pbkdf2(password.as_bytes(), &self.salt, self.iterations);
+        digest_eq(&hash, &self.hash)
+    }
+}
+
+impl fmt::Display for PasswordHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "${HASH_SCHEME}${}${}${}",
+            self.iterations,
+            STANDARD_NO_PAD.encode(&self.salt),
+            STANDARD_NO_PAD.encode(&self.hash)
+        )
+    }
+}
+
+impl TryFrom<&str> for PasswordHash {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        let error = || Error::Generic("Failed to parse password hash.".into());
+
+        match s.split('$').collect::<Vec<_>>()[..] {
+            ["", HASH_SCHEME, iterations, salt, hash] => Ok(Self {
+                iterations: iterations.parse().map_err(|_| error())?,
+                salt: STANDARD_NO_PAD.decode(salt).map_err(|_| error())?,
+                hash: STANDARD_NO_PAD.decode(hash).map_err(|_| error())?,
+            }),
+            _ => Err(error()),
+        }
+    }
+}
+
+/// Paths whose requests must be authenticated, optionally only for some methods.
+#[derive(Debug, Clone)]
+pub struct AuthRule {
+    prefix: Vec<String>,
+    methods: Vec<Method>,
+}
+
+impl AuthRule {
+    /// Parses a `--protect` value of the form `PREFIX[=METHOD,...]`. Without methods, every
+    /// method is protected.
+    pub fn parse(s: &str) -> Result<Self> {
+        let (prefix, methods) = s.split_once('=').unwrap_or((s, ""));
+
+        let methods = methods
+            .split(',')
+            .filter(|m| !m.is_empty())
+            .map(|m| Method::try_from(m.trim().to_ascii_uppercase().as_str()))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            prefix: url::path_segments(prefix).ok_or(Error::Generic(
+                "Protected prefix climbs above the root.".into(),
+            ))?,
+            methods,
+        })
+    }
+
+    /// Whether the rule covers the request, by the same segments the router matches on. A path
+    /// that can't be resolved is covered by every rule.
+    pub fn applies(&self, rq: &Request) -> bool {
+        rq.segments()
+            .map_or(true, |segments| segments.starts_with(&self.prefix))
+            && (self.methods.is_empty() || self.methods.iter().any(|m| m.admits(&rq.method)))
+    }
+}
+
+/// Users and tokens that may access protected paths.
+#[derive(Debug, Clone)]
+pub struct Auth {
+    pub realm: String,
+    pub rules: Vec<AuthRule>,
+    users: HashMap<String, PasswordHash>,
+    tokens: Vec<(String, String)>,
+    /// Checked in place of an unknown user's hash, so that timing doesn't reveal who exists.
+    decoy: PasswordHash,
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Self {
+            realm: "Restricted".into(),
+            rules: Vec::new(),
+            users: HashMap::new(),
+            tokens: Vec::new(),
+            decoy: PasswordHash {
+                iterations: HASH_ITERATIONS,
+                salt: vec![0; SALT_LEN],
+                hash: vec![0; 32],
+            },
+        }
+    }
+}
+
+// Lines of a credentials or token file, skipping blanks and `#` comments.
+fn read_lines(path: &Path) -> Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+impl Auth {
+    /// Loads `USER:HASH` lines, as printed by `--hash-password`.
+    pub fn load_credentials(&mut self, path: &Path) -> Result<()> {
+        for line in read_lines(path)? {
+            let (user, hash) = line.split_once(':').ok_or(Error::Generic(format!(
+                "Failed to parse credentials in '{}'.",
+                path.display()
+            )))?;
+
+            self.users
+                .insert(user.into(), PasswordHash::try_from(hash)?);
+        }
+
+        Ok(())
+    }
+
+    /// Loads bearer tokens, one per line, each optionally named as `NAME:TOKEN`.
+    pub fn load_tokens(&mut self, path: &Path) -> Result<()> {
+        for line in read_lines(path)? {
+            let (name, token) = line.split_once(':').unwrap_or(("bearer", &line));
+            self.tokens.push((name.into(), token.into()));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.users.is_empty() || !self.tokens.is_empty()
+    }
+
+    pub fn protects(&self, rq: &Request) -> bool {
+        self.rules.iter().any(|rule| rule.applies(rq))
+    }
+
+    /// The name of the user or token the request is authenticated as.
+    pub fn authenticate(&self, rq: &Request) -> Option<String> {
+        let credentials = Credentials::try_from(rq.header("Authorization")?.as_str()).ok()?;
+
+        match credentials {
+            Credentials::Basic { user, password } => {
+                let hash = self.users.get(&user);
+                let verified = hash.unwrap_or(&self.decoy).verify(&password);
+                (hash.is_some() && verified).then_some(user)
+            }
+            // Every token is checked so that timing doesn't reveal which one came close.
+            Credentials::Bearer(token) => self
+                .tokens
+                .iter()
+                .filter(|(_, t)| digest_eq(t.as_bytes(), token.as_bytes()))
+                .fold(None, |found, (name, _)| found.or(Some(name.clone()))),
+        }
+    }
+
+    /// Adds a `WWW-Authenticate` challenge for each scheme that could succeed.
+    pub fn challenge(&self, rq: &Request, headers: &mut Headers) {
+        let realm = escape_json(&self.realm);
+
+        if !self.users.is_empty() {
+            headers.append(
+                "WWW-Authenticate",
+                format!("Basic realm={realm}, charset=\"UTF-8\""),
+            );
+        }
+
+        if !self.tokens.is_empty() {
+            let presented = rq
+                .header("Authorization")
+                .is_some_and(|h| h.to_ascii_lowercase().starts_with("bearer "));
+
+            let challenge = match presented {
+                true => format!("Bearer realm={realm}, error=\"invalid_token\""),
+                false => format!("Bearer realm={realm}"),
+            };
+
+            headers.append("WWW-Authenticate", challenge);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(head: &str, test: impl FnOnce(&Request)) {
+        let mut reader = head.as_bytes();
+        let mut interim = Vec::new();
+        test(&Request::parse(&mut reader, &mut interim, 0).unwrap());
+    }
+
+    // A hash that's quick to check, as the iterations are stored with it.
+    fn quick_hash(password: &str) -> PasswordHash {
+        PasswordHash {
+            iterations: 1,
+            salt: b"salt".to_vec(),
+            hash: pbkdf2(password.as_bytes(), b"salt", 1).to_vec(),
+        }
+    }
+
+    fn applies(rule: &str, method: &str, path: &str) -> bool {
+        let rule = AuthRule::parse(rule).unwrap();
+        let mut applies = false;
+        request(&format!("{method} {path} HTTP/1.1\r\n\r\n"), |rq| {
+            applies = rule.applies(rq)
+        });
+        applies
+    }
+
+    #[test]
+    fn parses_credentials() {
+        assert_eq!(
+            Credentials::try_from("Basic YWxpY2U6czNjcjN0OmV4dHJh").unwrap(),
+            Credentials::Basic {
+                user: "alice".into(),
+                password: "s3cr3t:extra".into()
+            }
+        );
+        assert_eq!(
+            Credentials::try_from("bearer  abc.def ").unwrap(),
+            Credentials::Bearer("abc.def".into())
+        );
+
+        for header in ["", "Basic", "Basic !!!", "Basic YWxpY2U=", "Digest abc"] {
+            assert!(Credentials::try_from(header).is_err(), "{header}");
+        }
+    }
+
+    #[test]
+    fn derives_pbkdf2_keys() {
+        assert_eq!(
+            pbkdf2(b"password", b"salt", 1),
+            [
+                0x12, 0x0f, 0xb6, 0xcf, 0xfc, 0xf8, 0xb3, 0x2c, 0x43, 0xe7, 0x22, 0x52, 0x56, 0xc4,
+                0xf8, 0x37, 0xa8, 0x65, 0x48, 0xc9, 0x2c, 0xcc, 0x35, 0x48, 0x08, 0x05, 0x98, 0x7c,
+                0xb7, 0x0b, 0xe1, 0x7b
+            ]
+        );
+        assert_eq!(
+            pbkdf2(b"password", b"salt", 2),
+            [
+                0xae, 0x4d, 0x0c, 0x95, 0xaf, 0x6b, 0x46, 0xd3, 0x2d, 0x0a, 0xdf, 0xf9, 0x28, 0xf0,
+                0x6d, 0xd0, 0x2a, 0x30, 0x3f, 0x8e, 0xf3, 0xc2, 0x51, 0xdf, 0xd6, 0xe2, 0xd8, 0x5a,
+                0x95, 0x47, 0x4c, 0x43
+            ]
+        );
+    }
+
+    #[test]
+    fn verifies_password_hashes() {
+        let new = PasswordHash::new("s3cr3t").unwrap().to_string();
+        assert!(new.starts_with("$pbkdf2-sha256$100000$"));
+
+        let hash = quick_hash("s3cr3t").to_string();
+        let parsed = PasswordHash::try_from(hash.as_str()).unwrap();
+
+        assert_eq!(parsed.to_string(), hash);
+        assert!(parsed.verify("s3cr3t"));
+        assert!(!parsed.verify("s3cr3"));
+    }
+
+    #[test]
+    fn rejects_malformed_password_hashes() {
+        for hash in [
+            "",
+            "s3cr3t",
+            "$pbkdf2-sha256$100000$c2FsdA",
+            "$bcrypt$100000$c2FsdA$aGFzaA",
+            "$pbkdf2-sha256$many$c2FsdA$aGFzaA",
+            "$pbkdf2-sha256$100000$!!$aGFzaA",
+            "$pbkdf2-sha256$100000$c2FsdA$aGFzaA$",
+        ] {
+            assert!(PasswordHash::try_from(hash).is_err(), "{hash}");
+        }
+    }
+
+    #[test]
+    fn matches_rules_by_prefix_and_method() {
+        assert!(applies("/files", "GET", "/files"));
+        assert!(applies("files/", "GET", "/files/a/b?x=1"));
+        assert!(!applies("/files", "GET", "/filesystem"));
+        assert!(!applies("/files", "GET", "/"));
+        assert!(applies("/", "GET", "/anything"));
+
+        assert!(applies("/files=post,PUT", "PUT", "/files/a"));
+        assert!(!applies("/files=POST,PUT", "GET", "/files/a"));
+        assert!(AuthRule::parse("/files=FETCH").is_err());
+    }
+
+    #[test]
+    fn matches_rules_however_the_path_is_spelled() {
+        for path in [
+            "//files/x",
+            "/files//x",
+            "/%66iles/x",
+            "/%66%69%6c%65%73",
+            "/./files/x",
+            "/other/../files/x",
+            "/files/x/..",
+        ] {
+            assert!(applies("/files=POST", "POST", path), "{path}");
+        }
+
+        assert!(applies("/files/private", "GET", "/files%2Fprivate/key"));
+        assert!(!applies("/files", "POST", "/files/../other"));
+    }
+
+    #[test]
+    fn authenticates_users_and_tokens() {
+        let mut auth = Auth::default();
+        auth.users.insert("alice".into(), quick_hash("s3cr3t"));
+        auth.tokens.push(("ci".into(), "t0k3n".into()));
+
+        let check = |authorization: &str| {
+            let mut user = None;
+            request(
+                &format!("GET / HTTP/1.1\r\nAuthorization: {authorization}\r\n\r\n"),
+                |rq| user = auth.authenticate(rq),
+            );
+            user
+        };
+
+        // alice:s3cr3t, alice:wrong and mallory:s3cr3t.
+        assert_eq!(check("Basic YWxpY2U6czNjcjN0"), Some("alice".into()));
+        assert_eq!(check("Basic YWxpY2U6d3Jvbmc="), None);
+        assert_eq!(check("Basic bWFsbG9yeTpzM2NyM3Q="), None);
+
+        assert_eq!(check("Bearer t0k3n"), Some("ci".into()));
+        assert_eq!(check("Bearer t0k3"), None);
+        assert_eq!(check("Negotiate t0k3n"), None);
+    }
+}
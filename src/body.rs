@@ -0,0 +1,473 @@
+use std::{
+    error, fmt,
+    io::{self, BufRead, Read, Write},
+};
+
+const MAX_CHUNK_LINE: usize = 1024;
+
+/// Raised through `io::Error` when a request body grows beyond the configured limit.
+#[derive(Debug)]
+pub struct BodyTooLarge;
+
+impl error::Error for BodyTooLarge {}
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request body exceeds the maximum size.")
+    }
+}
+
+/// Raised through `io::Error` when a chunked body doesn't follow the format.
+#[derive(Debug)]
+pub struct InvalidChunk(&'static str);
+
+impl error::Error for InvalidChunk {}
+
+impl fmt::Display for InvalidChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, InvalidChunk(msg))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    Empty,
+    Length(u64),
+    Chunked {
+        remaining: u64,
+        done: bool,
+    },
+    /// Whatever the reader yields until it ends, for protocols that frame bodies themselves.
+    Eof,
+}
+
+/// A message body read off `R` as its framing says, which leaves the reader at the start of the
+/// next message.
+pub struct Framed<R> {
+    reader: R,
+    framing: Framing,
+}
+
+impl<R: BufRead> Framed<R> {
+    /// Frames a body by `Transfer-Encoding: chunked` or else by its `Content-Length`. Without
+    /// either, the body is empty, unless it's `delimited` by the end of the reader.
+    pub fn new(reader: R, chunked: bool, length: Option<u64>, delimited: bool) -> Self {
+        let framing = match (chunked, length) {
+            (true, _) => Framing::Chunked {
+                remaining: 0,
+                done: false,
+            },
+            (false, Some(0)) => Framing::Empty,
+            (false, Some(n)) => Framing::Length(n),
+            (false, None) if delimited => Framing::Eof,
+            (false, None) => Framing::Empty,
+        };
+
+        Self { reader, framing }
+    }
+
+    fn read_chunk_size(&mut self) -> io::Result<u64> {
+        let line = self.read_line()?;
+
+        if line.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let size = line.split(';').next().unwrap_or_default().trim();
+
+        u64::from_str_radix(size, 16).map_err(|_| invalid("Invalid chunk size."))
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        (&mut self.reader)
+            .take(MAX_CHUNK_LINE as u64)
+            .read_line(&mut line)?;
+
+        // The rest of a line cut off at the limit would otherwise be read as chunk data.
+        if line.len() == MAX_CHUNK_LINE && !line.ends_with('\n') {
+            return Err(invalid("Chunk line too long."));
+        }
+
+        Ok(line)
+    }
+}
+
+impl<R: BufRead> Read for Framed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.framing {
+            Framing::Empty => Ok(0),
+            Framing::Eof => self.reader.read(buf),
+            Framing::Length(remaining) => {
+                let max = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+                let n = self.reader.read(&mut buf[..max])?;
+
+                if n == 0 && max > 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+
+                self.framing = match remaining - n as u64 {
+                    0 => Framing::Empty,
+                    remaining => Framing::Length(remaining),
+                };
+
+                Ok(n)
+            }
+            Framing::Chunked { done: true, .. } => Ok(0),
+            Framing::Chunked { remaining: 0, .. } => {
+                let size = self.read_chunk_size()?;
+
+                if size == 0 {
+                    // Skip any trailer fields up to the terminating empty line.
+                    while !matches!(self.read_line()?.as_str(), "\r\n" | "\n" | "") {}
+
+                    self.framing = Framing::Chunked {
+                        remaining: 0,
+                        done: true,
+                    };
+
+                    return Ok(0);
+                }
+
+                self.framing = Framing::Chunked {
+                    remaining: size,
+                    done: false,
+                };
+
+                self.read(buf)
+            }
+            Framing::Chunked { remaining, .. } => {
+                let max = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+                let n = self.reader.read(&mut buf[..max])?;
+
+                if n == 0 && max > 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+
+                let remaining = remaining - n as u64;
+
+                // Each chunk's data ends with a line break and nothing else.
+                if remaining == 0 {
+                    match self.read_line()?.as_str() {
+                        "\r\n" | "\n" => {}
+                        "" => return Err(io::ErrorKind::UnexpectedEof.into()),
+                        _ => return Err(invalid("Chunk data overruns its size.")),
+                    }
+                }
+
+                self.framing = Framing::Chunked {
+                    remaining,
+                    done: false,
+                };
+
+                Ok(n)
+            }
+        }
+    }
+}
+
+/// A request body, read straight from the connection as the handler consumes it.
+pub struct Body<'a> {
+    framed: Framed<&'a mut dyn BufRead>,
+    interim: Option<&'a mut dyn Write>,
+    length: Option<u64>,
+    limit: u64,
+    consumed: u64,
+}
+
+impl<'a> Body<'a> {
+    /// Frames a body by `Transfer-Encoding: chunked` or else by its `Content-Length`.
+    pub fn new(
+        reader: &'a mut dyn BufRead,
+        chunked: bool,
+        length: Option<u64>,
+        limit: u64,
+    ) -> Self {
+        Self {
+            framed: Framed::new(reader, chunked, length, false),
+            interim: None,
+            length: if chunked { None } else { length.or(Some(0)) },
+            limit,
+            consumed: 0,
+        }
+    }
+
+    /// Reads the body until the reader ends, as with HTTP/2 streams. A declared length is still
+    /// checked against the limit before anything is read.
+    pub fn delimited(reader: &'a mut dyn BufRead, length: Option<u64>, limit: u64) -> Self {
+        Self {
+            framed: Framed::new(reader, false, length, true),
+            interim: None,
+            length,
+            limit,
+            consumed: 0,
+        }
+    }
+
+    /// Defers `100 Continue` until the body is first read, so that a handler can still reject the
+    /// request with a final response before the client sends the body.
+    pub fn expect_continue(&mut self, writer: &'a mut dyn Write) {
+        if !self.is_empty() {
+            self.interim = Some(writer);
+        }
+    }
+
+    /// Whether the client is still waiting for `100 Continue` before sending the body.
+    pub fn awaiting_continue(&self) -> bool {
+        self.interim.is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == Some(0)
+    }
+
+    /// The declared length of the body, if it is not chunked or delimited.
+    pub fn declared_len(&self) -> Option<u64> {
+        self.length
+    }
+
+    /// Whether the declared length already exceeds the limit, before anything is read.
+    pub fn exceeds_limit(&self) -> bool {
+        self.declared_len().is_some_and(|n| n > self.limit)
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Lowers the limit, for bodies that are read into memory whole.
+    pub fn restrict(&mut self, limit: u64) {
+        self.limit = self.limit.min(limit);
+    }
+
+    /// Reads and discards whatever is left of the body.
+    pub fn drain(&mut self) -> io::Result<u64> {
+        self.limit = u64::MAX;
+        io::copy(self, &mut io::sink())
+    }
+}
+
+impl fmt::Debug for Body<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Body")
+            .field("framing", &self.framed.framing)
+            .field("limit", &self.limit)
+            .field("consumed", &self.consumed)
+            .finish()
+    }
+}
+
+impl Read for Body<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.exceeds_limit() {
+            return Err(io::Error::other(BodyTooLarge));
+        }
+
+        if let Some(writer) = self.interim.take() {
+            writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            writer.flush()?;
+        }
+
+        let n = self.framed.read(buf)?;
+        self.consumed += n as u64;
+
+        if self.consumed > self.limit {
+            return Err(io::Error::other(BodyTooLarge));
+        }
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(body: &mut Body) -> io::Result<String> {
+        let mut s = String::new();
+        body.read_to_string(&mut s)?;
+        Ok(s)
+    }
+
+    #[test]
+    fn reads_by_content_length() {
+        let mut reader: &[u8] = b"hello worldGET / HTTP/1.1";
+        let mut body = Body::new(&mut reader, false, Some(11), 1024);
+
+        assert_eq!(body.declared_len(), Some(11));
+        assert_eq!(read_all(&mut body).unwrap(), "hello world");
+        assert_eq!(reader, b"GET / HTTP/1.1");
+    }
+
+    #[test]
+    fn is_empty_without_framing() {
+        let mut reader: &[u8] = b"GET / HTTP/1.1";
+        let mut body = Body::new(&mut reader, false, None, 1024);
+
+        assert!(body.is_empty());
+        assert_eq!(read_all(&mut body).unwrap(), "");
+        assert_eq!(reader, b"GET / HTTP/1.1");
+    }
+
+    #[test]
+    fn fails_on_a_short_body() {
+        let mut reader: &[u8] = b"hello";
+        let mut body = Body::new(&mut reader, false, Some(11), 1024);
+
+        let error = read_all(&mut body).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn reads_chunks() {
+        let mut reader: &[u8] =
+            b"5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nTrailer: x\r\n\r\nGET / HTTP/1.1";
+        let mut body = Body::new(&mut reader, true, None, 1024);
+
+        assert_eq!(body.declared_len(), None);
+        assert_eq!(read_all(&mut body).unwrap(), "hello world");
+        assert_eq!(reader, b"GET / HTTP/1.1");
+    }
+
+    #[test]
+    fn prefers_chunks_to_content_length() {
+        let mut reader: &[u8] = b"a\r\n0123456789\r\n0\r\n\r\n";
+        let mut body = Body::new(&mut reader, true, Some(3), 1024);
+
+        assert_eq!(read_all(&mut body).unwrap(), "0123456789");
+    }
+
+    #[test]
+    fn rejects_invalid_chunk_sizes() {
+        for chunk in [&b"x\r\nhello\r\n0\r\n\r\n"[..], b"\r\n", b"-5\r\nhello\r\n"] {
+            let mut reader = chunk;
+            let mut body = Body::new(&mut reader, true, None, 1024);
+
+            let error = read_all(&mut body).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn rejects_overlong_chunk_lines() {
+        let line = format!("5;{}\r\nhello\r\n0\r\n\r\n", "x".repeat(MAX_CHUNK_LINE));
+        let mut reader = line.as_bytes();
+        let mut body = Body::new(&mut reader, true, None, 1024);
+
+        let error = read_all(&mut body).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_chunks_longer_than_their_size() {
+        let mut reader: &[u8] = b"3\r\nhello\r\n0\r\n\r\n";
+        let mut body = Body::new(&mut reader, true, None, 1024);
+
+        let error = read_all(&mut body).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn fails_on_a_truncated_chunk() {
+        for chunk in [&b"a\r\nhello"[..], b"5\r\nhello", b"5\r\nhello\r\n"] {
+            let mut reader = chunk;
+            let mut body = Body::new(&mut reader, true, None, 1024);
+
+            let error = read_all(&mut body).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn refuses_a_declared_length_over_the_limit() {
+        let mut reader: &[u8] = b"hello world";
+        let mut body = Body::new(&mut reader, false, Some(11), 5);
+
+        assert!(body.exceeds_limit());
+        let error = read_all(&mut body).unwrap_err();
+        assert!(error.get_ref().unwrap().is::<BodyTooLarge>());
+    }
+
+    #[test]
+    fn stops_chunks_at_the_limit() {
+        let mut reader: &[u8] = b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        let mut body = Body::new(&mut reader, true, None, 8);
+
+        assert!(!body.exceeds_limit());
+        let error = read_all(&mut body).unwrap_err();
+        assert!(error.get_ref().unwrap().is::<BodyTooLarge>());
+    }
+
+    #[test]
+    fn restricts_the_limit() {
+        let mut reader: &[u8] = b"hello world";
+        let mut body = Body::new(&mut reader, false, Some(11), 1024);
+
+        body.restrict(5);
+        assert_eq!(body.limit(), 5);
+        body.restrict(100);
+        assert_eq!(body.limit(), 5);
+        assert!(body.exceeds_limit());
+    }
+
+    #[test]
+    fn drains_past_the_limit() {
+        let mut reader: &[u8] = b"hello worldnext";
+        let mut body = Body::new(&mut reader, false, Some(11), 5);
+
+        assert_eq!(body.drain().unwrap(), 11);
+        assert_eq!(reader, b"next");
+    }
+
+    #[test]
+    fn reads_delimited_bodies_to_the_end() {
+        let mut reader: &[u8] = b"hello world";
+        let mut body = Body::delimited(&mut reader, None, 1024);
+
+        assert_eq!(read_all(&mut body).unwrap(), "hello world");
+    }
+
+    #[test]
+    fn sends_100_continue_on_the_first_read() {
+        let mut reader: &[u8] = b"hello";
+        let mut interim = Vec::new();
+
+        {
+            let mut body = Body::new(&mut reader, false, Some(5), 1024);
+            body.expect_continue(&mut interim);
+            assert!(body.awaiting_continue());
+
+            let mut buf = [0; 2];
+            body.read_exact(&mut buf).unwrap();
+            assert!(!body.awaiting_continue());
+            assert_eq!(read_all(&mut body).unwrap(), "llo");
+        }
+
+        assert_eq!(interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+    }
+
+    #[test]
+    fn skips_100_continue_for_refused_or_empty_bodies() {
+        let mut reader: &[u8] = b"hello world";
+        let mut interim = Vec::new();
+
+        {
+            let mut body = Body::new(&mut reader, false, Some(11), 5);
+            body.expect_continue(&mut interim);
+            assert!(read_all(&mut body).is_err());
+        }
+
+        {
+            let mut empty: &[u8] = b"";
+            let mut body = Body::new(&mut empty, false, None, 5);
+            body.expect_continue(&mut interim);
+            assert!(!body.awaiting_continue());
+        }
+
+        assert!(interim.is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    fmt,
+    fs::Metadata,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    date::{format_http_date, parse_http_date, truncate_to_secs},
+    prelude::*,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag {
+    pub weak: bool,
+    pub tag: String,
+}
+
+impl ETag {
+    pub fn strong_eq(&self, other: &Self) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    pub fn weak_eq(&self, other: &Self) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+impl TryFrom<&str> for ETag {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (weak, s) = s.strip_prefix("W/").map_or((false, s), |s| (true, s));
+
+        match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            Some(tag) if !tag.contains('"') => Ok(Self {
+                weak,
+                tag: tag.into(),
+            }),
+            _ => Err(Error::Generic("Failed to parse entity tag.".into())),
+        }
+    }
+}
+
+/// The value of an `If-Match` or `If-None-Match` header.
+enum ETagList {
+    Any,
+    Tags(Vec<ETag>),
+}
+
+impl ETagList {
+    fn parse(s: &str) -> Self {
+        if s.trim() == "*" {
+            Self::Any
+        } else {
+            Self::Tags(
+                s.split(',')
+                    .filter_map(|t| ETag::try_from(t).ok())
+                    .collect(),
+            )
+        }
+    }
+
+    fn matches(&self, etag: Option<&ETag>, eq: fn(&ETag, &ETag) -> bool) -> bool {
+        match (self, etag) {
+            (Self::Any, Some(_)) => true,
+            (Self::Tags(tags), Some(etag)) => tags.iter().any(|t| eq(t, etag)),
+            (_, None) => false,
+        }
+    }
+}
+
+/// The validators of the current representation of a resource.
+#[derive(Debug, Clone)]
+pub struct Validators {
+    pub etag: ETag,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let last_modified = metadata.modified().ok();
+
+        let nanos = last_modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos());
+
+        Self {
+            etag: ETag {
+                weak: false,
+                tag: format!("{:x}-{nanos:x}", metadata.len()),
+            },
+            last_modified: last_modified.map(truncate_to_secs),
+        }
+    }
+
+    pub fn apply(&self, response: &mut Response) {
+        response.headers.insert("ETag", self.etag.to_string());
+
+        if let Some(last_modified) = self.last_modified {
+            response
+                .headers
+                .insert("Last-Modified", format_http_date(last_modified));
+        }
+    }
+}
+
+/// Evaluates the request's preconditions against the current representation, if there is one.
+/// Returns the status code to respond with when a precondition fails.
+pub fn evaluate(rq: &Request, current: Option<&Validators>) -> Option<StatusCode> {
+    let etag = current.map(|v| &v.etag);
+    let last_modified = current.and_then(|v| v.last_modified);
+
+    if let Some(header) = rq.header("If-Match") {
+        if !ETagList::parse(header).matches(etag, ETag::strong_eq) {
+            return Some(StatusCode::PreconditionFailed);
+        }
+    } else if let Some(date) = rq
+        .header("If-Unmodified-Since")
+        .and_then(|s| parse_http_date(s))
+    {
+        if last_modified.is_some_and(|modified| modified > date) {
+            return Some(StatusCode::PreconditionFailed);
+        }
+    }
+
+    if let Some(header) = rq.header("If-None-Match") {
+        if ETagList::parse(header).matches(etag, ETag::weak_eq) {
+            return Some(if rq.method.is_read() {
+                StatusCode::NotModified
+            } else {
+                StatusCode::PreconditionFailed
+            });
+        }
+    } else if let Some(date) = rq
+        .header("If-Modified-Since")
+        .and_then(|s| parse_http_date(s))
+    {
+        if rq.method.is_read() && last_modified.is_some_and(|modified| modified <= date) {
+            return Some(StatusCode::NotModified);
+        }
+    }
+
+    None
+}
+
+/// Whether a `Range` header should be honoured, according to any `If-Range` header.
+pub fn if_range(rq: &Request, current: &Validators) -> bool {
+    let Some(header) = rq.header("If-Range") else {
+        return true;
+    };
+
+    if let Ok(etag) = ETag::try_from(header.as_str()) {
+        etag.strong_eq(&current.etag)
+    } else {
+        parse_http_date(header).is_some_and(|date| current.last_modified == Some(date))
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "secure-cookies")]
+use crate::cookie::Key;
+use crate::{
+    auth::{Auth, AuthRule},
+    cors::{split_list, Cors},
+    prelude::*,
+    proxy::Proxy,
+    sandbox::{Sandbox, SandboxOptions},
+    session::{SessionBackend, SESSION_DIRECTORY},
+    tls::TlsOptions,
+};
+
+/// Parses a byte count with an optional `K`, `M` or `G` suffix.
+fn parse_size(s: &str) -> Result<u64> {
+    let (digits, shift) = match s.trim().to_ascii_uppercase() {
+        s if s.ends_with('K') => (s[..s.len() - 1].to_string(), 10),
+        s if s.ends_with('M') => (s[..s.len() - 1].to_string(), 20),
+        s if s.ends_with('G') => (s[..s.len() - 1].to_string(), 30),
+        s => (s, 0),
+    };
+
+    digits
+        .parse::<u64>()?
+        .checked_mul(1 << shift)
+        .ok_or(Error::Generic(format!("Size '{s}' is too large.")))
+}
+
+/// Parses a number of seconds for `flag`, which sockets can't wait for if it's zero.
+fn parse_timeout(flag: &str, s: &str) -> Result<u64> {
+    match s.parse::<u64>()? {
+        0 => Err(Error::Generic(format!("{flag} must be at least 1 second."))),
+        secs => Ok(secs),
+    }
+}
+
+/// A directory served under a URL prefix.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    pub prefix: String,
+    pub directory: PathBuf,
+    pub writable: bool,
+    pub listing: bool,
+    pub cache_control: Option<String>,
+    pub sandbox: SandboxOptions,
+}
+
+impl Mount {
+    pub fn new(prefix: &str, directory: PathBuf) -> Self {
+        Self {
+            prefix: format!("/{}", prefix.trim_matches('/')),
+            directory,
+            writable: false,
+            listing: false,
+            cache_control: None,
+            sandbox: SandboxOptions::default(),
+        }
+    }
+
+    /// Parses a `--mount` value of the form `PREFIX=DIRECTORY[,OPTION...]`, where options are
+    /// `ro`, `rw`, `listing`, `no-listing`, `no-cache`, `max-age=SECONDS` and
+    /// `cache-control=VALUE`.
+    pub fn parse(s: &str, sandbox: &SandboxOptions) -> Result<Self> {
+        let (prefix, rest) = s
+            .split_once('=')
+            .ok_or(Error::Generic(format!("Failed to parse mount '{s}'.")))?;
+
+        let mut options = rest.split(',');
+        let directory = options.next().unwrap_or_default();
+
+        if directory.is_empty() {
+            return Err(Error::Generic(format!("Mount '{s}' has no directory.")));
+        }
+
+        let mut mount = Self::new(prefix, directory.into());
+        mount.sandbox = sandbox.clone();
+
+        for option in options {
+            match option.split_once('=').unwrap_or((option, "")) {
+                ("ro", "") => mount.writable = false,
+                ("rw", "") => mount.writable = true,
+                ("listing", "") => mount.listing = true,
+                ("no-listing", "") => mount.listing = false,
+                ("no-cache", "") => mount.cache_control = Some("no-cache".into()),
+                ("max-age", secs) => {
+                    let secs = secs.parse::<u64>()?;
+                    mount.cache_control = Some(format!("public, max-age={secs}"));
+                }
+                ("cache-control", value) => mount.cache_control = Some(value.into()),
+                _ => {
+                    return Err(Error::Generic(format!(
+                        "Unknown option '{option}' for mount '{s}'."
+                    )))
+                }
+            }
+        }
+
+        Ok(mount)
+    }
+
+    pub fn methods(&self) -> &'static [Method] {
+        if self.writable {
+            &[Method::Get, Method::Post, Method::Put, Method::Delete]
+        } else {
+            &[Method::Get]
+        }
+    }
+
+    /// The sandbox for the mounted directory, which must have been canonicalized already.
+    pub fn sandbox(&self) -> Sandbox {
+        Sandbox::new(&self.directory, self.sandbox.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub directory: PathBuf,
+    pub host: String,
+    pub port: u32,
+    pub listing: bool,
+    pub show_hidden: bool,
+    pub sandbox: SandboxOptions,
+    pub mounts: Vec<Mount>,
+    pub proxies: Vec<Proxy>,
+    pub max_body_size: u64,
+    pub max_part_size: u64,
+    /// The largest WebSocket message accepted, however it's fragmented.
+    pub max_message_size: u64,
+    pub session_store: SessionBackend,
+    pub session_ttl: u64,
+    /// Seconds an event stream may go quiet before a heartbeat comment is sent.
+    pub heartbeat_interval: u64,
+    /// Seconds a client may take to send the next part of a request before it's given up on.
+    pub read_timeout: u64,
+    /// Seconds a persistent connection may sit idle between requests before it's closed.
+    pub keep_alive_timeout: u64,
+    pub auth: Auth,
+    pub cors: Cors,
+    pub tls: TlsOptions,
+    /// Whether the plaintext listener redirects everything to HTTPS.
+    pub https_redirect: bool,
+    /// Set by `--hash-password`, to print a hash for a credentials file instead of serving.
+    pub hash_password: Option<String>,
+    /// Set by `from_args`, from `--cookie-secret` or else at random.
+    #[cfg(feature = "secure-cookies")]
+    pub cookie_key: Option<Key>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            directory: ".".into(),
+            host: "127.0.0.1".into(),
+            port: 4221,
+            listing: false,
+            show_hidden: false,
+            sandbox: SandboxOptions::default(),
+            mounts: Vec::new(),
+            proxies: Vec::new(),
+            max_body_size: 1 << 30,
+            max_part_size: 1 << 30,
+            max_message_size: 1 << 20,
+            session_store: SessionBackend::Memory,
+            session_ttl: 3600,
+            heartbeat_interval: 15,
+            read_timeout: 30,
+            keep_alive_timeout: 5,
+            auth: Auth::default(),
+            cors: Cors::default(),
+            tls: TlsOptions::default(),
+            https_redirect: false,
+            hash_password: None,
+            #[cfg(feature = "secure-cookies")]
+            cookie_key: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Result<Self> {
+        let mut config = Self::default();
+        let mut mounts = Vec::new();
+        let mut args = env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or(Error::Generic(format!("Missing value for {arg}.")))
+            };
+
+            match arg.as_str() {
+                "--directory" => {
+                    config.directory = value()?.into();
+                }
+                "--host" => {
+                    config.host = value()?;
+                }
+                "--port" => {
+                    config.port = value()?.parse::<u32>()?;
+                }
+                "--listing" => {
+                    config.listing = true;
+                }
+                "--show-hidden" => {
+                    config.show_hidden = true;
+                }
+                "--no-symlinks" => {
+                    config.sandbox.symlinks = false;
+                }
+                "--no-dotfiles" => {
+                    config.sandbox.dotfiles = false;
+                }
+                "--allow-special-files" => {
+                    config.sandbox.special_files = true;
+                }
+                "--mount" => {
+                    mounts.push(value()?);
+                }
+                "--proxy" => {
+                    config.proxies.push(Proxy::parse(&value()?)?);
+                }
+                "--max-body-size" => {
+                    config.max_body_size = parse_size(&value()?)?;
+                }
+                "--max-part-size" => {
+                    config.max_part_size = parse_size(&value()?)?;
+                }
+                "--max-message-size" => {
+                    config.max_message_size = parse_size(&value()?)?;
+                }
+                "--session-store" => {
+                    config.session_store = SessionBackend::try_from(value()?.as_str())?;
+                }
+                "--session-ttl" => {
+                    config.session_ttl = value()?.parse::<u64>()?;
+                }
+                "--heartbeat-interval" => {
+                    config.heartbeat_interval = value()?.parse::<u64>()?;
+
+                    // A stream would do nothing but send heartbeats.
+                    if config.heartbeat_interval == 0 {
+                        return Err(Error::Generic(
+                            "--heartbeat-interval must be at least 1 second.".into(),
+                        ));
+                    }
+                }
+                "--read-timeout" => {
+                    config.read_timeout = parse_timeout(&arg, &value()?)?;
+                }
+                "--keep-alive-timeout" => {
+                    config.keep_alive_timeout = parse_timeout(&arg, &value()?)?;
+                }
+                "--credentials" => {
+                    config.auth.load_credentials(Path::new(&value()?))?;
+                }
+                "--tokens" => {
+                    config.auth.load_tokens(Path::new(&value()?))?;
+                }
+                "--protect" => {
+                    config.auth.rules.push(AuthRule::parse(&value()?)?);
+                }
+                "--realm" => {
+                    config.auth.realm = value()?;
+                }
+                "--cors-origin" => {
+                    config.cors.origins.push(value()?);
+                }
+                "--cors-methods" => {
+                    for method in split_list(&value()?) {
+                        let method = method.to_ascii_uppercase();
+                        config.cors.methods.push(Method::try_from(method.as_str())?);
+                    }
+                }
+                "--cors-headers" => {
+                    config
+                        .cors
+                        .headers
+                        .extend(split_list(&value()?).map(String::from));
+                }
+                "--cors-expose" => {
+                    config
+                        .cors
+                        .expose
+                        .extend(split_list(&value()?).map(String::from));
+                }
+                "--cors-credentials" => {
+                    config.cors.credentials = true;
+                }
+                "--cors-max-age" => {
+                    config.cors.max_age = Some(value()?.parse::<u64>()?);
+                }
+                "--tls-cert" => {
+                    config.tls.cert = Some(value()?.into());
+                }
+                "--tls-key" => {
+                    config.tls.key = Some(value()?.into());
+                }
+                "--tls-port" => {
+                    config.tls.port = value()?.parse::<u32>()?;
+                }
+                "--tls-client-ca" => {
+                    config.tls.client_ca = Some(value()?.into());
+                }
+                "--https-redirect" => {
+                    config.https_redirect = true;
+                }
+                "--hash-password" => {
+                    config.hash_password = Some(value()?);
+                }
+                #[cfg(feature = "secure-cookies")]
+                "--cookie-secret" => {
+                    config.cookie_key = Some(Key::derive(value()?.as_bytes())?);
+                }
+                _ => {}
+            }
+        }
+
+        #[cfg(feature = "secure-cookies")]
+        if config.cookie_key.is_none() {
+            config.cookie_key = Some(Key::generate()?);
+        }
+
+        config.tls.validate()?;
+
+        if !config.auth.rules.is_empty() && !config.auth.is_configured() {
+            return Err(Error::Generic(
+                "Protected paths need --credentials or --tokens.".into(),
+            ));
+        }
+
+        if config.cors.credentials && config.cors.origins.iter().any(|o| o == "*") {
+            return Err(Error::Generic(
+                "--cors-credentials needs origins listed rather than '*'.".into(),
+            ));
+        }
+
+        if config.session_store == SessionBackend::File {
+            config.sandbox.reserved.push(SESSION_DIRECTORY.into());
+        }
+
+        // Mounts are parsed last so that sandbox flags apply wherever they appear.
+        let mut files = Mount::new("/files", config.directory.clone());
+        files.writable = true;
+        files.listing = config.listing;
+        files.sandbox = config.sandbox.clone();
+        config.mounts.push(files);
+
+        for mount in mounts {
+            config.mounts.push(Mount::parse(&mount, &config.sandbox)?);
+        }
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,517 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{date::format_http_date, prelude::*};
+
+/// The cookies a client sent, from every `Cookie` header of the request.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+    pub fn parse<'h>(headers: impl Iterator<Item = &'h str>) -> Self {
+        let cookies = headers
+            .flat_map(|header| header.split(';'))
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .filter(|(name, _)| !name.is_empty())
+            .map(|(name, value)| {
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+
+                (name.to_string(), value.to_string())
+            })
+            .collect();
+
+        Self { cookies }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Strict => write!(f, "Strict"),
+            Self::Lax => write!(f, "Lax"),
+            Self::None => write!(f, "None"),
+        }
+    }
+}
+
+impl TryFrom<&str> for SameSite {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "lax" => Ok(Self::Lax),
+            "none" => Ok(Self::None),
+            _ => Err(Error::Generic("Failed to parse SameSite.".into())),
+        }
+    }
+}
+
+/// A `Set-Cookie` header value. The name and value are sent as is, so they must already be
+/// valid cookie octets.
+#[derive(Debug, Clone)]
+pub struct SetCookie {
+    pub name: String,
+    pub value: String,
+    pub expires: Option<SystemTime>,
+    pub max_age: Option<u64>,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// Tells the client to delete the cookie `name`. The domain and path must match the ones it
+    /// was set with.
+    pub fn removal(name: &str) -> Self {
+        Self::new(name, "").max_age(0).expires(UNIX_EPOCH)
+    }
+
+    pub fn expires(mut self, time: SystemTime) -> Self {
+        self.expires = Some(time);
+        self
+    }
+
+    pub fn max_age(mut self, secs: u64) -> Self {
+        self.max_age = Some(secs);
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    pub fn http_only(mut self) -> Self {
+        self.http_only = true;
+        self
+    }
+
+    /// Browsers reject `SameSite=None` on cookies that aren't also `Secure`, so it implies it.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.secure |= same_site == SameSite::None;
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", format_http_date(expires))?;
+        }
+
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={max_age}")?;
+        }
+
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={same_site}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "secure-cookies")]
+pub use keyed::Key;
+
+#[cfg(feature = "secure-cookies")]
+mod keyed {
+    use std::fmt;
+
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use chacha20poly1305::{
+        aead::{Aead, Payload},
+        ChaCha20Poly1305, KeyInit,
+    };
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    use super::{CookieJar, SetCookie};
+    use crate::prelude::*;
+
+    const MIN_SECRET_LEN: usize = 32;
+    const NONCE_LEN: usize = 12;
+    const SIGNATURE_LEN: usize = 43;
+
+    /// Keys derived from the server secret, for cookies that clients can read but not forge
+    /// (signed) or can neither read nor forge (private).
+    #[derive(Clone)]
+    pub struct Key {
+        signing: [u8; 32],
+        encryption: [u8; 32],
+    }
+
+    fn hmac(key: &[u8]) -> Hmac<Sha256> {
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length")
+    }
+
+    impl Key {
+        pub fn derive(secret: &[u8]) -> Result<Self> {
+            if secret.len() < MIN_SECRET_LEN {
+                return Err(Error::Generic(format!(
+                    "Cookie secret must be at least {MIN_SECRET_LEN} bytes."
+                )));
+            }
+
+            let subkey = |label: &[u8]| {
+                let mut mac = hmac(secret);
+                mac.update(label);
+                mac.finalize().into_bytes().into()
+            };
+
+            Ok(Self {
+                signing: subkey(b"cookie-signing"),
+                encryption: subkey(b"cookie-encryption"),
+            })
+        }
+
+        /// A random key, for when no secret is configured. Cookies it protects don't survive a
+        /// restart.
+        pub fn generate() -> Result<Self> {
+            let mut secret = [0; MIN_SECRET_LEN];
+            getrandom::getrandom(&mut secret)
+                .map_err(|e| Error::Generic(format!("Failed to generate cookie key: {e}.")))?;
+            Self::derive(&secret)
+        }
+
+        // The signature covers the name so that a value can't be moved to another cookie.
+        fn signature(&self, name: &str, value: &str) -> Hmac<Sha256> {
+            let mut mac = hmac(&self.signing);
+            mac.update(name.as_bytes());
+            mac.update(b"=");
+            mac.update(value.as_bytes());
+            mac
+        }
+
+        fn sign(&self, name: &str, value: &str) -> String {
+            let signature = self.signature(name, value).finalize().into_bytes();
+            format!("{}{value}", URL_SAFE_NO_PAD.encode(signature))
+        }
+
+        fn verify(&self, name: &str, signed: &str) -> Option<String> {
+            let signature = URL_SAFE_NO_PAD.decode(signed.get(..SIGNATURE_LEN)?).ok()?;
+            let value = &signed[SIGNATURE_LEN..];
+
+            self.signature(name, value)
+                .verify_slice(&signature)
+                .ok()
+                .map(|_| value.to_string())
+        }
+
+        fn encrypt(&self, name: &str, value: &str) -> Result<String> {
+            let mut nonce = [0; NONCE_LEN];
+            getrandom::getrandom(&mut nonce)
+                .map_err(|e| Error::Generic(format!("Failed to generate nonce: {e}.")))?;
+
+            let payload = Payload {
+                msg: value.as_bytes(),
+                aad: name.as_bytes(),
+            };
+
+            let ciphertext = ChaCha20Poly1305::new(&self.encryption.into())
+                .encrypt(&nonce.into(), payload)
+                .map_err(|_| Error::Generic("Failed to encrypt cookie.".into()))?;
+
+            Ok(URL_SAFE_NO_PAD.encode([&nonce[..], &ciphertext].concat()))
+        }
+
+        fn decrypt(&self, name: &str, sealed: &str) -> Option<String> {
+            let sealed = URL_SAFE_NO_PAD.decode(sealed).ok()?;
+
+            if sealed.len() < NONCE_LEN {
+                return None;
+            }
+
+            let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+            let payload = Payload {
+                msg: ciphertext,
+                aad: name.as_bytes(),
+            };
+
+            let plaintext = ChaCha20Poly1305::new(&self.encryption.into())
+                .decrypt(nonce.into(), payload)
+                .ok()?;
+
+            String::from_utf8(plaintext).ok()
+        }
+    }
+
+    impl fmt::Debug for Key {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Key(..)")
+        }
+    }
+
+    impl CookieJar {
+        /// The value of a cookie set with [`SetCookie::signed`], if its signature is intact.
+        pub fn get_signed(&self, name: &str, key: &Key) -> Option<String> {
+            key.verify(name, self.get(name)?)
+        }
+
+        /// The value of a cookie set with [`SetCookie::private`], if it decrypts.
+        pub fn get_private(&self, name: &str, key: &Key) -> Option<String> {
+            key.decrypt(name, self.get(name)?)
+        }
+    }
+
+    impl SetCookie {
+        /// Signs the value, so the client can read it but not change it.
+        pub fn signed(mut self, key: &Key) -> Self {
+            self.value = key.sign(&self.name, &self.value);
+            self
+        }
+
+        /// Encrypts the value, so the client can neither read nor change it.
+        pub fn private(mut self, key: &Key) -> Result<Self> {
+            self.value = key.encrypt(&self.name, &self.value)?;
+            Ok(self)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+        fn jar(name: &str, value: &str) -> CookieJar {
+            CookieJar::parse([format!("{name}={value}").as_str()].into_iter())
+        }
+
+        #[test]
+        fn requires_a_long_secret() {
+            assert!(Key::derive(&SECRET[..31]).is_err());
+            assert!(Key::derive(SECRET).is_ok());
+        }
+
+        #[test]
+        fn verifies_signed_cookies() {
+            let key = Key::derive(SECRET).unwrap();
+            let cookie = SetCookie::new("user", "alice").signed(&key);
+
+            assert!(cookie.value.ends_with("alice"));
+            assert_eq!(
+                jar("user", &cookie.value).get_signed("user", &key),
+                Some("alice".into())
+            );
+
+            // The same secret signs the same way after a restart.
+            let restarted = Key::derive(SECRET).unwrap();
+            assert_eq!(
+                cookie.value,
+                SetCookie::new("user", "alice").signed(&restarted).value
+            );
+        }
+
+        #[test]
+        fn rejects_forged_signed_cookies() {
+            let key = Key::derive(SECRET).unwrap();
+            let signed = SetCookie::new("user", "alice").signed(&key).value;
+            let other_key = Key::derive(b"fedcba9876543210fedcba9876543210").unwrap();
+
+            let tampered = signed.replace("alice", "admin");
+            let truncated = &signed[..SIGNATURE_LEN - 1];
+            let garbled = format!("{}alice", "!".repeat(SIGNATURE_LEN));
+            let multibyte = format!("{}\u{e9}alice", &signed[..SIGNATURE_LEN - 1]);
+
+            for value in [&tampered, truncated, &garbled, &multibyte, "alice", ""] {
+                assert_eq!(jar("user", value).get_signed("user", &key), None, "{value}");
+            }
+
+            // A value can't be moved to another cookie, nor checked with another key.
+            assert_eq!(jar("role", &signed).get_signed("role", &key), None);
+            assert_eq!(jar("user", &signed).get_signed("user", &other_key), None);
+            assert_eq!(jar("user", &signed).get_signed("missing", &key), None);
+        }
+
+        #[test]
+        fn decrypts_private_cookies() {
+            let key = Key::derive(SECRET).unwrap();
+            let first = SetCookie::new("user", "alice").private(&key).unwrap();
+            let second = SetCookie::new("user", "alice").private(&key).unwrap();
+
+            assert!(!first.value.contains("alice"));
+            assert_ne!(first.value, second.value);
+
+            for cookie in [first, second] {
+                assert_eq!(
+                    jar("user", &cookie.value).get_private("user", &key),
+                    Some("alice".into())
+                );
+            }
+        }
+
+        #[test]
+        fn rejects_forged_private_cookies() {
+            let key = Key::derive(SECRET).unwrap();
+            let sealed = SetCookie::new("user", "alice").private(&key).unwrap().value;
+            let other_key = Key::derive(b"fedcba9876543210fedcba9876543210").unwrap();
+
+            let mut bytes = URL_SAFE_NO_PAD.decode(&sealed).unwrap();
+            *bytes.last_mut().unwrap() ^= 1;
+            let tampered = URL_SAFE_NO_PAD.encode(bytes);
+
+            for value in [&tampered, &sealed[..10], "not base64!", ""] {
+                assert_eq!(
+                    jar("user", value).get_private("user", &key),
+                    None,
+                    "{value}"
+                );
+            }
+
+            assert_eq!(jar("role", &sealed).get_private("role", &key), None);
+            assert_eq!(jar("user", &sealed).get_private("user", &other_key), None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn parses_cookies_from_every_header() {
+        let jar = CookieJar::parse(
+            [
+                "a=1; b=\"two\"",
+                " c = 3 ;=x; flag; d=",
+                "a=shadowed; e=x=y",
+            ]
+            .into_iter(),
+        );
+
+        assert_eq!(
+            jar.iter().collect::<Vec<_>>(),
+            vec![
+                ("a", "1"),
+                ("b", "two"),
+                ("c", "3"),
+                ("d", ""),
+                ("a", "shadowed"),
+                ("e", "x=y")
+            ]
+        );
+        assert_eq!(jar.get("a"), Some("1"));
+        assert_eq!(jar.get("flag"), None);
+        assert!(CookieJar::parse(["", ";;"].into_iter()).is_empty());
+    }
+
+    #[test]
+    fn formats_set_cookie() {
+        let cookie = SetCookie::new("id", "abc")
+            .expires(UNIX_EPOCH + Duration::from_secs(784_111_777))
+            .max_age(60)
+            .domain("example.com")
+            .path("/")
+            .http_only()
+            .same_site(SameSite::Lax);
+
+        assert_eq!(
+            cookie.to_string(),
+            "id=abc; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Max-Age=60; Domain=example.com; \
+             Path=/; HttpOnly; SameSite=Lax"
+        );
+        assert_eq!(SetCookie::new("id", "abc").to_string(), "id=abc");
+    }
+
+    #[test]
+    fn removes_cookies() {
+        assert_eq!(
+            SetCookie::removal("id").path("/").to_string(),
+            "id=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0; Path=/"
+        );
+    }
+
+    #[test]
+    fn same_site_none_implies_secure() {
+        let cookie = SetCookie::new("id", "abc").same_site(SameSite::None);
+        assert_eq!(cookie.to_string(), "id=abc; Secure; SameSite=None");
+    }
+
+    #[test]
+    fn parses_same_site() {
+        assert_eq!(SameSite::try_from("Strict").unwrap(), SameSite::Strict);
+        assert_eq!(SameSite::try_from("LAX").unwrap(), SameSite::Lax);
+        assert_eq!(SameSite::try_from("none").unwrap(), SameSite::None);
+        assert!(SameSite::try_from("").is_err());
+        assert!(SameSite::try_from("always").is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{headers::Headers, prelude::*};
+
+/// Which cross-origin requests browsers may make. CORS is off until an origin is allowed.
+#[derive(Debug, Clone, Default)]
+pub struct Cors {
+    /// Allowed origins, or `*` for any.
+    pub origins: Vec<String>,
+    /// Methods allowed across origins, or empty for whatever the route accepts.
+    pub methods: Vec<Method>,
+    /// Request headers allowed across origins, or `*` for any.
+    pub headers: Vec<String>,
+    /// Response headers that scripts may read beyond the safelisted ones.
+    pub expose: Vec<String>,
+    /// Whether scripts may send cookies along, which is never allowed for `*`.
+    pub credentials: bool,
+    pub max_age: Option<u64>,
+}
+
+/// Splits a comma-separated flag or header value.
+pub fn split_list(s: &str) -> impl Iterator<Item = &str> {
+    s.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
+
+impl Cors {
+    pub fn is_enabled(&self) -> bool {
+        !self.origins.is_empty()
+    }
+
+    fn any_origin(&self) -> bool {
+        self.origins.iter().any(|o| o == "*")
+    }
+
+    // Credentials are only ever allowed for listed origins, so any origin gets `*` and never its
+    // own name back.
+    fn allowed_origin<'o>(&self, origin: &'o str) -> Option<&'o str> {
+        if self.any_origin() {
+            Some("*")
+        } else {
+            self.origins
+                .iter()
+                .any(|o| o.eq_ignore_ascii_case(origin))
+                .then_some(origin)
+        }
+    }
+
+    fn allows_header(&self, name: &str) -> bool {
+        self.headers
+            .iter()
+            .any(|h| h == "*" || h.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the request is a browser asking in advance whether it may make a request.
+    pub fn is_preflight(&self, rq: &Request) -> bool {
+        self.is_enabled()
+            && rq.method == Method::Options
+            && rq.header("Origin").is_some()
+            && rq.header("Access-Control-Request-Method").is_some()
+    }
+
+    /// Answers a preflight for a route accepting `methods`. Anything not allowed is simply left
+    /// out, which makes the browser refuse the actual request.
+    pub fn preflight(&self, rq: &Request, methods: &[Method], headers: &mut Headers) {
+        let origin_allowed = rq
+            .header("Origin")
+            .is_some_and(|o| self.allowed_origin(o).is_some());
+
+        let allowed = methods
+            .iter()
+            .filter(|m| self.methods.is_empty() || self.methods.contains(m))
+            .collect::<Vec<_>>();
+
+        let method_allowed = rq
+            .header("Access-Control-Request-Method")
+            .and_then(|m| Method::try_from(m.as_str()).ok())
+            .is_some_and(|m| allowed.contains(&&m));
+
+        let requested = rq
+            .header("Access-Control-Request-Headers")
+            .map(|h| split_list(h).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        if !origin_allowed || !method_allowed || !requested.iter().all(|h| self.allows_header(h)) {
+            return;
+        }
+
+        let allowed = allowed.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        headers.insert("Access-Control-Allow-Methods", allowed.join(", "));
+
+        if !requested.is_empty() {
+            headers.insert("Access-Control-Allow-Headers", requested.join(", "));
+        }
+
+        if let Some(max_age) = self.max_age {
+            headers.insert("Access-Control-Max-Age", max_age.to_string());
+        }
+    }
+
+    /// Adds the headers that let a browser hand the response to a script from another origin.
+    pub fn decorate(&self, rq: &Request, headers: &mut Headers) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        // Unless every origin gets the same answer, caches must keep them apart.
+        if !self.any_origin() {
+            headers.append("Vary", "Origin");
+        }
+
+        let Some(origin) = rq.header("Origin").and_then(|o| self.allowed_origin(o)) else {
+            return;
+        };
+
+        headers.insert("Access-Control-Allow-Origin", origin);
+
+        if self.credentials && !self.any_origin() {
+            headers.insert("Access-Control-Allow-Credentials", "true");
+        }
+
+        if !self.expose.is_empty() {
+            headers.insert("Access-Control-Expose-Headers", self.expose.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors(origins: &[&str], credentials: bool) -> Cors {
+        Cors {
+            origins: origins.iter().map(|o| o.to_string()).collect(),
+            methods: vec![Method::Get, Method::Put],
+            headers: vec!["Content-Type".into()],
+            credentials,
+            max_age: Some(600),
+            ..Cors::default()
+        }
+    }
+
+    fn request(head: &str, test: impl FnOnce(&Request)) {
+        let mut reader = head.as_bytes();
+        let mut interim = Vec::new();
+        test(&Request::parse(&mut reader, &mut interim, 0).unwrap());
+    }
+
+    // The fields a preflight is answered with, for a route accepting GET, POST and PUT.
+    fn preflight(cors: &Cors, fields: &str) -> Headers {
+        let mut headers = Headers::new();
+        request(&format!("OPTIONS /x HTTP/1.1\r\n{fields}\r\n"), |rq| {
+            assert!(cors.is_preflight(rq));
+            cors.preflight(rq, &[Method::Get, Method::Post, Method::Put], &mut headers);
+            cors.decorate(rq, &mut headers);
+        });
+        headers
+    }
+
+    fn decorate(cors: &Cors, fields: &str) -> Headers {
+        let mut headers = Headers::new();
+        request(&format!("GET /x HTTP/1.1\r\n{fields}\r\n"), |rq| {
+            cors.decorate(rq, &mut headers)
+        });
+        headers
+    }
+
+    #[test]
+    fn answers_allowed_preflights() {
+        let headers = preflight(
+            &cors(&["https://a.example"], false),
+            "Origin: https://a.example\r\n\
+            Access-Control-Request-Method: PUT\r\n\
+            Access-Control-Request-Headers: content-type\r\n",
+        );
+
+        assert_eq!(
+            headers.get("Access-Control-Allow-Origin").unwrap(),
+            "https://a.example"
+        );
+        assert_eq!(
+            headers.get("Access-Control-Allow-Methods").unwrap(),
+            "GET, PUT"
+        );
+        assert_eq!(
+            headers.get("Access-Control-Allow-Headers").unwrap(),
+            "content-type"
+        );
+        assert_eq!(headers.get("Access-Control-Max-Age").unwrap(), "600");
+    }
+
+    #[test]
+    fn leaves_out_what_preflights_may_not_do() {
+        let cors = cors(&["https://a.example"], false);
+
+        for fields in [
+            "Origin: https://b.example\r\nAccess-Control-Request-Method: GET\r\n",
+            "Origin: https://a.example\r\nAccess-Control-Request-Method: POST\r\n",
+            "Origin: https://a.example\r\nAccess-Control-Request-Method: GET\r\n\
+            Access-Control-Request-Headers: X-Secret\r\n",
+        ] {
+            let headers = preflight(&cors, fields);
+            assert!(
+                !headers.contains("Access-Control-Allow-Methods"),
+                "{fields}"
+            );
+        }
+
+        let headers = preflight(
+            &cors,
+            "Origin: https://b.example\r\nAccess-Control-Request-Method: GET\r\n",
+        );
+        assert!(!headers.contains("Access-Control-Allow-Origin"));
+    }
+
+    #[test]
+    fn varies_by_origin_unless_any_is_allowed() {
+        let listed = cors(&["https://a.example"], true);
+
+        for fields in [
+            "Origin: https://a.example\r\n",
+            "Origin: https://b.example\r\n",
+            "",
+        ] {
+            assert_eq!(decorate(&listed, fields).get("Vary").unwrap(), "Origin");
+        }
+
+        let headers = decorate(&listed, "Origin: https://a.example\r\n");
+        assert_eq!(
+            headers.get("Access-Control-Allow-Credentials").unwrap(),
+            "true"
+        );
+
+        let any = cors(&["*"], false);
+        let headers = decorate(&any, "Origin: https://b.example\r\n");
+        assert!(!headers.contains("Vary"));
+        assert_eq!(headers.get("Access-Control-Allow-Origin").unwrap(), "*");
+
+        assert!(decorate(&Cors::default(), "Origin: https://a.example\r\n").is_empty());
+    }
+
+    #[test]
+    fn never_allows_credentials_for_any_origin() {
+        let headers = decorate(&cors(&["*"], true), "Origin: https://evil.example\r\n");
+
+        assert_eq!(headers.get("Access-Control-Allow-Origin").unwrap(), "*");
+        assert!(!headers.contains("Access-Control-Allow-Credentials"));
+    }
+}
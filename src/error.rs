@@ -1,8 +1,16 @@
 use core::num;
 use std::{error, fmt, io, string};
 
+use crate::{
+    body::{BodyTooLarge, InvalidChunk},
+    multipart::Malformed,
+    prelude::StatusCode,
+};
+
 pub enum Error {
     Generic(String),
+    Status(StatusCode),
+    Rejected(StatusCode, String),
     IO(io::Error),
     FromUtf8(string::FromUtf8Error),
     ParseInt(num::ParseIntError),
@@ -12,6 +20,8 @@ impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Self::Generic(_) => None,
+            Self::Status(_) => None,
+            Self::Rejected(..) => None,
             Self::IO(e) => Some(e),
             Self::FromUtf8(e) => Some(e),
             Self::ParseInt(e) => Some(e),
@@ -23,6 +33,8 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Generic(s) => write!(f, "Error: {s}"),
+            Self::Status(code) => write!(f, "HTTP Error: {code}"),
+            Self::Rejected(code, s) => write!(f, "HTTP Error: {code}: {s}"),
             Self::IO(e) => write!(f, "IO Error: {e}"),
             Self::FromUtf8(e) => write!(f, "UTF-8 Error: {e}"),
             Self::ParseInt(e) => write!(f, "Parsing Error: {e}"),
@@ -34,6 +46,8 @@ impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let base = match self {
             Self::Generic(s) => s.clone(),
+            Self::Status(code) => code.to_string(),
+            Self::Rejected(code, s) => format!("{code}: {s}"),
             Self::IO(e) => format!("{e:#?}"),
             Self::FromUtf8(e) => format!("{e:#?}"),
             Self::ParseInt(e) => format!("{e:#?}"),
@@ -57,7 +71,22 @@ impl From<&str> for Error {
 
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
-        Self::IO(e)
+        if e.get_ref().is_some_and(|inner| inner.is::<BodyTooLarge>()) {
+            Self::Status(StatusCode::PayloadTooLarge)
+        } else if e
+            .get_ref()
+            .is_some_and(|inner| inner.is::<Malformed>() || inner.is::<InvalidChunk>())
+        {
+            Self::Status(StatusCode::BadRequest)
+        } else {
+            Self::IO(e)
+        }
+    }
+}
+
+impl From<StatusCode> for Error {
+    fn from(code: StatusCode) -> Self {
+        Self::Status(code)
     }
 }
 
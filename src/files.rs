@@ -0,0 +1,512 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs::{self, File, Metadata},
+    hash::{BuildHasher, Hasher},
+    io::{self, Read},
+    path::Path,
+};
+
+use crate::{
+    conditional::{self, Validators},
+    config::Mount,
+    listing::{Listing, SortKey},
+    multipart::Limits,
+    prelude::*,
+    range::{self, Ranges},
+    router::Context,
+    sandbox::{Resolved, Sandbox},
+};
+
+pub fn handle(rq: &Request, cx: Context, mount: &Mount) -> Result<Response> {
+    match rq.method {
+        Method::Get | Method::Head => serve_file(rq, cx, mount),
+        Method::Post => write_file(rq, cx, mount, WriteMode::Create),
+        Method::Put => write_file(rq, cx, mount, WriteMode::CreateOrReplace),
+        Method::Delete => delete_file(rq, cx, mount),
+        _ => rq.response(StatusCode::MethodNotAllowed, None),
+    }
+}
+
+fn serve_file(rq: &Request, cx: Context, mount: &Mount) -> Result<Response> {
+    let filename = cx.get("filename").ok_or(Error::Generic(
+        "Failed to get filename from context.".into(),
+    ))?;
+
+    let sandbox = mount.sandbox();
+
+    let mut response = match sandbox.resolve(filename)? {
+        Resolved::Found { path, metadata } if metadata.is_dir() => {
+            serve_directory(rq, &cx, mount, &sandbox, filename, &path)?
+        }
+        Resolved::Found { path, .. } => send_file(rq, &path)?,
+        Resolved::Missing { .. } => rq.response(StatusCode::NotFound, None)?,
+        Resolved::Forbidden => rq.response(StatusCode::Forbidden, None)?,
+    };
+
+    let cacheable = matches!(
+        response.code,
+        StatusCode::Ok | StatusCode::PartialContent | StatusCode::NotModified
+    );
+
+    if let (true, Some(cache_control)) = (cacheable, &mount.cache_control) {
+        response
+            .headers
+            .insert("Cache-Control", cache_control.clone());
+    }
+
+    Ok(response)
+}
+
+fn serve_directory(
+    rq: &Request,
+    cx: &Context,
+    mount: &Mount,
+    sandbox: &Sandbox,
+    filename: &str,
+    path: &Path,
+) -> Result<Response> {
+    if !rq.path().ends_with('/') {
+        let location = rq.uri.replacen(rq.path(), &format!("{}/", rq.path()), 1);
+        return Response::builder(rq).moved_permanently(&location).build();
+    }
+
+    if let Resolved::Found { path, metadata } =
+        sandbox.resolve(&format!("{filename}/index.html"))?
+    {
+        if metadata.is_file() {
+            return send_file(rq, &path);
+        }
+    }
+
+    if !mount.listing {
+        return rq.response(StatusCode::Forbidden, None);
+    }
+
+    let key = rq
+        .query_param("sort")
+        .and_then(|s| SortKey::try_from(s.as_str()).ok())
+        .unwrap_or(SortKey::Name);
+
+    let descending = rq.query_param("order").as_deref() == Some("desc");
+
+    let parent = !filename.trim_matches('/').is_empty();
+    let mut listing = Listing::read(path, sandbox, cx.config.show_hidden)?;
+    listing.sort(key, descending);
+
+    let offers = vec![
+        Content::new(
+            MimeType::Html,
+            &listing.html(rq.path(), parent, key, descending),
+        ),
+        Content::new(MimeType::Json, &listing.json(rq.path())),
+    ];
+
+    rq.negotiate(StatusCode::Ok, offers)
+}
+
+fn send_file(rq: &Request, path: &Path) -> Result<Response> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let length = metadata.len();
+    let content_type = ContentType::from(MimeType::from_path(path));
+    let validators = Validators::from_metadata(&metadata);
+
+    if let Some(code) = conditional::evaluate(rq, Some(&validators)) {
+        let mut response = rq.response(code, None)?;
+
+        if matches!(response.code, StatusCode::NotModified) {
+            validators.apply(&mut response);
+        }
+
+        return Ok(response);
+    }
+
+    let ranges = rq
+        .header("Range")
+        .filter(|_| conditional::if_range(rq, &validators))
+        .and_then(|header| Ranges::parse(header, length));
+
+    let mut response = match ranges {
+        Some(Ranges::Satisfiable(ranges)) if ranges.len() == 1 => Response::builder(rq)
+            .status(StatusCode::PartialContent)
+            .header("Content-Range", ranges[0].content_range(length))
+            .stream(content_type, ranges[0].reader(file)?, Some(ranges[0].len()))
+            .build()?,
+        Some(Ranges::Satisfiable(ranges)) => {
+            let body = range::Multipart::new(file, &ranges, &content_type, length);
+            let (content_type, length) = (body.content_type(), body.length());
+
+            Response::builder(rq)
+                .status(StatusCode::PartialContent)
+                .stream(content_type, body, Some(length))
+                .build()?
+        }
+        Some(Ranges::Unsatisfiable) => {
+            let mut response = rq.response(StatusCode::RangeNotSatisfiable, None)?;
+            let content_range = format!("bytes */{length}");
+            response.headers.insert("Content-Range", content_range);
+            response
+        }
+        None => Response::builder(rq)
+            .stream(content_type, file, Some(length))
+            .build()?,
+    };
+
+    // Ranges refer to the unencoded bytes, so partial responses must not be compressed.
+    if matches!(response.code, StatusCode::PartialContent) {
+        response.encoding = None;
+    }
+
+    response.headers.insert("Accept-Ranges", "bytes");
+    validators.apply(&mut response);
+    Ok(response)
+}
+
+/// Whether a write may create a new file, replace an existing one, or both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    Create,
+    CreateOrReplace,
+}
+
+fn write_file(rq: &Request, cx: Context, mount: &Mount, mode: WriteMode) -> Result<Response> {
+    let filename = cx.get("filename").ok_or(Error::Generic(
+        "Failed to get filename from context.".into(),
+    ))?;
+
+    let is_form = rq
+        .content_type
+        .as_ref()
+        .is_some_and(|ct| ct.mime_type == MimeType::MultipartFormData);
+
+    if is_form && mode == WriteMode::Create {
+        return upload_form(rq, &cx, mount, filename);
+    }
+
+    let (path, current) = match mount.sandbox().resolve(filename)? {
+        Resolved::Found { metadata, .. } if metadata.is_dir() => {
+            return rq.response(StatusCode::Conflict, None);
+        }
+        Resolved::Found { path, metadata } => (path, Some(Validators::from_metadata(&metadata))),
+        Resolved::Missing { path } => (path, None),
+        Resolved::Forbidden => return rq.response(StatusCode::Forbidden, None),
+    };
+
+    if let Some(code) = conditional::evaluate(rq, current.as_ref()) {
+        return rq.response(code, None);
+    }
+
+    if mode == WriteMode::Create && current.is_some() {
+        return rq.response(StatusCode::Conflict, None);
+    }
+
+    let Some(metadata) = write_atomically(&path, &mut *rq.body(), mode)? else {
+        return rq.response(StatusCode::Conflict, None);
+    };
+
+    let code = if current.is_some() {
+        StatusCode::NoContent
+    } else {
+        StatusCode::Created
+    };
+
+    let mut response = rq.response(code, None)?;
+    Validators::from_metadata(&metadata).apply(&mut response);
+    Ok(response)
+}
+
+/// Saves the files of a `multipart/form-data` upload. Posting to a directory saves every file
+/// under its own name, while posting to a file path saves the first file there.
+fn upload_form(rq: &Request, cx: &Context, mount: &Mount, filename: &str) -> Result<Response> {
+    let sandbox = mount.sandbox();
+
+    let into_directory = matches!(
+        sandbox.resolve(filename)?,
+        Resolved::Found { ref metadata, .. } if metadata.is_dir()
+    );
+
+    let limits = Limits {
+        part_size: cx.config.max_part_size,
+        total_size: rq.body().limit(),
+        ..Limits::default()
+    };
+
+    let mut form = rq.multipart(limits)?;
+    let mut saved = Vec::new();
+
+    while let Some(mut part) = form.next_part()? {
+        // Browsers may send a full client-side path, of which only the last component matters.
+        let name = part
+            .filename
+            .as_deref()
+            .and_then(|f| f.rsplit(['/', '\\']).next())
+            .filter(|f| !f.is_empty())
+            .map(String::from);
+
+        let Some(name) = name else {
+            continue;
+        };
+
+        let target = if into_directory {
+            format!("{}/{name}", filename.trim_end_matches('/'))
+        } else {
+            filename.into()
+        };
+
+        let path = match sandbox.resolve(&target)? {
+            Resolved::Missing { path } => path,
+            Resolved::Found { .. } => return rq.response(StatusCode::Conflict, None),
+            Resolved::Forbidden => return rq.response(StatusCode::Forbidden, None),
+        };
+
+        if write_atomically(&path, &mut part, WriteMode::Create)?.is_none() {
+            return rq.response(StatusCode::Conflict, None);
+        }
+
+        saved.push(escape_json(target.trim_start_matches('/')));
+
+        if !into_directory {
+            break;
+        }
+    }
+
+    if saved.is_empty() {
+        return rq.response(StatusCode::BadRequest, None);
+    }
+
+    let json = format!("{{\"files\":[{}]}}", saved.join(","));
+    rq.response(
+        StatusCode::Created,
+        Some(Content::new(MimeType::Json, &json)),
+    )
+}
+
+/// Streams `source` to a temporary file beside `path` and then moves it into place, so that
+/// readers only ever see the old or the new contents. Returns `None` if the file appeared in the
+/// meantime and the mode does not allow replacing it.
+pub fn write_atomically(
+    path: &Path,
+    source: &mut dyn Read,
+    mode: WriteMode,
+) -> Result<Option<Metadata>> {
+    let parent = path
+        .parent()
+        .ok_or(Error::Generic("Failed to get parent directory.".into()))?;
+
+    // A file in the way of the directories leaves nowhere to put this one.
+    if let Err(e) = fs::create_dir_all(parent) {
+        return match parent.ancestors().any(Path::is_file) {
+            true => Err(Error::Status(StatusCode::Conflict)),
+            false => Err(e.into()),
+        };
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let suffix = RandomState::new().build_hasher().finish();
+    let temp = parent.join(format!(".{name}.{suffix:016x}.tmp"));
+
+    let result = (|| {
+        let mut file = File::options().write(true).create_new(true).open(&temp)?;
+        io::copy(source, &mut file)?;
+        file.sync_all()?;
+
+        match mode {
+            // Linking fails if the target exists, which makes creation race-free.
+            WriteMode::Create => match fs::hard_link(&temp, path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(None),
+                Err(e) => return Err(e),
+            },
+            WriteMode::CreateOrReplace => fs::rename(&temp, path)?,
+        }
+
+        fs::metadata(path).map(Some)
+    })();
+
+    let _ = fs::remove_file(&temp);
+    Ok(result?)
+}
+
+fn delete_file(rq: &Request, cx: Context, mount: &Mount) -> Result<Response> {
+    let filename = cx.get("filename").ok_or(Error::Generic(
+        "Failed to get filename from context.".into(),
+    ))?;
+
+    let (path, current) = match mount.sandbox().resolve(filename)? {
+        Resolved::Found { metadata, .. } if metadata.is_dir() => {
+            return rq.response(StatusCode::Conflict, None);
+        }
+        Resolved::Found { path, metadata } => (path, Validators::from_metadata(&metadata)),
+        Resolved::Missing { .. } => return rq.response(StatusCode::NotFound, None),
+        Resolved::Forbidden => return rq.response(StatusCode::Forbidden, None),
+    };
+
+    if let Some(code) = conditional::evaluate(rq, Some(&current)) {
+        return rq.response(code, None);
+    }
+
+    match fs::remove_file(path) {
+        Ok(()) => rq.response(StatusCode::NoContent, None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => rq.response(StatusCode::NotFound, None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{config::Config, headers::Headers, testing::TempDir};
+
+    fn mount(dir: &TempDir) -> Mount {
+        let mut mount = Mount::new("/files", dir.path().into());
+        mount.writable = true;
+        mount
+    }
+
+    // The status and fields a request for `filename` is answered with, as the router would.
+    fn call(mount: &Mount, filename: &str, head: &str) -> (u16, Headers) {
+        let mut reader = head.as_bytes();
+        let mut interim = Vec::new();
+        let rq = Request::parse(&mut reader, &mut interim, 1024).unwrap();
+
+        let mut cx = Context::new(Arc::new(Config::default()));
+        cx.params.insert("filename".into(), filename.into());
+
+        match handle(&rq, cx, mount) {
+            Ok(response) => (response.code.code(), response.headers),
+            Err(Error::Status(code)) => (code.code(), Headers::new()),
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+
+    fn put(mount: &Mount, filename: &str, fields: &str, body: &str) -> (u16, Headers) {
+        let head = format!(
+            "PUT /files/{filename} HTTP/1.1\r\n{fields}Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        call(mount, filename, &head)
+    }
+
+    // Only the files a test wrote, without any temporary ones left behind.
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn creates_and_replaces_files_with_put() {
+        let dir = TempDir::new();
+        let mount = mount(&dir);
+
+        let (code, headers) = put(&mount, "a/b.txt", "", "first");
+        assert_eq!(code, 201);
+        assert!(headers.contains("ETag"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a/b.txt")).unwrap(),
+            "first"
+        );
+
+        let (code, _) = put(&mount, "a/b.txt", "", "second");
+        assert_eq!(code, 204);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a/b.txt")).unwrap(),
+            "second"
+        );
+
+        assert_eq!(put(&mount, "a", "", "x").0, 409);
+        assert_eq!(put(&mount, "a/b.txt/c", "", "x").0, 409);
+        assert_eq!(names(&dir.path().join("a")), ["b.txt"]);
+    }
+
+    #[test]
+    fn checks_preconditions_before_writing() {
+        let dir = TempDir::new();
+        let mount = mount(&dir);
+
+        let (_, headers) = put(&mount, "a.txt", "", "first");
+        let etag = headers.get("ETag").unwrap().clone();
+
+        assert_eq!(put(&mount, "a.txt", "If-None-Match: *\r\n", "x").0, 412);
+        assert_eq!(put(&mount, "a.txt", "If-Match: \"other\"\r\n", "x").0, 412);
+        assert_eq!(put(&mount, "new.txt", "If-Match: *\r\n", "x").0, 412);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "first"
+        );
+        assert!(!dir.path().join("new.txt").exists());
+
+        let (code, _) = put(&mount, "a.txt", &format!("If-Match: {etag}\r\n"), "second");
+        assert_eq!(code, 204);
+        assert_eq!(put(&mount, "b.txt", "If-None-Match: *\r\n", "b").0, 201);
+    }
+
+    #[test]
+    fn creates_files_with_post_only_once() {
+        let dir = TempDir::new();
+        let mount = mount(&dir);
+        let post = "POST /files/a.txt HTTP/1.1\r\nContent-Length: 1\r\n\r\na";
+
+        assert_eq!(call(&mount, "a.txt", post).0, 201);
+        assert_eq!(call(&mount, "a.txt", post).0, 409);
+    }
+
+    #[test]
+    fn deletes_files() {
+        let dir = TempDir::new();
+        let mount = mount(&dir);
+        dir.write("a.txt", "a");
+        dir.write("d/b.txt", "b");
+
+        let delete = |filename: &str, fields: &str| {
+            let head = format!("DELETE /files/{filename} HTTP/1.1\r\n{fields}\r\n");
+            call(&mount, filename, &head).0
+        };
+
+        assert_eq!(delete("a.txt", "If-Match: \"other\"\r\n"), 412);
+        assert!(dir.path().join("a.txt").exists());
+
+        assert_eq!(delete("a.txt", ""), 204);
+        assert!(!dir.path().join("a.txt").exists());
+        assert_eq!(delete("a.txt", ""), 404);
+        assert_eq!(delete("d", ""), 409);
+        assert_eq!(delete("../a.txt", ""), 403);
+    }
+
+    /// Fails partway, as a client that disconnects mid-upload does.
+    struct Failing<'a>(&'a [u8]);
+
+    impl Read for Failing<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::ErrorKind::ConnectionReset.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    #[test]
+    fn writes_through_a_temporary_file() {
+        let dir = TempDir::new();
+        let path = dir.write("a.txt", "old");
+
+        let result = write_atomically(&path, &mut Failing(b"new"), WriteMode::CreateOrReplace);
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(names(dir.path()), ["a.txt"]);
+
+        let created = write_atomically(&path, &mut &b"new"[..], WriteMode::Create).unwrap();
+        assert!(created.is_none());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        let replaced = write_atomically(&path, &mut &b"new"[..], WriteMode::CreateOrReplace);
+        assert_eq!(replaced.unwrap().unwrap().len(), 3);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(names(dir.path()), ["a.txt"]);
+    }
+}
@@ -0,0 +1,116 @@
+use std::str::FromStr;
+
+use crate::{prelude::*, url};
+
+/// Decoded `application/x-www-form-urlencoded` pairs, as sent in query strings and form bodies.
+/// Keys may repeat, and pairs keep the order they were sent in.
+#[derive(Debug, Clone, Default)]
+pub struct Form {
+    pairs: Vec<(String, String)>,
+}
+
+impl Form {
+    pub fn parse(s: &str) -> Self {
+        Self {
+            pairs: url::parse_query(s),
+        }
+    }
+
+    /// The first value for `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value for `key`, such as from a group of checkboxes.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// The first value for `key`, or 400 if it is missing.
+    pub fn require(&self, key: &str) -> Result<&str> {
+        self.get(key).ok_or(Error::Status(StatusCode::BadRequest))
+    }
+
+    /// Parses the first value for `key`, or responds with 400 if it is missing or malformed.
+    pub fn parse_value<T: FromStr>(&self, key: &str) -> Result<T> {
+        self.require(key)?
+            .parse()
+            .map_err(|_| Error::Status(StatusCode::BadRequest))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+/// Builds a handler's input from submitted form fields.
+pub trait FromForm: Sized {
+    fn from_form(form: &Form) -> Result<Self>;
+}
+
+impl FromForm for Form {
+    fn from_form(form: &Form) -> Result<Self> {
+        Ok(form.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_plus_signs_and_escapes() {
+        let form = Form::parse("q=a+b%2Bc&name=J%C3%BCrgen&a%20b=%3D");
+
+        assert_eq!(form.get("q"), Some("a b+c"));
+        assert_eq!(form.get("name"), Some("J\u{fc}rgen"));
+        assert_eq!(form.get("a b"), Some("="));
+    }
+
+    #[test]
+    fn keeps_malformed_escapes_as_sent() {
+        let form = Form::parse("a=100%&b=%zz&c=%4&d=%E2%82");
+
+        assert_eq!(form.get("a"), Some("100%"));
+        assert_eq!(form.get("b"), Some("%zz"));
+        assert_eq!(form.get("c"), Some("%4"));
+        assert_eq!(form.get("d"), Some("\u{fffd}"));
+    }
+
+    #[test]
+    fn keeps_repeated_keys_in_order() {
+        let form = Form::parse("tag=a&x&tag=b&&tag=");
+
+        assert_eq!(form.get("tag"), Some("a"));
+        assert_eq!(form.get_all("tag"), ["a", "b", ""]);
+        assert_eq!(form.get("x"), Some(""));
+        assert_eq!(form.iter().count(), 4);
+    }
+
+    #[test]
+    fn requires_and_parses_values() {
+        let form = Form::parse("n=12&bad=x");
+
+        assert_eq!(form.parse_value::<u32>("n").unwrap(), 12);
+        assert!(matches!(
+            form.parse_value::<u32>("bad"),
+            Err(Error::Status(StatusCode::BadRequest))
+        ));
+        assert!(form.require("missing").is_err());
+    }
+}
@@ -0,0 +1,748 @@
+use std::{collections::VecDeque, sync::OnceLock};
+
+use crate::prelude::*;
+
+/// The size of each side's dynamic table until settings say otherwise.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The Huffman code for each byte, right-aligned, and its length in bits.
+const HUFFMAN_CODES: [u32; 256] = [
+    0x1ff8, 0x7fffd8, 0xfffffe2, 0xfffffe3, 0xfffffe4, 0xfffffe5, 0xfffffe6, 0xfffffe7, 0xfffffe8,
+    0xffffea, 0x3ffffffc, 0xfffffe9, 0xfffffea, 0x3ffffffd, 0xfffffeb, 0xfffffec, 0xfffffed,
+    0xfffffee, 0xfffffef, 0xffffff0, 0xffffff1, 0xffffff2, 0x3ffffffe, 0xffffff3, 0xffffff4,
+    0xffffff5, 0xffffff6, 0xffffff7, 0xffffff8, 0xffffff9, 0xffffffa, 0xffffffb, 0x14, 0x3f8,
+    0x3f9, 0xffa, 0x1ff9, 0x15, 0xf8, 0x7fa, 0x3fa, 0x3fb, 0xf9, 0x7fb, 0xfa, 0x16, 0x17, 0x18,
+    0x0, 0x1, 0x2, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x5c, 0xfb, 0x7ffc, 0x20, 0xffb,
+    0x3fc, 0x1ffa, 0x21, 0x5d, 0x5e, 0x5f, 0x60, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70, 0x71, 0x72, 0xfc, 0x73, 0xfd, 0x1ffb, 0x7fff0,
+    0x1ffc, 0x3ffc, 0x22, 0x7ffd, 0x3, 0x23, 0x4, 0x24, 0x5, 0x25, 0x26, 0x27, 0x6, 0x74, 0x75,
+    0x28, 0x29, 0x2a, 0x7, 0x2b, 0x76, 0x2c, 0x8, 0x9, 0x2d, 0x77, 0x78, 0x79, 0x7a, 0x7b, 0x7ffe,
+    0x7fc, 0x3ffd, 0x1ffd, 0xffffffc, 0xfffe6, 0x3fffd2, 0xfffe7, 0xfffe8, 0x3fffd3, 0x3fffd4,
+    0x3fffd5, 0x7fffd9, 0x3fffd6, 0x7fffda, 0x7fffdb, 0x7fffdc, 0x7fffdd, 0x7fffde, 0xffffeb,
+    0x7fffdf, 0xffffec, 0xffffed, 0x3fffd7, 0x7fffe0, 0xffffee, 0x7fffe1, 0x7fffe2, 0x7fffe3,
+    0x7fffe4, 0x1fffdc, 0x3fffd8, 0x7fffe5, 0x3fffd9, 0x7fffe6, 0x7fffe7, 0xffffef, 0x3fffda,
+    0x1fffdd, 0xfffe9, 0x3fffdb, 0x3fffdc, 0x7fffe8, 0x7fffe9, 0x1fffde, 0x7fffea, 0x3fffdd,
+    0x3fffde, 0xfffff0, 0x1fffdf, 0x3fffdf, 0x7fffeb, 0x7fffec, 0x1fffe0, 0x1fffe1, 0x3fffe0,
+    0x1fffe2, 0x7fffed, 0x3fffe1, 0x7fffee, 0x7fffef, 0xfffea, 0x3fffe2, 0x3fffe3, 0x3fffe4,
+    0x7ffff0, 0x3fffe5, 0x3fffe6, 0x7ffff1, 0x3ffffe0, 0x3ffffe1, 0xfffeb, 0x7fff1, 0x3fffe7,
+    0x7ffff2, 0x3fffe8, 0x1ffffec, 0x3ffffe2, 0x3ffffe3, 0x3ffffe4, 0x7ffffde, 0x7ffffdf,
+    0x3ffffe5, 0xfffff1, 0x1ffffed, 0x7fff2, 0x1fffe3, 0x3ffffe6, 0x7ffffe0, 0x7ffffe1, 0x3ffffe7,
+    0x7ffffe2, 0xfffff2, 0x1fffe4, 0x1fffe5, 0x3ffffe8, 0x3ffffe9, 0xffffffd, 0x7ffffe3, 0x7ffffe4,
+    0x7ffffe5, 0xfffec, 0xfffff3, 0xfffed, 0x1fffe6, 0x3fffe9, 0x1fffe7, 0x1fffe8, 0x7ffff3,
+    0x3fffea, 0x3fffeb, 0x1ffffee, 0x1ffffef, 0xfffff4, 0xfffff5, 0x3ffffea, 0x7ffff4, 0x3ffffeb,
+    0x7ffffe6, 0x3ffffec, 0x3ffffed, 0x7ffffe7, 0x7ffffe8, 0x7ffffe9, 0x7ffffea, 0x7ffffeb,
+    0xffffffe, 0x7ffffec, 0x7ffffed, 0x7ffffee, 0x7ffffef, 0x7fffff0, 0x3ffffee,
+];
+
+const HUFFMAN_LENGTHS: [u8; 256] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+];
+
+/// The end-of-string code, 30 one bits, which must never appear inside a string.
+const EOS: u16 = 256;
+const EOS_LENGTH: u8 = 30;
+
+fn error() -> Error {
+    Error::Generic("Failed to decode header block.".into())
+}
+
+// Fields that shouldn't be kept in a table, where they could be probed for.
+fn is_sensitive(name: &str) -> bool {
+    matches!(
+        name,
+        "authorization" | "proxy-authorization" | "cookie" | "set-cookie"
+    )
+}
+
+/// Fields seen recently on a connection, newest first.
+#[derive(Debug)]
+struct DynamicTable {
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    // Each entry counts its name and value plus 32 bytes of overhead.
+    fn entry_size(name: &str, value: &str) -> usize {
+        name.len() + value.len() + 32
+    }
+
+    fn evict(&mut self, target: usize) {
+        while self.size > target {
+            match self.entries.pop_back() {
+                Some((name, value)) => self.size -= Self::entry_size(&name, &value),
+                None => break,
+            }
+        }
+    }
+
+    /// Adds an entry, evicting old ones to make room. An entry larger than the table just empties
+    /// it.
+    fn insert(&mut self, name: String, value: String) {
+        let size = Self::entry_size(&name, &value);
+        self.evict(self.max_size.saturating_sub(size));
+
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    fn resize(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(max_size);
+    }
+
+    /// The entry at a 1-based index into the static table followed by this one.
+    fn get(&self, index: usize) -> Result<(String, String)> {
+        match index {
+            0 => Err(error()),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.into(), value.into()))
+            }
+            _ => self.entries.get(index - 62).cloned().ok_or_else(error),
+        }
+    }
+
+    /// The index of an entry matching both name and value, or else of one matching the name.
+    fn find(&self, name: &str, value: &str) -> Option<(usize, bool)> {
+        let entries = STATIC_TABLE
+            .iter()
+            .copied()
+            .chain(self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str())));
+
+        let mut name_match = None;
+
+        for (i, (n, v)) in entries.enumerate() {
+            if n == name {
+                if v == value {
+                    return Some((i + 1, true));
+                }
+
+                name_match.get_or_insert((i + 1, false));
+            }
+        }
+
+        name_match
+    }
+}
+
+fn encode_integer(out: &mut Vec<u8>, flags: u8, prefix_bits: u8, mut value: usize) {
+    let max = (1 << prefix_bits) - 1;
+
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+
+    out.push(flags | max as u8);
+    value -= max;
+
+    while value >= 128 {
+        out.push((value % 128) as u8 | 0x80);
+        value /= 128;
+    }
+
+    out.push(value as u8);
+}
+
+fn decode_integer(input: &[u8], pos: &mut usize, prefix_bits: u8) -> Result<usize> {
+    let max = (1 << prefix_bits) - 1;
+    let first = *input.get(*pos).ok_or_else(error)?;
+    *pos += 1;
+
+    let mut value = first as usize & max;
+
+    if value < max {
+        return Ok(value);
+    }
+
+    for shift in (0..28).step_by(7) {
+        let byte = *input.get(*pos).ok_or_else(error)?;
+        *pos += 1;
+        value += ((byte & 0x7f) as usize) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(error())
+}
+
+fn huffman_encode(out: &mut Vec<u8>, s: &[u8]) {
+    let mut bits = 0u64;
+    let mut count = 0;
+
+    for &b in s {
+        let length = HUFFMAN_LENGTHS[b as usize];
+        bits = (bits << length) | HUFFMAN_CODES[b as usize] as u64;
+        count += length;
+
+        while count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+
+        bits &= (1 << count) - 1;
+    }
+
+    // The last byte is padded with the start of the end-of-string code.
+    if count > 0 {
+        out.push((bits << (8 - count)) as u8 | (0xff >> count));
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Branch {
+    Empty,
+    Node(usize),
+    Symbol(u16),
+}
+
+/// The Huffman code as a binary tree, each node holding its `0` and `1` branches.
+fn huffman_tree() -> &'static [[Branch; 2]] {
+    static TREE: OnceLock<Vec<[Branch; 2]>> = OnceLock::new();
+
+    TREE.get_or_init(|| {
+        let mut tree = vec![[Branch::Empty; 2]];
+        let codes = HUFFMAN_CODES.iter().zip(HUFFMAN_LENGTHS);
+
+        for (symbol, (&code, length)) in codes.chain([(&0x3fff_ffff, EOS_LENGTH)]).enumerate() {
+            let mut node = 0;
+
+            for i in (0..length).rev() {
+                let bit = (code >> i) as usize & 1;
+
+                node = match tree[node][bit] {
+                    _ if i == 0 => {
+                        tree[node][bit] = Branch::Symbol(symbol as u16);
+                        break;
+                    }
+                    Branch::Node(next) => next,
+                    _ => {
+                        tree.push([Branch::Empty; 2]);
+                        tree[node][bit] = Branch::Node(tree.len() - 1);
+                        tree.len() - 1
+                    }
+                };
+            }
+        }
+
+        tree
+    })
+}
+
+fn huffman_decode(input: &[u8]) -> Result<Vec<u8>> {
+    let tree = huffman_tree();
+    let mut output = Vec::with_capacity(input.len() * 8 / 5);
+    let (mut node, mut depth, mut all_ones) = (0, 0, true);
+
+    for byte in input {
+        for i in (0..8).rev() {
+            let bit = (byte >> i) as usize & 1;
+
+            match tree[node][bit] {
+                Branch::Symbol(EOS) | Branch::Empty => return Err(error()),
+                Branch::Symbol(symbol) => {
+                    output.push(symbol as u8);
+                    (node, depth, all_ones) = (0, 0, true);
+                }
+                Branch::Node(next) => {
+                    node = next;
+                    depth += 1;
+                    all_ones &= bit == 1;
+                }
+            }
+        }
+    }
+
+    // Padding must be the start of the end-of-string code and shorter than a byte.
+    match depth < 8 && all_ones {
+        true => Ok(output),
+        false => Err(error()),
+    }
+}
+
+fn encode_string(out: &mut Vec<u8>, s: &str) {
+    let bits = s
+        .bytes()
+        .map(|b| HUFFMAN_LENGTHS[b as usize] as usize)
+        .sum::<usize>();
+
+    if bits.div_ceil(8) < s.len() {
+        encode_integer(out, 0x80, 7, bits.div_ceil(8));
+        huffman_encode(out, s.as_bytes());
+    } else {
+        encode_integer(out, 0, 7, s.len());
+        out.extend(s.as_bytes());
+    }
+}
+
+fn decode_string(input: &[u8], pos: &mut usize) -> Result<String> {
+    let huffman = input.get(*pos).ok_or_else(error)? & 0x80 != 0;
+    let length = decode_integer(input, pos, 7)?;
+
+    let end = pos.checked_add(length).ok_or_else(error)?;
+    let bytes = input.get(*pos..end).ok_or_else(error)?;
+    *pos = end;
+
+    let bytes = match huffman {
+        true => huffman_decode(bytes)?,
+        false => bytes.to_vec(),
+    };
+
+    String::from_utf8(bytes).map_err(|_| error())
+}
+
+/// Decompresses the header blocks a client sends on one connection.
+#[derive(Debug)]
+pub struct Decoder {
+    table: DynamicTable,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            table: DynamicTable::new(),
+        }
+    }
+
+    /// Decodes a complete header block into its fields, in order. Any error leaves the table out
+    /// of step with the client's, so the connection can't continue.
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+
+        while let Some(&byte) = block.get(pos) {
+            if byte & 0x80 != 0 {
+                let index = decode_integer(block, &mut pos, 7)?;
+                fields.push(self.table.get(index)?);
+            } else if byte & 0x40 != 0 {
+                let (name, value) = self.literal(block, &mut pos, 6)?;
+                self.table.insert(name.clone(), value.clone());
+                fields.push((name, value));
+            } else if byte & 0x20 != 0 {
+                // Size updates may only start a block, and can't exceed what this side allows.
+                let size = decode_integer(block, &mut pos, 5)?;
+
+                if !fields.is_empty() || size > DEFAULT_TABLE_SIZE {
+                    return Err(error());
+                }
+
+                self.table.resize(size);
+            } else {
+                fields.push(self.literal(block, &mut pos, 4)?);
+            }
+        }
+
+        Ok(fields)
+    }
+
+    fn literal(&self, block: &[u8], pos: &mut usize, prefix_bits: u8) -> Result<(String, String)> {
+        let name = match decode_integer(block, pos, prefix_bits)? {
+            0 => decode_string(block, pos)?,
+            index => self.table.get(index)?.0,
+        };
+
+        Ok((name, decode_string(block, pos)?))
+    }
+}
+
+/// Compresses the header blocks sent to a client on one connection. Blocks must reach the client
+/// in the order they were encoded.
+#[derive(Debug)]
+pub struct Encoder {
+    table: DynamicTable,
+    /// The smallest and latest sizes the table took since the last block, which the client has
+    /// to be told about.
+    resized: Option<(usize, usize)>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self {
+            table: DynamicTable::new(),
+            resized: None,
+        }
+    }
+
+    /// Follows the client's `SETTINGS_HEADER_TABLE_SIZE`, never growing past the default.
+    pub fn set_max_size(&mut self, size: usize) {
+        let size = size.min(DEFAULT_TABLE_SIZE);
+
+        if size != self.table.max_size {
+            self.table.resize(size);
+            let smallest = self
+                .resized
+                .map_or(size, |(smallest, _)| smallest.min(size));
+            self.resized = Some((smallest, size));
+        }
+    }
+
+    pub fn encode<'f>(&mut self, fields: impl IntoIterator<Item = (&'f str, &'f str)>) -> Vec<u8> {
+        let mut block = Vec::new();
+
+        if let Some((smallest, size)) = self.resized.take() {
+            encode_integer(&mut block, 0x20, 5, smallest);
+
+            if size != smallest {
+                encode_integer(&mut block, 0x20, 5, size);
+            }
+        }
+
+        for (name, value) in fields {
+            let name_index = match self.table.find(name, value) {
+                Some((index, true)) => {
+                    encode_integer(&mut block, 0x80, 7, index);
+                    continue;
+                }
+                Some((index, false)) => index,
+                None => 0,
+            };
+
+            if is_sensitive(name) {
+                encode_integer(&mut block, 0x10, 4, name_index);
+            } else {
+                encode_integer(&mut block, 0x40, 6, name_index);
+                self.table.insert(name.into(), value.into());
+            }
+
+            if name_index == 0 {
+                encode_string(&mut block, name);
+            }
+
+            encode_string(&mut block, value);
+        }
+
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s = s.replace(' ', "");
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|&(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    // The requests of RFC 7541, C.3 and C.4, which share a connection and so a table.
+    const REQUESTS: [&[(&str, &str)]; 3] = [
+        &[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+        ],
+        &[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+            ("cache-control", "no-cache"),
+        ],
+        &[
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/index.html"),
+            (":authority", "www.example.com"),
+            ("custom-key", "custom-value"),
+        ],
+    ];
+
+    #[test]
+    fn encodes_integers() {
+        for (value, prefix_bits, encoded) in [
+            (10, 5, "0a"),
+            (1337, 5, "1f 9a 0a"),
+            (42, 8, "2a"),
+            (31, 5, "1f 00"),
+            (127, 7, "7f 00"),
+            (128, 7, "7f 01"),
+        ] {
+            let mut out = Vec::new();
+            encode_integer(&mut out, 0, prefix_bits, value);
+            assert_eq!(out, hex(encoded), "{value}");
+
+            let mut pos = 0;
+            assert_eq!(decode_integer(&out, &mut pos, prefix_bits).unwrap(), value);
+            assert_eq!(pos, out.len());
+        }
+    }
+
+    #[test]
+    fn decodes_integers_ignoring_flags() {
+        let mut pos = 0;
+        assert_eq!(decode_integer(&hex("ff 9a 0a"), &mut pos, 5).unwrap(), 1337);
+    }
+
+    #[test]
+    fn rejects_malformed_integers() {
+        for encoded in ["", "1f", "1f 9a", "1f ff ff ff ff 0f"] {
+            let mut pos = 0;
+            assert!(
+                decode_integer(&hex(encoded), &mut pos, 5).is_err(),
+                "{encoded}"
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_plain_requests() {
+        let mut decoder = Decoder::new();
+        let blocks = [
+            "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+            "8286 84be 5808 6e6f 2d63 6163 6865",
+            "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+        ];
+
+        for ((block, expected), size) in blocks.iter().zip(REQUESTS).zip([57, 110, 164]) {
+            assert_eq!(decoder.decode(&hex(block)).unwrap(), fields(expected));
+            assert_eq!(decoder.table.size, size);
+        }
+    }
+
+    // The same requests with Huffman-coded strings, from RFC 7541, C.4.
+    const HUFFMAN_BLOCKS: [&str; 3] = [
+        "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+        "8286 84be 5886 a8eb 1064 9cbf",
+        "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+    ];
+
+    #[test]
+    fn decodes_huffman_requests() {
+        let mut decoder = Decoder::new();
+
+        for (block, expected) in HUFFMAN_BLOCKS.iter().zip(REQUESTS) {
+            assert_eq!(decoder.decode(&hex(block)).unwrap(), fields(expected));
+        }
+
+        assert_eq!(
+            decoder.table.entries,
+            fields(&[
+                ("custom-key", "custom-value"),
+                ("cache-control", "no-cache"),
+                (":authority", "www.example.com"),
+            ])
+        );
+    }
+
+    #[test]
+    fn encodes_requests() {
+        let mut encoder = Encoder::new();
+
+        for (block, request) in HUFFMAN_BLOCKS.iter().zip(REQUESTS) {
+            assert_eq!(encoder.encode(request.iter().copied()), hex(block));
+        }
+    }
+
+    #[test]
+    fn round_trips_through_a_shared_table() {
+        let (mut encoder, mut decoder) = (Encoder::new(), Decoder::new());
+        let response = [
+            (":status", "200"),
+            ("content-type", "text/plain"),
+            ("x-bytes", "\u{0}\u{7f} ünïcode ~|"),
+            ("set-cookie", "id=1; Secure"),
+        ];
+
+        for _ in 0..3 {
+            let block = encoder.encode(response);
+            assert_eq!(decoder.decode(&block).unwrap(), fields(&response));
+        }
+
+        // Only the repeated, insensitive fields are indexed.
+        assert!(encoder.encode(response).len() < 20);
+        assert!(decoder
+            .table
+            .entries
+            .iter()
+            .all(|(name, _)| name != "set-cookie"));
+    }
+
+    #[test]
+    fn huffman_round_trips_every_byte() {
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        let mut encoded = Vec::new();
+        huffman_encode(&mut encoded, &bytes);
+
+        assert_eq!(huffman_decode(&encoded).unwrap(), bytes);
+        assert_eq!(huffman_decode(&[]).unwrap(), b"");
+    }
+
+    #[test]
+    fn rejects_malformed_huffman() {
+        for encoded in [
+            // The end-of-string code itself.
+            "ffff fffc",
+            // Padding of a whole byte.
+            "f1e3 c2e5 f23a 6ba0 ab90 f4ff ff",
+            // Padding that isn't all ones.
+            "f1e3 c2e5 f23a 6ba0 ab90 f4fe",
+        ] {
+            assert!(huffman_decode(&hex(encoded)).is_err(), "{encoded}");
+        }
+    }
+
+    #[test]
+    fn evicts_the_oldest_entries() {
+        let mut table = DynamicTable::new();
+        table.resize(100);
+
+        table.insert("a".into(), "1".into());
+        table.insert("b".into(), "2".into());
+        table.insert("c".into(), "3".into());
+
+        assert_eq!(table.entries, fields(&[("c", "3"), ("b", "2")]));
+        assert_eq!(table.size, 68);
+        assert_eq!(table.get(62).unwrap(), ("c".into(), "3".into()));
+        assert_eq!(table.get(2).unwrap(), (":method".into(), "GET".into()));
+        assert!(table.get(0).is_err());
+        assert!(table.get(64).is_err());
+
+        // An entry larger than the table empties it.
+        table.insert("d".into(), "x".repeat(100));
+        assert!(table.entries.is_empty());
+        assert_eq!(table.size, 0);
+    }
+
+    #[test]
+    fn follows_table_size_updates() {
+        let mut decoder = Decoder::new();
+        decoder.decode(&hex(HUFFMAN_BLOCKS[0])).unwrap();
+
+        // A size update to zero, then a lookup of the entry it evicted.
+        assert_eq!(decoder.decode(&hex("20")).unwrap(), vec![]);
+        assert!(decoder.table.entries.is_empty());
+        assert!(decoder.decode(&hex("be")).is_err());
+    }
+
+    #[test]
+    fn signals_table_size_changes() {
+        let mut encoder = Encoder::new();
+        encoder.set_max_size(0);
+        encoder.set_max_size(100);
+        encoder.set_max_size(1 << 20);
+
+        let block = encoder.encode([(":status", "200")]);
+        assert_eq!(block, hex("20 3f e1 1f 88"));
+        assert!(encoder.encode([]).is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        let mut long_string = hex("00 7f e1 1f");
+        long_string.extend([b'a'; 10]);
+
+        for block in [
+            // Index zero, and one past the static table.
+            hex("80"),
+            hex("be"),
+            // A literal whose string runs past the block.
+            hex("40 05 61 62"),
+            long_string,
+            // A size update after a field, and one past the default.
+            hex("82 20"),
+            hex("3f e2 1f"),
+            // A value that isn't UTF-8.
+            hex("00 01 61 01 ff"),
+        ] {
+            assert!(Decoder::new().decode(&block).is_err(), "{block:02x?}");
+        }
+    }
+}
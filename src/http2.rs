@@ -0,0 +1,1383 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, BufReader, Cursor, Read, Write},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        mpsc, Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+use crate::{
+    body::Body,
+    config::Config,
+    headers::Headers,
+    hpack::{Decoder, Encoder},
+    prelude::*,
+    request::Peer,
+    router::Router,
+};
+
+/// What every HTTP/2 client sends before its first frame.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_HEADER_LEN: usize = 9;
+const DEFAULT_FRAME_SIZE: usize = 16_384;
+const MAX_FRAME_SIZE: usize = (1 << 24) - 1;
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+/// How much of a request body the client may send ahead of the handler reading it.
+const STREAM_WINDOW: i64 = 1 << 18;
+/// How much the client may send ahead on the whole connection. It's topped up as frames arrive,
+/// so each stream's window is what bounds the memory a connection can take.
+const CONNECTION_WINDOW: i64 = 1 << 20;
+const MAX_CONCURRENT_STREAMS: usize = 100;
+/// The most a header block may take across its HEADERS and CONTINUATION frames.
+const MAX_HEADER_BLOCK: usize = 1 << 16;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameType {
+    Data = 0x0,
+    Headers = 0x1,
+    Priority = 0x2,
+    RstStream = 0x3,
+    Settings = 0x4,
+    PushPromise = 0x5,
+    Ping = 0x6,
+    GoAway = 0x7,
+    WindowUpdate = 0x8,
+    Continuation = 0x9,
+}
+
+impl TryFrom<u8> for FrameType {
+    type Error = Error;
+
+    fn try_from(n: u8) -> Result<Self> {
+        match n {
+            0x0 => Ok(Self::Data),
+            0x1 => Ok(Self::Headers),
+            0x2 => Ok(Self::Priority),
+            0x3 => Ok(Self::RstStream),
+            0x4 => Ok(Self::Settings),
+            0x5 => Ok(Self::PushPromise),
+            0x6 => Ok(Self::Ping),
+            0x7 => Ok(Self::GoAway),
+            0x8 => Ok(Self::WindowUpdate),
+            0x9 => Ok(Self::Continuation),
+            _ => Err(Error::Generic("Failed to parse frame type.".into())),
+        }
+    }
+}
+
+/// Why a stream or connection was ended, as sent in `RST_STREAM` and `GOAWAY` frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    FlowControlError = 0x3,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    CompressionError = 0x9,
+    EnhanceYourCalm = 0xb,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoError => write!(f, "NO_ERROR"),
+            Self::ProtocolError => write!(f, "PROTOCOL_ERROR"),
+            Self::FlowControlError => write!(f, "FLOW_CONTROL_ERROR"),
+            Self::StreamClosed => write!(f, "STREAM_CLOSED"),
+            Self::FrameSizeError => write!(f, "FRAME_SIZE_ERROR"),
+            Self::RefusedStream => write!(f, "REFUSED_STREAM"),
+            Self::CompressionError => write!(f, "COMPRESSION_ERROR"),
+            Self::EnhanceYourCalm => write!(f, "ENHANCE_YOUR_CALM"),
+        }
+    }
+}
+
+/// Ends a connection: either the client broke the protocol, which it's told about, the client
+/// went quiet for longer than the read timeout, or the transport failed.
+enum Failure {
+    Protocol(ErrorCode),
+    Idle,
+    Io(io::Error),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<Failure> for Error {
+    fn from(failure: Failure) -> Self {
+        match failure {
+            Failure::Protocol(code) => Error::Generic(format!("HTTP/2 connection error: {code}.")),
+            Failure::Idle => io::Error::from(io::ErrorKind::TimedOut).into(),
+            Failure::Io(e) => e.into(),
+        }
+    }
+}
+
+type Outcome<T> = core::result::Result<T, Failure>;
+
+fn protocol_error<T>() -> Outcome<T> {
+    Err(Failure::Protocol(ErrorCode::ProtocolError))
+}
+
+fn frame_size_error<T>() -> Outcome<T> {
+    Err(Failure::Protocol(ErrorCode::FrameSizeError))
+}
+
+// Fields that only make sense for a single HTTP/1.1 hop, which HTTP/2 forbids.
+fn is_connection_specific(name: &str) -> bool {
+    matches!(
+        name.to_ascii_lowercase().as_str(),
+        "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade"
+    )
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// The payload without padding, or for HEADERS, without padding and priority fields. The
+    /// length of the padding is also returned, as it counts against flow control.
+    fn content(&self) -> Outcome<(&[u8], usize)> {
+        let mut content = self.payload.as_slice();
+        let mut padding = 0;
+
+        if self.has(PADDED) {
+            let Some((&length, rest)) = content.split_first() else {
+                return frame_size_error();
+            };
+
+            padding = length as usize + 1;
+            content = match rest.len().checked_sub(length as usize) {
+                Some(end) => &rest[..end],
+                None => return protocol_error(),
+            };
+        }
+
+        if self.kind == FrameType::Headers as u8 && self.has(PRIORITY) {
+            content = match content.get(5..) {
+                Some(content) => content,
+                None => return frame_size_error(),
+            };
+        }
+
+        Ok((content, padding))
+    }
+}
+
+fn read_frame(reader: &mut dyn BufRead) -> Outcome<Option<Frame>> {
+    let mut head = [0; FRAME_HEADER_LEN];
+
+    // Waiting for a frame to start consumes nothing, so a timeout there leaves the connection
+    // intact, unlike one partway through a frame.
+    match reader.fill_buf() {
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ) =>
+        {
+            return Err(Failure::Idle)
+        }
+        _ => {}
+    }
+
+    // Clients may reset the connection rather than close it once they have their responses.
+    match reader.read_exact(&mut head) {
+        Ok(()) => {}
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let length = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+
+    if length > DEFAULT_FRAME_SIZE {
+        return frame_size_error();
+    }
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+
+    Ok(Some(Frame {
+        kind: head[3],
+        flags: head[4],
+        stream: u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff,
+        payload,
+    }))
+}
+
+/// What the threads answering streams share with the thread reading frames.
+struct State {
+    writer: Box<dyn Write + Send>,
+    encoder: Encoder,
+    /// How much more DATA the client accepts on the whole connection.
+    window: i64,
+    /// How much more DATA the client accepts on each stream still being answered.
+    streams: HashMap<u32, i64>,
+    /// The window streams start with, as the client last set it.
+    initial_window: i64,
+    max_frame_size: usize,
+    closed: bool,
+}
+
+fn stream_reset() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "Stream was reset.")
+}
+
+impl State {
+    /// Fails if the stream was reset or the connection closed, after which nothing more may be
+    /// sent on it.
+    fn check_open(&self, stream: u32) -> io::Result<()> {
+        match !self.closed && self.streams.contains_key(&stream) {
+            true => Ok(()),
+            false => Err(stream_reset()),
+        }
+    }
+
+    fn write_frame(
+        &mut self,
+        kind: FrameType,
+        flags: u8,
+        stream: u32,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        frame.extend(&(payload.len() as u32).to_be_bytes()[1..]);
+        frame.extend([kind as u8, flags]);
+        frame.extend(stream.to_be_bytes());
+        frame.extend(payload);
+
+        self.writer.write_all(&frame)?;
+        self.writer.flush()
+    }
+}
+
+struct Connection {
+    state: Mutex<State>,
+    /// Signalled whenever a send window grows, a stream is reset or the connection closes.
+    changed: Condvar,
+}
+
+impl Connection {
+    fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                writer,
+                encoder: Encoder::new(),
+                window: DEFAULT_WINDOW,
+                streams: HashMap::new(),
+                initial_window: DEFAULT_WINDOW,
+                max_frame_size: DEFAULT_FRAME_SIZE,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send(&self, kind: FrameType, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+        let mut state = self.lock();
+
+        if state.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        state.write_frame(kind, flags, stream, payload)
+    }
+
+    fn reset(&self, stream: u32, code: ErrorCode) -> io::Result<()> {
+        self.finish(stream);
+        self.send(
+            FrameType::RstStream,
+            0,
+            stream,
+            &(code as u32).to_be_bytes(),
+        )
+    }
+
+    fn go_away(&self, last_stream: u32, code: ErrorCode) -> io::Result<()> {
+        let payload = [last_stream.to_be_bytes(), (code as u32).to_be_bytes()].concat();
+        self.send(FrameType::GoAway, 0, 0, &payload)
+    }
+
+    fn window_update(&self, stream: u32, increment: u32) -> io::Result<()> {
+        self.send(FrameType::WindowUpdate, 0, stream, &increment.to_be_bytes())
+    }
+
+    /// Stops sending on a stream, waking its handler if it's waiting for window.
+    fn finish(&self, stream: u32) {
+        self.lock().streams.remove(&stream);
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+
+    fn apply_settings(&self, payload: &[u8]) -> Outcome<()> {
+        if payload.len() % 6 != 0 {
+            return frame_size_error();
+        }
+
+        let mut state = self.lock();
+
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+
+            match id {
+                SETTINGS_HEADER_TABLE_SIZE => state.encoder.set_max_size(value as usize),
+                SETTINGS_ENABLE_PUSH if value > 1 => return protocol_error(),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = value as i64;
+
+                    if value > MAX_WINDOW {
+                        return Err(Failure::Protocol(ErrorCode::FlowControlError));
+                    }
+
+                    // Streams already open move by the difference, which may leave them negative.
+                    let delta = value - state.initial_window;
+                    state.initial_window = value;
+
+                    for window in state.streams.values_mut() {
+                        *window += delta;
+
+                        if *window > MAX_WINDOW {
+                            return Err(Failure::Protocol(ErrorCode::FlowControlError));
+                        }
+                    }
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(DEFAULT_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&(value as usize)) {
+                        return protocol_error();
+                    }
+
+                    state.max_frame_size = value as usize;
+                }
+                _ => {}
+            }
+        }
+
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Sends a header block, continued over as many frames as it needs. The block is encoded
+    /// under the same lock it's sent with, as the client decodes blocks in the order they arrive.
+    fn send_headers(
+        &self,
+        stream: u32,
+        fields: &[(String, String)],
+        end_stream: bool,
+    ) -> io::Result<()> {
+        let mut state = self.lock();
+        state.check_open(stream)?;
+
+        let fields = fields.iter().map(|(n, v)| (n.as_str(), v.as_str()));
+        let block = state.encoder.encode(fields);
+        let frames = block.chunks(state.max_frame_size).collect::<Vec<_>>();
+
+        for (i, fragment) in frames.iter().enumerate() {
+            let (kind, mut flags) = match i {
+                0 if end_stream => (FrameType::Headers, END_STREAM),
+                0 => (FrameType::Headers, 0),
+                _ => (FrameType::Continuation, 0),
+            };
+
+            if i == frames.len() - 1 {
+                flags |= END_HEADERS;
+            }
+
+            state.write_frame(kind, flags, stream, fragment)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends a body as DATA frames, waiting whenever the client's windows are used up.
+    fn send_data(&self, stream: u32, body: &mut dyn Read) -> io::Result<()> {
+        let mut buffer = vec![0; DEFAULT_FRAME_SIZE];
+
+        loop {
+            let n = body.read(&mut buffer)?;
+
+            if n == 0 {
+                let mut state = self.lock();
+                state.check_open(stream)?;
+                return state.write_frame(FrameType::Data, END_STREAM, stream, &[]);
+            }
+
+            let mut sent = 0;
+
+            while sent < n {
+                let mut state = self.lock();
+
+                let available = loop {
+                    state.check_open(stream)?;
+
+                    match state.window.min(state.streams[&stream]) {
+                        available if available > 0 => break available as usize,
+                        _ => state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner()),
+                    }
+                };
+
+                let end = n.min(sent + available);
+                state.window -= (end - sent) as i64;
+                state
+                    .streams
+                    .entry(stream)
+                    .and_modify(|w| *w -= (end - sent) as i64);
+                state.write_frame(FrameType::Data, 0, stream, &buffer[sent..end])?;
+                sent = end;
+            }
+        }
+    }
+}
+
+/// Reads a request body from the DATA frames of its stream, opening the stream's window again
+/// as the handler consumes it.
+struct StreamBody<'c> {
+    connection: &'c Connection,
+    id: u32,
+    /// Each DATA frame's content, then an empty chunk once the client ends the stream. The sender
+    /// is dropped without one if the stream is reset.
+    chunks: mpsc::Receiver<Vec<u8>>,
+    chunk: Cursor<Vec<u8>>,
+    window: Arc<AtomicI64>,
+    /// Bytes read that the client hasn't been given window for yet.
+    unacknowledged: i64,
+    complete: bool,
+}
+
+impl StreamBody<'_> {
+    /// Whether the client may still send more of a body the handler has stopped reading.
+    fn still_sending(&mut self) -> bool {
+        while !self.complete {
+            match self.chunks.try_recv() {
+                Ok(chunk) if chunk.is_empty() => self.complete = true,
+                Ok(_) => {}
+                Err(mpsc::TryRecvError::Empty) => return true,
+                Err(mpsc::TryRecvError::Disconnected) => return false,
+            }
+        }
+
+        false
+    }
+}
+
+impl Read for StreamBody<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.position() as usize == self.chunk.get_ref().len() {
+            if self.complete {
+                return Ok(0);
+            }
+
+            match self.chunks.recv() {
+                Ok(chunk) if chunk.is_empty() => self.complete = true,
+                Ok(chunk) => self.chunk = Cursor::new(chunk),
+                Err(_) => return Err(stream_reset()),
+            }
+        }
+
+        let n = self.chunk.read(buf)?;
+        self.unacknowledged += n as i64;
+
+        // The window is credited before the client hears of it, so its next frames always fit.
+        if self.unacknowledged >= STREAM_WINDOW / 2 && !self.complete {
+            self.window.fetch_add(self.unacknowledged, Ordering::SeqCst);
+            self.connection
+                .window_update(self.id, self.unacknowledged as u32)?;
+            self.unacknowledged = 0;
+        }
+
+        Ok(n)
+    }
+}
+
+/// Stands in for the connection when a body sends `100 Continue`, which HTTP/2 sends as a HEADERS
+/// frame rather than a status line.
+struct Interim<'c> {
+    connection: &'c Connection,
+    id: u32,
+}
+
+impl Write for Interim<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let status = StatusCode::Continue.code().to_string();
+        let fields = [(":status".into(), status)];
+        self.connection.send_headers(self.id, &fields, false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A request's method, target and fields, as decoded from its header block.
+struct Head {
+    method: Method,
+    uri: String,
+    headers: Headers,
+    length: Option<u64>,
+}
+
+impl Head {
+    /// Checks the decoded fields and moves the pseudo-header fields into place, or returns `None`
+    /// for a malformed request.
+    fn parse(fields: Vec<(String, String)>) -> Option<Self> {
+        let mut pseudo = HashMap::new();
+        let mut headers = Headers::new();
+
+        for (name, value) in fields {
+            if name.starts_with(':') {
+                let known = matches!(
+                    name.as_str(),
+                    ":method" | ":scheme" | ":path" | ":authority"
+                );
+
+                // Pseudo-header fields come first, and only once each.
+                if !known || !headers.is_empty() || pseudo.insert(name, value).is_some() {
+                    return None;
+                }
+            } else {
+                let malformed = name.bytes().any(|b| b.is_ascii_uppercase())
+                    || is_connection_specific(&name)
+                    || (name == "te" && value != "trailers");
+
+                if malformed {
+                    return None;
+                }
+
+                headers.append(name, value);
+            }
+        }
+
+        let method = Method::try_from(pseudo.get(":method")?.as_str()).ok()?;
+        pseudo.get(":scheme")?;
+        let uri = pseudo.remove(":path").filter(|path| !path.is_empty())?;
+
+        if let Some(authority) = pseudo.remove(":authority") {
+            if !headers.contains("host") {
+                headers.insert("host", authority);
+            }
+        }
+
+        let length = match headers.get("content-length") {
+            Some(length) => Some(length.trim().parse().ok()?),
+            None => None,
+        };
+
+        Some(Self {
+            method,
+            uri,
+            headers,
+            length,
+        })
+    }
+}
+
+/// An HTTP/1.1 request that asked to continue over HTTP/2 without TLS. It's answered as stream 1
+/// once the switch is made.
+pub struct Upgrade {
+    head: Head,
+    settings: Vec<u8>,
+}
+
+impl Upgrade {
+    /// Takes up a request's offer of `h2c`. Requests with a body stay on HTTP/1.1, which would
+    /// have to carry it.
+    pub fn offered(rq: &Request) -> Option<Self> {
+        let offered = rq.protocol == Protocol::Http11
+            && rq.has_token("Upgrade", "h2c")
+            && rq.has_token("Connection", "HTTP2-Settings")
+            && rq.body().is_empty();
+
+        if !offered {
+            return None;
+        }
+
+        let settings = rq.header("HTTP2-Settings")?.trim().trim_end_matches('=');
+        let settings = URL_SAFE_NO_PAD.decode(settings).ok()?;
+
+        let mut headers = rq.headers.clone();
+
+        for name in ["Connection", "Upgrade", "HTTP2-Settings"] {
+            headers.remove(name);
+        }
+
+        Some(Self {
+            head: Head {
+                method: rq.method.clone(),
+                uri: rq.uri.clone(),
+                headers,
+                length: Some(0),
+            },
+            settings,
+        })
+    }
+}
+
+/// A request whose header block is complete, ready to be answered on its own thread.
+struct Opened<'c> {
+    id: u32,
+    head: Head,
+    body: StreamBody<'c>,
+}
+
+/// A request body still arriving, as the thread reading frames sees it.
+struct Inbound {
+    chunks: mpsc::Sender<Vec<u8>>,
+    window: Arc<AtomicI64>,
+}
+
+/// The thread reading frames, which handles everything but answering requests.
+struct Frames<'c> {
+    connection: &'c Connection,
+    decoder: Decoder,
+    inbound: HashMap<u32, Inbound>,
+    last_stream: u32,
+}
+
+impl<'c> Frames<'c> {
+    fn new(connection: &'c Connection) -> Self {
+        Self {
+            connection,
+            decoder: Decoder::new(),
+            inbound: HashMap::new(),
+            last_stream: 0,
+        }
+    }
+
+    /// Handles frames until a new request's header block is complete, or returns `None` once the
+    /// client closes the connection.
+    fn next_request(&mut self, reader: &mut dyn BufRead) -> Outcome<Option<Opened<'c>>> {
+        loop {
+            let frame = match read_frame(reader) {
+                // Clients may have nothing to say while responses stream, such as events.
+                Err(Failure::Idle) if !self.connection.lock().streams.is_empty() => continue,
+                frame => frame?,
+            };
+
+            let Some(frame) = frame else {
+                return Ok(None);
+            };
+
+            // Frames of unknown types are ignored.
+            let Ok(kind) = FrameType::try_from(frame.kind) else {
+                continue;
+            };
+
+            match kind {
+                FrameType::Data => self.data(&frame)?,
+                FrameType::Headers => {
+                    if let Some(opened) = self.headers(frame, reader)? {
+                        return Ok(Some(opened));
+                    }
+                }
+                FrameType::Priority if frame.stream == 0 => return protocol_error(),
+                FrameType::Priority if frame.payload.len() != 5 => return frame_size_error(),
+                FrameType::Priority => {}
+                FrameType::RstStream => self.rst_stream(&frame)?,
+                FrameType::Settings => self.settings(&frame)?,
+                FrameType::Ping => self.ping(&frame)?,
+                FrameType::GoAway if frame.stream != 0 => return protocol_error(),
+                // The client closes the connection once it has its responses.
+                FrameType::GoAway => {}
+                FrameType::WindowUpdate => self.window_update(&frame)?,
+                FrameType::PushPromise | FrameType::Continuation => return protocol_error(),
+            }
+        }
+    }
+
+    fn data(&mut self, frame: &Frame) -> Outcome<()> {
+        if frame.stream == 0 || frame.stream > self.last_stream {
+            return protocol_error();
+        }
+
+        // The connection's window is opened again straight away, and padding's stream window too.
+        let (data, padding) = frame.content()?;
+
+        if !frame.payload.is_empty() {
+            self.connection
+                .window_update(0, frame.payload.len() as u32)?;
+        }
+
+        // Streams that ended or were reset may still have frames in flight, which are dropped.
+        let Some(inbound) = self.inbound.get(&frame.stream) else {
+            return Ok(());
+        };
+
+        if padding > 0 {
+            self.connection
+                .window_update(frame.stream, padding as u32)?;
+        }
+
+        let length = data.len() as i64;
+
+        if inbound.window.fetch_sub(length, Ordering::SeqCst) < length {
+            return Err(Failure::Protocol(ErrorCode::FlowControlError));
+        }
+
+        // The handler may have answered without reading the whole body.
+        let delivered = data.is_empty() || inbound.chunks.send(data.to_vec()).is_ok();
+
+        if frame.has(END_STREAM) || !delivered {
+            let _ = inbound.chunks.send(Vec::new());
+            self.inbound.remove(&frame.stream);
+        }
+
+        Ok(())
+    }
+
+    fn headers(&mut self, frame: Frame, reader: &mut dyn BufRead) -> Outcome<Option<Opened<'c>>> {
+        let id = frame.stream;
+        let end_stream = frame.has(END_STREAM);
+        let mut block = frame.content()?.0.to_vec();
+        let mut end_headers = frame.has(END_HEADERS);
+
+        while !end_headers {
+            let Some(next) = read_frame(reader)? else {
+                return Err(Failure::Io(io::ErrorKind::UnexpectedEof.into()));
+            };
+
+            if next.kind != FrameType::Continuation as u8 || next.stream != id {
+                return protocol_error();
+            }
+
+            block.extend(&next.payload);
+            end_headers = next.has(END_HEADERS);
+
+            if block.len() > MAX_HEADER_BLOCK {
+                return Err(Failure::Protocol(ErrorCode::EnhanceYourCalm));
+            }
+        }
+
+        let fields = self
+            .decoder
+            .decode(&block)
+            .map_err(|_| Failure::Protocol(ErrorCode::CompressionError))?;
+
+        if id == 0 || id % 2 == 0 {
+            return protocol_error();
+        }
+
+        // On a stream already open, the block can only be trailers, which end the body.
+        if id <= self.last_stream {
+            return match self.inbound.remove(&id) {
+                Some(inbound) if end_stream => {
+                    let _ = inbound.chunks.send(Vec::new());
+                    Ok(None)
+                }
+                Some(_) => protocol_error(),
+                None => Err(Failure::Protocol(ErrorCode::StreamClosed)),
+            };
+        }
+
+        self.last_stream = id;
+
+        let Some(head) = Head::parse(fields) else {
+            self.connection.reset(id, ErrorCode::ProtocolError)?;
+            return Ok(None);
+        };
+
+        if self.connection.lock().streams.len() >= MAX_CONCURRENT_STREAMS {
+            self.connection.reset(id, ErrorCode::RefusedStream)?;
+            return Ok(None);
+        }
+
+        Ok(Some(self.open(id, head, end_stream)))
+    }
+
+    fn open(&mut self, id: u32, mut head: Head, end_stream: bool) -> Opened<'c> {
+        {
+            let mut state = self.connection.lock();
+            let window = state.initial_window;
+            state.streams.insert(id, window);
+        }
+
+        let (sender, chunks) = mpsc::channel();
+        let window = Arc::new(AtomicI64::new(STREAM_WINDOW));
+
+        if end_stream {
+            head.length = Some(0);
+            let _ = sender.send(Vec::new());
+        } else {
+            let inbound = Inbound {
+                chunks: sender,
+                window: window.clone(),
+            };
+
+            self.inbound.insert(id, inbound);
+        }
+
+        let body = StreamBody {
+            connection: self.connection,
+            id,
+            chunks,
+            chunk: Cursor::new(Vec::new()),
+            window,
+            unacknowledged: 0,
+            complete: false,
+        };
+
+        Opened { id, head, body }
+    }
+
+    fn rst_stream(&mut self, frame: &Frame) -> Outcome<()> {
+        if frame.stream == 0 || frame.stream > self.last_stream {
+            return protocol_error();
+        }
+
+        if frame.payload.len() != 4 {
+            return frame_size_error();
+        }
+
+        // Dropping the sender fails the handler's next read of the body.
+        self.inbound.remove(&frame.stream);
+        self.connection.finish(frame.stream);
+        Ok(())
+    }
+
+    fn settings(&mut self, frame: &Frame) -> Outcome<()> {
+        if frame.stream != 0 {
+            return protocol_error();
+        }
+
+        if frame.has(ACK) {
+            return match frame.payload.is_empty() {
+                true => Ok(()),
+                false => frame_size_error(),
+            };
+        }
+
+        self.connection.apply_settings(&frame.payload)?;
+        self.connection.send(FrameType::Settings, ACK, 0, &[])?;
+        Ok(())
+    }
+
+    fn ping(&mut self, frame: &Frame) -> Outcome<()> {
+        if frame.stream != 0 {
+            return protocol_error();
+        }
+
+        if frame.payload.len() != 8 {
+            return frame_size_error();
+        }
+
+        if !frame.has(ACK) {
+            self.connection
+                .send(FrameType::Ping, ACK, 0, &frame.payload)?;
+        }
+
+        Ok(())
+    }
+
+    fn window_update(&mut self, frame: &Frame) -> Outcome<()> {
+        let Ok(increment) = <[u8; 4]>::try_from(frame.payload.as_slice()) else {
+            return frame_size_error();
+        };
+
+        let increment = (u32::from_be_bytes(increment) & 0x7fff_ffff) as i64;
+
+        if increment == 0 {
+            return match frame.stream {
+                0 => protocol_error(),
+                id => Ok(self.connection.reset(id, ErrorCode::ProtocolError)?),
+            };
+        }
+
+        let mut state = self.connection.lock();
+
+        let window = match frame.stream {
+            0 => Some(&mut state.window),
+            id => state.streams.get_mut(&id),
+        };
+
+        // Updates for streams that are already answered are ignored.
+        let overflowed = window.is_some_and(|window| {
+            *window += increment;
+            *window > MAX_WINDOW
+        });
+
+        drop(state);
+        self.connection.changed.notify_all();
+
+        match (overflowed, frame.stream) {
+            (false, _) => Ok(()),
+            (true, 0) => Err(Failure::Protocol(ErrorCode::FlowControlError)),
+            (true, id) => Ok(self.connection.reset(id, ErrorCode::FlowControlError)?),
+        }
+    }
+}
+
+/// Whether a client is starting HTTP/2 with prior knowledge, rather than sending a request line.
+pub fn is_preface(reader: &mut dyn BufRead) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.starts_with(&PREFACE[..4]))
+}
+
+fn respond(connection: &Connection, id: u32, response: Response) -> io::Result<()> {
+    let (code, headers, body) = response.into_parts();
+
+    let fields = [(":status".to_string(), code.code().to_string())]
+        .into_iter()
+        .chain(
+            headers
+                .iter()
+                .filter(|(name, _)| !is_connection_specific(name))
+                .map(|(name, value)| (name.to_ascii_lowercase(), value.to_string())),
+        )
+        .collect::<Vec<_>>();
+
+    connection.send_headers(id, &fields, body.is_none())?;
+
+    match body {
+        Some(mut body) => connection.send_data(id, &mut body),
+        None => Ok(()),
+    }
+}
+
+fn answer(connection: &Connection, router: &Router, config: &Config, peer: Peer, opened: Opened) {
+    let Opened { id, head, body } = opened;
+    println!("{:-<30}", "");
+
+    let expect = head
+        .headers
+        .get("expect")
+        .is_some_and(|e| e.eq_ignore_ascii_case("100-continue"));
+
+    let mut reader = BufReader::new(body);
+    let mut interim = Interim { connection, id };
+
+    let result = {
+        let mut body = Body::delimited(&mut reader, head.length, config.max_body_size);
+
+        if expect {
+            body.expect_continue(&mut interim);
+        }
+
+        let mut request =
+            Request::from_parts(head.method, head.uri, Protocol::Http2, head.headers, body);
+        request.peer = Some(peer);
+        println!("{request}");
+
+        let response = router.handle(&request);
+        println!("{response}");
+
+        respond(connection, id, response)
+    };
+
+    // A handler may answer without reading the whole body, which the client can stop sending.
+    let result = match reader.get_mut().still_sending() {
+        true => result.and_then(|_| connection.reset(id, ErrorCode::NoError)),
+        false => result,
+    };
+
+    connection.finish(id);
+
+    if let Err(e) = result {
+        eprintln!("{e}");
+    }
+}
+
+/// Serves an HTTP/2 connection until the client closes it, answering each stream on a thread of
+/// its own. `upgrade` is the HTTP/1.1 request that switched to `h2c`, if any.
+pub fn serve(
+    router: &Router,
+    config: &Config,
+    reader: &mut dyn BufRead,
+    writer: Box<dyn Write + Send>,
+    upgrade: Option<Upgrade>,
+    peer: Peer,
+) -> Result<()> {
+    let connection = Connection::new(writer);
+
+    let settings = [
+        (SETTINGS_ENABLE_PUSH, 0),
+        (
+            SETTINGS_MAX_CONCURRENT_STREAMS,
+            MAX_CONCURRENT_STREAMS as u32,
+        ),
+        (SETTINGS_INITIAL_WINDOW_SIZE, STREAM_WINDOW as u32),
+    ]
+    .iter()
+    .flat_map(|(id, value)| [&id.to_be_bytes()[..], &value.to_be_bytes()].concat())
+    .collect::<Vec<_>>();
+
+    connection.send(FrameType::Settings, 0, 0, &settings)?;
+    connection.window_update(0, (CONNECTION_WINDOW - DEFAULT_WINDOW) as u32)?;
+
+    let mut preface = [0; PREFACE.len()];
+    reader.read_exact(&mut preface)?;
+
+    if preface != PREFACE {
+        return Err(Error::Generic(
+            "Failed to parse HTTP/2 connection preface.".into(),
+        ));
+    }
+
+    let connection = &connection;
+
+    thread::scope(|scope| {
+        let mut frames = Frames::new(connection);
+
+        let outcome = (|| {
+            // The upgrade's settings count as the client's first SETTINGS frame, and the 101
+            // response as their acknowledgement.
+            if let Some(upgrade) = upgrade {
+                connection.apply_settings(&upgrade.settings)?;
+                frames.last_stream = 1;
+                let opened = frames.open(1, upgrade.head, true);
+                scope.spawn(move || answer(connection, router, config, peer, opened));
+            }
+
+            while let Some(opened) = frames.next_request(reader)? {
+                scope.spawn(move || answer(connection, router, config, peer, opened));
+            }
+
+            Ok(())
+        })();
+
+        let outcome = match outcome {
+            Err(Failure::Protocol(code)) => {
+                let _ = connection.go_away(frames.last_stream, code);
+                Err(Failure::Protocol(code))
+            }
+            // A quiet client is no error, but it's told why the connection closes all the same.
+            Err(Failure::Idle) => {
+                let _ = connection.go_away(frames.last_stream, ErrorCode::NoError);
+                Ok(())
+            }
+            outcome => outcome,
+        };
+
+        // Handlers still reading a body or waiting for window give up.
+        frames.inbound.clear();
+        connection.close();
+        outcome
+    })
+    .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Collects what the connection sends, for the test to read back.
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Sink {
+        // The frames sent so far, as type, flags, stream and payload.
+        fn frames(&self) -> Vec<(u8, u8, u32, Vec<u8>)> {
+            let mut reader = Cursor::new(self.0.lock().unwrap().clone());
+            let mut frames = Vec::new();
+
+            while let Ok(Some(frame)) = read_frame(&mut reader) {
+                frames.push((frame.kind, frame.flags, frame.stream, frame.payload));
+            }
+
+            frames
+        }
+    }
+
+    /// Yields each scripted read in turn, such as a timeout between frames.
+    struct Scripted(VecDeque<io::Result<Vec<u8>>>);
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                Some(Ok(bytes)) => {
+                    buf[..bytes.len()].copy_from_slice(&bytes);
+                    Ok(bytes.len())
+                }
+                Some(Err(e)) => Err(e),
+                None => Ok(0),
+            }
+        }
+    }
+
+    fn frame(kind: FrameType, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend([kind as u8, flags]);
+        frame.extend(stream.to_be_bytes());
+        frame.extend(payload);
+        frame
+    }
+
+    fn request_block() -> Vec<u8> {
+        Encoder::new().encode([
+            (":method", "POST"),
+            (":scheme", "http"),
+            (":path", "/echo"),
+            (":authority", "localhost"),
+        ])
+    }
+
+    fn error_code<T>(outcome: Outcome<T>) -> Option<ErrorCode> {
+        match outcome {
+            Err(Failure::Protocol(code)) => Some(code),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reads_frames() {
+        let mut bytes = frame(FrameType::Ping, ACK, 0x8000_0003, b"12345678");
+        bytes.extend(frame(FrameType::Data, 0, 1, b"ab"));
+        let mut reader = bytes.as_slice();
+
+        let Ok(Some(ping)) = read_frame(&mut reader) else {
+            panic!("No PING frame.");
+        };
+        assert_eq!(
+            (ping.kind, ping.flags, ping.stream),
+            (FrameType::Ping as u8, ACK, 3)
+        );
+        assert_eq!(ping.payload, b"12345678");
+
+        assert!(matches!(read_frame(&mut reader), Ok(Some(data)) if data.payload == b"ab"));
+        assert!(matches!(read_frame(&mut reader), Ok(None)));
+
+        let oversized = frame(FrameType::Data, 0, 1, &[0; DEFAULT_FRAME_SIZE + 1]);
+        assert_eq!(
+            error_code(read_frame(&mut oversized.as_slice())),
+            Some(ErrorCode::FrameSizeError)
+        );
+
+        let truncated = frame(FrameType::Data, 0, 1, b"abc");
+        assert!(matches!(
+            read_frame(&mut &truncated[..10]),
+            Err(Failure::Io(_))
+        ));
+    }
+
+    #[test]
+    fn strips_padding_and_priority() {
+        let data = Frame {
+            kind: FrameType::Data as u8,
+            flags: PADDED,
+            stream: 1,
+            payload: b"\x02ab\0\0".to_vec(),
+        };
+        assert!(matches!(data.content(), Ok((b"ab", 3))));
+
+        let headers = Frame {
+            kind: FrameType::Headers as u8,
+            flags: PADDED | PRIORITY,
+            stream: 1,
+            payload: b"\x01\0\0\0\0\x10ab\0".to_vec(),
+        };
+        assert!(matches!(headers.content(), Ok((b"ab", 2))));
+
+        let overpadded = Frame {
+            payload: b"\x05ab".to_vec(),
+            ..data
+        };
+        assert_eq!(
+            error_code(overpadded.content()),
+            Some(ErrorCode::ProtocolError)
+        );
+
+        let empty = Frame {
+            payload: Vec::new(),
+            ..overpadded
+        };
+        assert_eq!(error_code(empty.content()), Some(ErrorCode::FrameSizeError));
+    }
+
+    #[test]
+    fn accounts_for_settings_and_window_updates() {
+        let sink = Sink::default();
+        let connection = Connection::new(Box::new(sink.clone()));
+        let mut frames = Frames::new(&connection);
+
+        let mut input = frame(FrameType::Headers, END_HEADERS, 1, &request_block());
+        input.extend(frame(FrameType::Settings, 0, 0, &[0, 4, 0, 0, 0, 100]));
+        input.extend(frame(FrameType::WindowUpdate, 0, 1, &50u32.to_be_bytes()));
+        input.extend(frame(FrameType::WindowUpdate, 0, 0, &10u32.to_be_bytes()));
+        let mut reader = input.as_slice();
+
+        let opened = frames.next_request(&mut reader).ok().flatten().unwrap();
+        assert_eq!(opened.id, 1);
+        assert_eq!(connection.lock().streams[&1], DEFAULT_WINDOW);
+
+        assert!(frames.next_request(&mut reader).ok().unwrap().is_none());
+        {
+            let state = connection.lock();
+            assert_eq!(state.initial_window, 100);
+            assert_eq!(state.streams[&1], 150);
+            assert_eq!(state.window, DEFAULT_WINDOW + 10);
+        }
+        assert!(sink
+            .frames()
+            .contains(&(FrameType::Settings as u8, ACK, 0, Vec::new())));
+
+        // Overflowing a stream's window resets the stream, and the connection's ends it.
+        let overflow = (MAX_WINDOW as u32).to_be_bytes();
+        let input = frame(FrameType::WindowUpdate, 0, 1, &overflow);
+        assert!(frames.next_request(&mut input.as_slice()).is_ok());
+        assert!(!connection.lock().streams.contains_key(&1));
+        assert!(sink.frames().contains(&(
+            FrameType::RstStream as u8,
+            0,
+            1,
+            (ErrorCode::FlowControlError as u32).to_be_bytes().to_vec()
+        )));
+
+        let input = frame(FrameType::WindowUpdate, 0, 0, &overflow);
+        assert_eq!(
+            error_code(frames.next_request(&mut input.as_slice())),
+            Some(ErrorCode::FlowControlError)
+        );
+
+        let input = frame(FrameType::WindowUpdate, 0, 0, &0u32.to_be_bytes());
+        assert_eq!(
+            error_code(frames.next_request(&mut input.as_slice())),
+            Some(ErrorCode::ProtocolError)
+        );
+    }
+
+    #[test]
+    fn holds_request_bodies_to_the_stream_window() {
+        let connection = Connection::new(Box::new(Sink::default()));
+        let mut frames = Frames::new(&connection);
+
+        let input = frame(FrameType::Headers, END_HEADERS, 1, &request_block());
+        let opened = frames.next_request(&mut input.as_slice()).ok().flatten();
+        assert!(opened.is_some());
+
+        let chunk = vec![0; DEFAULT_FRAME_SIZE];
+        let mut input = Vec::new();
+
+        for _ in 0..STREAM_WINDOW as usize / DEFAULT_FRAME_SIZE + 1 {
+            input.extend(frame(FrameType::Data, 0, 1, &chunk));
+        }
+
+        assert_eq!(
+            error_code(frames.next_request(&mut input.as_slice())),
+            Some(ErrorCode::FlowControlError)
+        );
+    }
+
+    #[test]
+    fn refuses_frames_on_streams_in_the_wrong_state() {
+        let connection = Connection::new(Box::new(Sink::default()));
+
+        // Stream 1 has been opened and ended, and no other.
+        let refused = [
+            (frame(FrameType::Data, 0, 0, b"a"), ErrorCode::ProtocolError),
+            (frame(FrameType::Data, 0, 3, b"a"), ErrorCode::ProtocolError),
+            (
+                frame(FrameType::Headers, END_HEADERS, 2, &request_block()),
+                ErrorCode::ProtocolError,
+            ),
+            (
+                frame(FrameType::RstStream, 0, 5, &[0; 4]),
+                ErrorCode::ProtocolError,
+            ),
+            (
+                frame(FrameType::Headers, END_HEADERS | END_STREAM, 1, &[]),
+                ErrorCode::StreamClosed,
+            ),
+            (
+                frame(FrameType::Continuation, END_HEADERS, 1, &[]),
+                ErrorCode::ProtocolError,
+            ),
+            (
+                frame(FrameType::Settings, 0, 1, &[]),
+                ErrorCode::ProtocolError,
+            ),
+            (
+                frame(FrameType::Ping, 0, 0, b"1234"),
+                ErrorCode::FrameSizeError,
+            ),
+        ];
+
+        for (input, code) in refused {
+            let mut frames = Frames {
+                last_stream: 1,
+                ..Frames::new(&connection)
+            };
+
+            assert_eq!(
+                error_code(frames.next_request(&mut input.as_slice())),
+                Some(code),
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn gives_up_on_quiet_clients_unless_answering() {
+        let connection = Connection::new(Box::new(Sink::default()));
+        let mut frames = Frames::new(&connection);
+
+        let timeout = || Err(io::ErrorKind::WouldBlock.into());
+        let mut reader = BufReader::new(Scripted(VecDeque::from([timeout()])));
+        assert!(matches!(
+            frames.next_request(&mut reader),
+            Err(Failure::Idle)
+        ));
+
+        // While a response streams, the client may stay quiet for as long as it likes.
+        connection.lock().streams.insert(1, DEFAULT_WINDOW);
+        let mut reader = BufReader::new(Scripted(VecDeque::from([timeout(), timeout()])));
+        assert!(matches!(frames.next_request(&mut reader), Ok(None)));
+    }
+}
@@ -0,0 +1,53 @@
+use std::io::Read;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{prelude::*, response::ResponseBuilder};
+
+/// JSON bodies are read into memory whole, so they're held to less than other bodies.
+const MAX_JSON_SIZE: u64 = 1 << 20;
+
+impl Content {
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Result<Self> {
+        let body = serde_json::to_vec(value)
+            .map_err(|e| Error::Generic(format!("Failed to serialize JSON: {e}.")))?;
+
+        Ok(Self::from_bytes(MimeType::Json, body))
+    }
+}
+
+impl Response {
+    /// Responds with `value` serialized as `application/json`.
+    pub fn json<T: Serialize + ?Sized>(rq: &Request, code: StatusCode, value: &T) -> Result<Self> {
+        rq.response(code, Some(Content::json(value)?))
+    }
+}
+
+impl ResponseBuilder {
+    pub fn json<T: Serialize + ?Sized>(self, value: &T) -> Result<Self> {
+        Ok(self.content(Content::json(value)?))
+    }
+}
+
+impl Request<'_> {
+    /// Reads a JSON body into `T`, or responds with 415 if the body is of another type, 413 if
+    /// it's over 1 MiB and 400 if it doesn't match `T`.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        let is_json = self
+            .content_type
+            .as_ref()
+            .is_some_and(|ct| ct.mime_type.is_json());
+
+        if !is_json {
+            return Err(Error::Status(StatusCode::UnsupportedMediaType));
+        }
+
+        let mut body = Vec::new();
+        let mut reader = self.body();
+        reader.restrict(MAX_JSON_SIZE);
+        reader.read_to_end(&mut body)?;
+
+        serde_json::from_slice(&body)
+            .map_err(|e| Error::Rejected(StatusCode::BadRequest, format!("Invalid JSON: {e}.")))
+    }
+}
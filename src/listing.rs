@@ -0,0 +1,177 @@
+use std::{cmp::Ordering, fmt, fs, path::Path, time::SystemTime};
+
+use crate::{
+    date::format_http_date,
+    prelude::*,
+    sandbox::Sandbox,
+    url::{percent_decode, percent_encode},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name => write!(f, "name"),
+            Self::Size => write!(f, "size"),
+            Self::Modified => write!(f, "modified"),
+        }
+    }
+}
+
+impl TryFrom<&str> for SortKey {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        match s {
+            "name" => Ok(Self::Name),
+            "size" => Ok(Self::Size),
+            "modified" => Ok(Self::Modified),
+            _ => Err(Error::Generic("Failed to parse sort key.".into())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub entries: Vec<Entry>,
+}
+
+impl Listing {
+    pub fn read(dir: &Path, sandbox: &Sandbox, show_hidden: bool) -> Result<Self> {
+        let mut entries = Vec::new();
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            let file_type = entry.file_type()?;
+
+            if !sandbox.permits(&name, file_type) || (!show_hidden && name.starts_with('.')) {
+                continue;
+            }
+
+            // Symlinks are listed as what they point to, provided that stays in the sandbox.
+            let path = match entry.path().canonicalize() {
+                Ok(path) if !file_type.is_symlink() || sandbox.contains(&path) => path,
+                _ => continue,
+            };
+
+            let Ok(metadata) = fs::metadata(path) else {
+                continue;
+            };
+
+            entries.push(Entry {
+                name,
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Sorts entries by the given key, always listing directories before files.
+    pub fn sort(&mut self, key: SortKey, descending: bool) {
+        self.entries.sort_by(|a, b| {
+            let ordering = match key {
+                SortKey::Name => Ordering::Equal,
+                SortKey::Size => a.size.cmp(&b.size),
+                SortKey::Modified => a.modified.cmp(&b.modified),
+            }
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+
+            let ordering = if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+
+            b.is_dir.cmp(&a.is_dir).then(ordering)
+        });
+    }
+
+    pub fn html(&self, path: &str, parent: bool, key: SortKey, descending: bool) -> String {
+        let base = escape_html(path.trim_end_matches('/'));
+        let title = escape_html(&format!(
+            "Index of {}/",
+            percent_decode(path.trim_end_matches('/'), false)
+        ));
+
+        let header = |column: SortKey| {
+            let order = if column == key && !descending {
+                "desc"
+            } else {
+                "asc"
+            };
+
+            format!("<th><a href=\"?sort={column}&amp;order={order}\">{column}</a></th>")
+        };
+
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+             <body>\n<h1>{title}</h1>\n<table>\n<tr>{}{}{}</tr>\n",
+            header(SortKey::Name),
+            header(SortKey::Size),
+            header(SortKey::Modified),
+        );
+
+        if parent {
+            html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+        }
+
+        for entry in &self.entries {
+            let suffix = if entry.is_dir { "/" } else { "" };
+            let size = if entry.is_dir {
+                "-".into()
+            } else {
+                entry.size.to_string()
+            };
+
+            html.push_str(&format!(
+                "<tr><td><a href=\"{base}/{}{suffix}\">{}{suffix}</a></td><td>{size}</td><td>{}</td></tr>\n",
+                percent_encode(&entry.name),
+                escape_html(&entry.name),
+                entry.modified.map(format_http_date).unwrap_or_default(),
+            ));
+        }
+
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+
+    pub fn json(&self, path: &str) -> String {
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{{\"name\":{},\"type\":\"{}\",\"size\":{},\"modified\":{}}}",
+                    escape_json(&entry.name),
+                    if entry.is_dir { "directory" } else { "file" },
+                    entry.size,
+                    entry
+                        .modified
+                        .map_or("null".into(), |m| escape_json(&format_http_date(m))),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        format!("{{\"path\":{},\"entries\":[{entries}]}}", escape_json(path))
+    }
+}
@@ -1,16 +1,50 @@
 #![allow(dead_code)]
+mod auth;
+mod body;
+mod conditional;
+mod config;
+mod cookie;
+mod cors;
+mod date;
 mod error;
+mod files;
+mod form;
+mod headers;
+mod hpack;
+mod http2;
+#[cfg(feature = "json")]
+mod json;
+mod listing;
+mod mime;
+mod multipart;
 mod prelude;
+mod proxy;
+mod range;
 mod request;
 mod response;
 mod router;
 mod routes;
+mod sandbox;
 mod server;
+mod session;
+mod sse;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod url;
+mod websocket;
 
-use crate::prelude::*;
+use crate::{auth::PasswordHash, config::Config, prelude::*};
 
 fn run() -> Result<()> {
-    server::Server::new()?.run()
+    let config = Config::from_args()?;
+
+    if let Some(password) = &config.hash_password {
+        println!("{}", PasswordHash::new(password)?);
+        return Ok(());
+    }
+
+    server::Server::new(config)?.run()
 }
 
 fn main() {
@@ -0,0 +1,481 @@
+use std::{fmt, path::Path};
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MimeType {
+    PlainText,
+    Html,
+    Css,
+    Csv,
+    JavaScript,
+    Markdown,
+    Json,
+    Xml,
+    Png,
+    Jpeg,
+    Gif,
+    Svg,
+    Webp,
+    Icon,
+    Pdf,
+    Zip,
+    Gzip,
+    Wasm,
+    Mp3,
+    Mp4,
+    Ogg,
+    Wav,
+    Webm,
+    Woff,
+    Woff2,
+    FormUrlEncoded,
+    MultipartFormData,
+    MultipartByteRanges,
+    EventStream,
+    OctetStream,
+    Other(String),
+}
+
+const KNOWN: [MimeType; 30] = [
+    MimeType::PlainText,
+    MimeType::Html,
+    MimeType::Css,
+    MimeType::Csv,
+    MimeType::JavaScript,
+    MimeType::Markdown,
+    MimeType::Json,
+    MimeType::Xml,
+    MimeType::Png,
+    MimeType::Jpeg,
+    MimeType::Gif,
+    MimeType::Svg,
+    MimeType::Webp,
+    MimeType::Icon,
+    MimeType::Pdf,
+    MimeType::Zip,
+    MimeType::Gzip,
+    MimeType::Wasm,
+    MimeType::Mp3,
+    MimeType::Mp4,
+    MimeType::Ogg,
+    MimeType::Wav,
+    MimeType::Webm,
+    MimeType::Woff,
+    MimeType::Woff2,
+    MimeType::FormUrlEncoded,
+    MimeType::MultipartFormData,
+    MimeType::MultipartByteRanges,
+    MimeType::EventStream,
+    MimeType::OctetStream,
+];
+
+const EXTENSIONS: [(&str, MimeType); 33] = [
+    ("txt", MimeType::PlainText),
+    ("log", MimeType::PlainText),
+    ("html", MimeType::Html),
+    ("htm", MimeType::Html),
+    ("css", MimeType::Css),
+    ("csv", MimeType::Csv),
+    ("js", MimeType::JavaScript),
+    ("mjs", MimeType::JavaScript),
+    ("md", MimeType::Markdown),
+    ("json", MimeType::Json),
+    ("xml", MimeType::Xml),
+    ("png", MimeType::Png),
+    ("jpg", MimeType::Jpeg),
+    ("jpeg", MimeType::Jpeg),
+    ("gif", MimeType::Gif),
+    ("svg", MimeType::Svg),
+    ("webp", MimeType::Webp),
+    ("ico", MimeType::Icon),
+    ("pdf", MimeType::Pdf),
+    ("zip", MimeType::Zip),
+    ("gz", MimeType::Gzip),
+    ("wasm", MimeType::Wasm),
+    ("mp3", MimeType::Mp3),
+    ("mp4", MimeType::Mp4),
+    ("m4v", MimeType::Mp4),
+    ("ogg", MimeType::Ogg),
+    ("oga", MimeType::Ogg),
+    ("wav", MimeType::Wav),
+    ("webm", MimeType::Webm),
+    ("woff", MimeType::Woff),
+    ("woff2", MimeType::Woff2),
+    ("bin", MimeType::OctetStream),
+    ("exe", MimeType::OctetStream),
+];
+
+impl MimeType {
+    pub fn essence(&self) -> &str {
+        match self {
+            Self::PlainText => "text/plain",
+            Self::Html => "text/html",
+            Self::Css => "text/css",
+            Self::Csv => "text/csv",
+            Self::JavaScript => "text/javascript",
+            Self::Markdown => "text/markdown",
+            Self::Json => "application/json",
+            Self::Xml => "application/xml",
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Gif => "image/gif",
+            Self::Svg => "image/svg+xml",
+            Self::Webp => "image/webp",
+            Self::Icon => "image/x-icon",
+            Self::Pdf => "application/pdf",
+            Self::Zip => "application/zip",
+            Self::Gzip => "application/gzip",
+            Self::Wasm => "application/wasm",
+            Self::Mp3 => "audio/mpeg",
+            Self::Mp4 => "video/mp4",
+            Self::Ogg => "audio/ogg",
+            Self::Wav => "audio/wav",
+            Self::Webm => "video/webm",
+            Self::Woff => "font/woff",
+            Self::Woff2 => "font/woff2",
+            Self::FormUrlEncoded => "application/x-www-form-urlencoded",
+            Self::MultipartFormData => "multipart/form-data",
+            Self::MultipartByteRanges => "multipart/byteranges",
+            Self::EventStream => "text/event-stream",
+            Self::OctetStream => "application/octet-stream",
+            Self::Other(s) => s,
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.essence().starts_with("text/")
+            || matches!(
+                self,
+                Self::Json | Self::Xml | Self::Svg | Self::FormUrlEncoded
+            )
+    }
+
+    /// Whether this is JSON, including structured syntax types such as `application/ld+json`.
+    pub fn is_json(&self) -> bool {
+        matches!(self, Self::Json) || self.essence().ends_with("+json")
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        let extension = extension.to_ascii_lowercase();
+
+        EXTENSIONS
+            .iter()
+            .find(|(ext, _)| *ext == extension)
+            .map(|(_, mime_type)| mime_type.clone())
+    }
+
+    pub fn from_path(path: &Path) -> Self {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::from_extension)
+            .unwrap_or(Self::OctetStream)
+    }
+}
+
+impl fmt::Display for MimeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.essence())
+    }
+}
+
+impl TryFrom<&str> for MimeType {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        let essence = s.trim().to_ascii_lowercase();
+
+        match essence.split_once('/') {
+            Some((t, st)) if !t.is_empty() && !st.is_empty() && !st.contains('/') => {}
+            _ => return Err(Error::Generic("Failed to parse mime type.".into())),
+        }
+
+        Ok(KNOWN
+            .iter()
+            .find(|mime_type| mime_type.essence() == essence)
+            .cloned()
+            .unwrap_or(Self::Other(essence)))
+    }
+}
+
+impl TryFrom<&String> for MimeType {
+    type Error = Error;
+
+    fn try_from(s: &String) -> Result<Self> {
+        Self::try_from(s.as_str())
+    }
+}
+
+/// A mime type together with its parameters, as found in a `Content-Type` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    pub mime_type: MimeType,
+    pub params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    pub fn new(mime_type: MimeType) -> Self {
+        Self {
+            mime_type,
+            params: Vec::new(),
+        }
+    }
+
+    pub fn with_param(mut self, key: &str, value: &str) -> Self {
+        let key = key.to_ascii_lowercase();
+        self.params.retain(|(k, _)| *k != key);
+        self.params.push((key, value.into()));
+        self
+    }
+
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+
+    pub fn boundary(&self) -> Option<&str> {
+        self.param("boundary")
+    }
+}
+
+impl From<MimeType> for ContentType {
+    fn from(mime_type: MimeType) -> Self {
+        Self::new(mime_type)
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mime_type)?;
+
+        for (k, v) in &self.params {
+            let is_token = !v.is_empty()
+                && v.bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+
+            if is_token {
+                write!(f, "; {k}={v}")?;
+            } else {
+                write!(
+                    f,
+                    "; {k}=\"{}\"",
+                    v.replace('\\', "\\\\").replace('"', "\\\"")
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&str> for ContentType {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        let (essence, mut rest) = s.split_once(';').unwrap_or((s, ""));
+        let mut content_type = Self::new(MimeType::try_from(essence)?);
+
+        while let Some((key, value)) = rest.split_once('=') {
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim_start();
+
+            let (value, remainder) = if let Some(quoted) = value.strip_prefix('"') {
+                let mut unquoted = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => unquoted.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        c => unquoted.push(c),
+                    }
+                }
+
+                let remainder = &quoted[end..];
+                (unquoted, remainder.split_once(';').map_or("", |(_, r)| r))
+            } else {
+                let (value, remainder) = value.split_once(';').unwrap_or((value, ""));
+                (value.trim().to_string(), remainder)
+            };
+
+            if !key.is_empty() {
+                content_type.params.push((key, value));
+            }
+
+            rest = remainder;
+        }
+
+        Ok(content_type)
+    }
+}
+
+impl TryFrom<&String> for ContentType {
+    type Error = Error;
+
+    fn try_from(s: &String) -> Result<Self> {
+        Self::try_from(s.as_str())
+    }
+}
+
+/// A single entry of an `Accept` header, such as `text/*;q=0.8`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    pub kind: String,
+    pub subtype: String,
+    pub params: Vec<(String, String)>,
+    pub quality: f32,
+}
+
+impl MediaRange {
+    /// How closely this range matches a mime type, or `None` if it does not match at all.
+    pub fn specificity(&self, content_type: &ContentType) -> Option<u8> {
+        let (kind, subtype) = content_type
+            .mime_type
+            .essence()
+            .split_once('/')
+            .unwrap_or_default();
+
+        let params_match = self.params.iter().all(|(k, v)| {
+            content_type
+                .param(k)
+                .is_some_and(|p| p.eq_ignore_ascii_case(v))
+        });
+
+        match (self.kind.as_str(), self.subtype.as_str()) {
+            ("*", "*") => Some(0),
+            (k, "*") if k == kind => Some(1),
+            (k, s) if k == kind && s == subtype && params_match => {
+                Some(if self.params.is_empty() { 2 } else { 3 })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<&str> for MediaRange {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        let mut parts = s.split(';');
+        let essence = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+
+        let (kind, subtype) = match essence.split_once('/') {
+            Some((k, s)) if !k.is_empty() && !s.is_empty() && (k != "*" || s == "*") => (k, s),
+            _ => return Err(Error::Generic("Failed to parse media range.".into())),
+        };
+
+        let mut range = Self {
+            kind: kind.into(),
+            subtype: subtype.into(),
+            params: Vec::new(),
+            quality: 1.0,
+        };
+
+        for param in parts {
+            let Some((k, v)) = param.split_once('=') else {
+                continue;
+            };
+
+            let (k, v) = (k.trim().to_ascii_lowercase(), v.trim().trim_matches('"'));
+
+            if k == "q" {
+                range.quality = v
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|q| (0.0..=1.0).contains(q))
+                    .ok_or(Error::Generic("Failed to parse quality value.".into()))?;
+
+                // Anything after the quality value is an accept extension, not a media type parameter.
+                break;
+            }
+
+            range.params.push((k, v.into()));
+        }
+
+        Ok(range)
+    }
+}
+
+/// The parsed value of an `Accept` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accept {
+    pub ranges: Vec<MediaRange>,
+}
+
+impl Accept {
+    /// Accepts everything, which is how a missing `Accept` header is to be interpreted.
+    pub fn any() -> Self {
+        Self {
+            ranges: vec![MediaRange {
+                kind: "*".into(),
+                subtype: "*".into(),
+                params: Vec::new(),
+                quality: 1.0,
+            }],
+        }
+    }
+
+    /// The quality the client assigned to a content type, taken from the most specific matching range.
+    pub fn quality(&self, content_type: &ContentType) -> f32 {
+        self.ranges
+            .iter()
+            .filter_map(|r| r.specificity(content_type).map(|s| (s, r.quality)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map_or(0.0, |(_, q)| q)
+    }
+
+    /// Picks the offer with the highest quality, preferring earlier offers on ties.
+    pub fn best<'a, T>(
+        &self,
+        offers: &'a [T],
+        content_type: impl Fn(&T) -> &ContentType,
+    ) -> Option<&'a T> {
+        let mut best = None;
+
+        for offer in offers {
+            let quality = self.quality(content_type(offer));
+
+            if quality > 0.0 && best.map_or(true, |(q, _)| quality > q) {
+                best = Some((quality, offer));
+            }
+        }
+
+        best.map(|(_, offer)| offer)
+    }
+}
+
+impl TryFrom<&str> for Accept {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        let ranges = s
+            .split(',')
+            .filter(|r| !r.trim().is_empty())
+            .map(MediaRange::try_from)
+            .collect::<Result<Vec<_>>>()?;
+
+        if ranges.is_empty() {
+            Ok(Self::any())
+        } else {
+            Ok(Self { ranges })
+        }
+    }
+}
+
+impl TryFrom<&String> for Accept {
+    type Error = Error;
+
+    fn try_from(s: &String) -> Result<Self> {
+        Self::try_from(s.as_str())
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    cell::RefMut,
+    error, fmt,
+    io::{self, Read},
+};
+
+use crate::{
+    body::{Body, BodyTooLarge},
+    prelude::*,
+};
+
+const MAX_HEADER_SIZE: usize = 8192;
+const READ_SIZE: usize = 8192;
+
+/// Raised through `io::Error` when a multipart body breaks off or doesn't follow the format.
+#[derive(Debug)]
+pub struct Malformed(&'static str);
+
+impl error::Error for Malformed {}
+
+impl fmt::Display for Malformed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub part_size: u64,
+    pub total_size: u64,
+    pub parts: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            part_size: 1 << 30,
+            total_size: 1 << 30,
+            parts: 128,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Headers,
+    Data,
+    Finished,
+}
+
+/// A streaming `multipart/form-data` parser over a request body.
+pub struct Multipart<'r, 'a> {
+    body: RefMut<'r, Body<'a>>,
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    state: State,
+    limits: Limits,
+    parts: usize,
+    part_size: u64,
+    total_size: u64,
+}
+
+/// One part of a multipart body. Reading it yields the part's data up to the next boundary.
+pub struct Part<'m, 'r, 'a> {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: ContentType,
+    multipart: &'m mut Multipart<'r, 'a>,
+}
+
+impl Part<'_, '_, '_> {
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    /// Reads a small part, such as a plain form field, into a string.
+    pub fn text(&mut self) -> Result<String> {
+        let mut buffer = Vec::new();
+        self.read_to_end(&mut buffer)?;
+        String::from_utf8(buffer).map_err(|_| Error::Status(StatusCode::BadRequest))
+    }
+}
+
+impl Read for Part<'_, '_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.multipart.read_data(buf)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Malformed(msg))
+}
+
+/// Splits a header parameter list such as `form-data; name="a"; filename="b.txt"`.
+fn parse_params(s: &str) -> Vec<(String, String)> {
+    ContentType::try_from(format!("x/{s}").as_str())
+        .map(|ct| ct.params)
+        .unwrap_or_default()
+}
+
+impl<'r, 'a> Multipart<'r, 'a> {
+    pub fn new(body: RefMut<'r, Body<'a>>, boundary: &str, limits: Limits) -> Self {
+        Self {
+            body,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            // The first delimiter need not be preceded by a line break, so pretend it is.
+            buffer: b"\r\n".to_vec(),
+            state: State::Preamble,
+            limits,
+            parts: 0,
+            part_size: 0,
+            total_size: 0,
+        }
+    }
+
+    // Reads more of the body into the buffer, returning false at the end of the body.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; READ_SIZE];
+        // A body cut short, or framed wrongly, is as malformed as the parts in it.
+        let n = self.body.read(&mut chunk).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData => {
+                invalid("Multipart body ended unexpectedly.")
+            }
+            _ => e,
+        })?;
+        self.buffer.extend_from_slice(&chunk[..n]);
+        Ok(n > 0)
+    }
+
+    // Reads part data up to the next delimiter, which is consumed once reached.
+    fn read_data(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.state != State::Data || out.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let available = match find(&self.buffer, &self.delimiter) {
+                Some(0) => {
+                    self.buffer.drain(..self.delimiter.len());
+                    self.state = State::Headers;
+                    return Ok(0);
+                }
+                Some(i) => i,
+                // Hold back anything that could be the start of a delimiter.
+                None => self.buffer.len().saturating_sub(self.delimiter.len() - 1),
+            };
+
+            if available > 0 {
+                let n = available.min(out.len());
+                out[..n].copy_from_slice(&self.buffer[..n]);
+                self.buffer.drain(..n);
+
+                self.part_size += n as u64;
+                self.total_size += n as u64;
+
+                if self.part_size > self.limits.part_size
+                    || self.total_size > self.limits.total_size
+                {
+                    return Err(io::Error::other(BodyTooLarge));
+                }
+
+                return Ok(n);
+            }
+
+            if !self.fill()? {
+                return Err(invalid("Multipart body ended unexpectedly."));
+            }
+        }
+    }
+
+    // Skips the preamble or the rest of the current part, leaving the buffer just after a
+    // delimiter.
+    fn skip_to_delimiter(&mut self) -> io::Result<()> {
+        match self.state {
+            State::Preamble => loop {
+                if let Some(i) = find(&self.buffer, &self.delimiter) {
+                    self.buffer.drain(..i + self.delimiter.len());
+                    self.state = State::Headers;
+                    return Ok(());
+                }
+
+                let keep = self.delimiter.len() - 1;
+                let discard = self.buffer.len().saturating_sub(keep);
+                self.buffer.drain(..discard);
+
+                if !self.fill()? {
+                    return Err(invalid("Multipart body has no boundary."));
+                }
+            },
+            State::Data => {
+                let mut sink = [0; READ_SIZE];
+                while self.read_data(&mut sink)? > 0 {}
+                Ok(())
+            }
+            State::Headers | State::Finished => Ok(()),
+        }
+    }
+
+    fn read_headers(&mut self) -> Result<Option<Vec<(String, String)>>> {
+        // After a delimiter comes either `--` for the end of the body, or a line break.
+        while self.buffer.len() < 2 {
+            if !self.fill()? {
+                return Err(invalid("Multipart body ended unexpectedly.").into());
+            }
+        }
+
+        if self.buffer.starts_with(b"--") {
+            self.state = State::Finished;
+            return Ok(None);
+        }
+
+        let end = loop {
+            match find(&self.buffer, b"\r\n\r\n") {
+                Some(i) if i <= MAX_HEADER_SIZE => break i,
+                // The end may have come in with the read that went over the limit.
+                Some(_) => return Err(Error::Status(StatusCode::BadRequest)),
+                None => {}
+            }
+
+            if self.buffer.len() > MAX_HEADER_SIZE {
+                return Err(Error::Status(StatusCode::BadRequest));
+            }
+
+            if !self.fill()? {
+                return Err(invalid("Multipart body ended unexpectedly.").into());
+            }
+        };
+
+        let head = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+        self.buffer.drain(..end + 4);
+
+        let headers = head
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+            .collect();
+
+        Ok(Some(headers))
+    }
+
+    /// Advances to the next part, discarding whatever is left of the current one.
+    pub fn next_part(&mut self) -> Result<Option<Part<'_, 'r, 'a>>> {
+        if self.state == State::Finished {
+            return Ok(None);
+        }
+
+        self.skip_to_delimiter()?;
+
+        let Some(headers) = self.read_headers()? else {
+            return Ok(None);
+        };
+
+        self.parts += 1;
+
+        if self.parts > self.limits.parts {
+            return Err(Error::Status(StatusCode::PayloadTooLarge));
+        }
+
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        };
+
+        let disposition = header("content-disposition")
+            .filter(|d| d.to_ascii_lowercase().starts_with("form-data"))
+            .ok_or(Error::Status(StatusCode::BadRequest))?;
+
+        let params = parse_params(disposition);
+        let param = |key: &str| {
+            params
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        };
+
+        let content_type = header("content-type")
+            .and_then(|ct| ContentType::try_from(ct).ok())
+            .unwrap_or(MimeType::PlainText.into());
+
+        self.state = State::Data;
+        self.part_size = 0;
+
+        Ok(Some(Part {
+            name: param("name").unwrap_or_default(),
+            filename: param("filename"),
+            content_type,
+            multipart: self,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    type Parts = Vec<(String, Option<String>, String, Vec<u8>)>;
+
+    // Reads every part as (name, filename, content type, data).
+    fn parse(raw: &[u8], limits: Limits) -> Result<Parts> {
+        let mut reader = raw;
+        let body = RefCell::new(Body::new(
+            &mut reader,
+            false,
+            Some(raw.len() as u64),
+            1 << 20,
+        ));
+        let mut multipart = Multipart::new(body.borrow_mut(), "XyZ", limits);
+        let mut parts = Vec::new();
+
+        while let Some(mut part) = multipart.next_part()? {
+            let mut data = Vec::new();
+            part.read_to_end(&mut data)?;
+            parts.push((
+                part.name.clone(),
+                part.filename.clone(),
+                part.content_type.to_string(),
+                data,
+            ));
+        }
+
+        Ok(parts)
+    }
+
+    // The status a request would be refused with.
+    fn status<T>(result: Result<T>) -> Option<u16> {
+        match result {
+            Err(Error::Status(code)) => Some(code.code()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_parts() {
+        let raw = b"preamble\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            Hello\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+            Content-Type: text/html\r\n\
+            \r\n\
+            one\r\n--Xy two\r\n\
+            --XyZ--\r\n\
+            epilogue";
+
+        assert_eq!(
+            parse(raw, Limits::default()).unwrap(),
+            vec![
+                ("title".into(), None, "text/plain".into(), b"Hello".to_vec()),
+                (
+                    "file".into(),
+                    Some("a.txt".into()),
+                    "text/html".into(),
+                    b"one\r\n--Xy two".to_vec()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_a_body_without_parts() {
+        assert_eq!(parse(b"--XyZ--\r\n", Limits::default()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn finds_delimiters_across_reads() {
+        let data = vec![b'x'; READ_SIZE * 2 + 3];
+        let raw = [
+            &b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n"[..],
+            &data,
+            b"\r\n--XyZ--",
+        ]
+        .concat();
+
+        let parts = parse(&raw, Limits::default()).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].3, data);
+    }
+
+    #[test]
+    fn skips_unread_parts() {
+        let raw = b"--XyZ\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+            skipped\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"b\"\r\n\r\n\
+            read\r\n\
+            --XyZ--";
+
+        let mut reader = &raw[..];
+        let body = RefCell::new(Body::new(&mut reader, false, Some(raw.len() as u64), 1024));
+        let mut multipart = Multipart::new(body.borrow_mut(), "XyZ", Limits::default());
+
+        assert_eq!(multipart.next_part().unwrap().unwrap().name, "a");
+
+        let mut part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.name, "b");
+        assert_eq!(part.text().unwrap(), "read");
+
+        assert!(multipart.next_part().unwrap().is_none());
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_truncated_bodies() {
+        for raw in [
+            &b""[..],
+            b"no boundary at all",
+            b"--XyZ",
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"",
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\ndata",
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\ndata\r\n--XyZ",
+        ] {
+            assert_eq!(status(parse(raw, Limits::default())), Some(400), "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn refuses_a_body_cut_short_of_its_length() {
+        let raw = b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\ndata";
+        let mut reader = &raw[..];
+        let body = RefCell::new(Body::new(&mut reader, false, Some(1000), 1024));
+        let mut multipart = Multipart::new(body.borrow_mut(), "XyZ", Limits::default());
+
+        let mut part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(status(part.text()), Some(400));
+    }
+
+    #[test]
+    fn refuses_malformed_parts() {
+        let long_headers = format!(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\nx\r\n--XyZ--",
+            "a".repeat(MAX_HEADER_SIZE)
+        );
+
+        for raw in [
+            &b"--XyZ\r\nContent-Type: text/plain\r\n\r\nx\r\n--XyZ--"[..],
+            b"--XyZ\r\nContent-Disposition: inline\r\n\r\nx\r\n--XyZ--",
+            long_headers.as_bytes(),
+        ] {
+            assert_eq!(status(parse(raw, Limits::default())), Some(400));
+        }
+    }
+
+    #[test]
+    fn refuses_text_that_is_not_utf8() {
+        let raw = b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n\xff\r\n--XyZ--";
+        let mut reader = &raw[..];
+        let body = RefCell::new(Body::new(&mut reader, false, Some(raw.len() as u64), 1024));
+        let mut multipart = Multipart::new(body.borrow_mut(), "XyZ", Limits::default());
+
+        let mut part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(status(part.text()), Some(400));
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let raw = b"--XyZ\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+            12345\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"b\"\r\n\r\n\
+            12345\r\n\
+            --XyZ--";
+
+        let limits = |part_size, total_size, parts| Limits {
+            part_size,
+            total_size,
+            parts,
+        };
+
+        assert!(parse(raw, limits(5, 10, 2)).is_ok());
+        assert_eq!(status(parse(raw, limits(4, 10, 2))), Some(413));
+        assert_eq!(status(parse(raw, limits(5, 9, 2))), Some(413));
+        assert_eq!(status(parse(raw, limits(5, 10, 1))), Some(413));
+    }
+}
@@ -1,18 +1,39 @@
 use std::fmt;
 
-pub use crate::{error::Error, request::Request, response::Response};
+pub use crate::{
+    error::Error,
+    mime::{Accept, ContentType, MimeType},
+    request::Request,
+    response::Response,
+};
 
 pub type Result<T> = core::result::Result<T, Error>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Protocol {
-    Http1,
+    Http10,
+    Http11,
+    Http2,
+}
+
+impl Protocol {
+    /// Whether connections stay open after a response unless either side says otherwise.
+    pub fn is_persistent(&self) -> bool {
+        matches!(self, Self::Http11 | Self::Http2)
+    }
+
+    /// Whether bodies may be sent with `Transfer-Encoding: chunked`.
+    pub fn supports_chunked(&self) -> bool {
+        matches!(self, Self::Http11)
+    }
 }
 
 impl fmt::Display for Protocol {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Http1 => write!(f, "HTTP/1.1"),
+            Self::Http10 => write!(f, "HTTP/1.0"),
+            Self::Http11 => write!(f, "HTTP/1.1"),
+            Self::Http2 => write!(f, "HTTP/2"),
         }
     }
 }
@@ -22,7 +43,8 @@ impl TryFrom<&str> for Protocol {
 
     fn try_from(s: &str) -> Result<Self> {
         match s {
-            "HTTP/1.1" => Ok(Self::Http1),
+            "HTTP/1.0" => Ok(Self::Http10),
+            "HTTP/1.1" => Ok(Self::Http11),
             _ => Err(Error::Generic("Failed to parse HTTP version.".into())),
         }
     }
@@ -30,40 +52,144 @@ impl TryFrom<&str> for Protocol {
 
 #[derive(Debug, Clone)]
 pub enum StatusCode {
-    Ok = 200,
-    Created = 201,
-    Forbidden = 403,
-    NotFound = 404,
-    MethodNotAllowed = 405,
-    InternalError = 500,
+    Continue,
+    SwitchingProtocols,
+    Ok,
+    Created,
+    Accepted,
+    NoContent,
+    ResetContent,
+    PartialContent,
+    MultipleChoices,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
+    Conflict,
+    Gone,
+    LengthRequired,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    UnprocessableContent,
+    UpgradeRequired,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    /// A code as given, such as by an upstream server, along with its reason phrase.
+    Other(u16, String),
+}
+
+impl StatusCode {
+    /// The numeric code and the reason phrase that goes with it.
+    fn parts(&self) -> (u16, &str) {
+        match self {
+            Self::Continue => (100, "Continue"),
+            Self::SwitchingProtocols => (101, "Switching Protocols"),
+            Self::Ok => (200, "OK"),
+            Self::Created => (201, "Created"),
+            Self::Accepted => (202, "Accepted"),
+            Self::NoContent => (204, "No Content"),
+            Self::ResetContent => (205, "Reset Content"),
+            Self::PartialContent => (206, "Partial Content"),
+            Self::MultipleChoices => (300, "Multiple Choices"),
+            Self::MovedPermanently => (301, "Moved Permanently"),
+            Self::Found => (302, "Found"),
+            Self::SeeOther => (303, "See Other"),
+            Self::NotModified => (304, "Not Modified"),
+            Self::TemporaryRedirect => (307, "Temporary Redirect"),
+            Self::PermanentRedirect => (308, "Permanent Redirect"),
+            Self::BadRequest => (400, "Bad Request"),
+            Self::Unauthorized => (401, "Unauthorized"),
+            Self::Forbidden => (403, "Forbidden"),
+            Self::NotFound => (404, "Not Found"),
+            Self::MethodNotAllowed => (405, "Method Not Allowed"),
+            Self::NotAcceptable => (406, "Not Acceptable"),
+            Self::RequestTimeout => (408, "Request Timeout"),
+            Self::Conflict => (409, "Conflict"),
+            Self::Gone => (410, "Gone"),
+            Self::LengthRequired => (411, "Length Required"),
+            Self::PreconditionFailed => (412, "Precondition Failed"),
+            Self::PayloadTooLarge => (413, "Payload Too Large"),
+            Self::UriTooLong => (414, "URI Too Long"),
+            Self::UnsupportedMediaType => (415, "Unsupported Media Type"),
+            Self::RangeNotSatisfiable => (416, "Range Not Satisfiable"),
+            Self::ExpectationFailed => (417, "Expectation Failed"),
+            Self::UnprocessableContent => (422, "Unprocessable Content"),
+            Self::UpgradeRequired => (426, "Upgrade Required"),
+            Self::TooManyRequests => (429, "Too Many Requests"),
+            Self::RequestHeaderFieldsTooLarge => (431, "Request Header Fields Too Large"),
+            Self::InternalError => (500, "Internal Error"),
+            Self::NotImplemented => (501, "Not Implemented"),
+            Self::BadGateway => (502, "Bad Gateway"),
+            Self::ServiceUnavailable => (503, "Service Unavailable"),
+            Self::GatewayTimeout => (504, "Gateway Timeout"),
+            Self::Other(code, reason) => (*code, reason),
+        }
+    }
+
+    pub fn code(&self) -> u16 {
+        self.parts().0
+    }
 }
 
 impl fmt::Display for StatusCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let msg = match self {
-            Self::Ok => "OK",
-            Self::Created => "Created",
-            Self::Forbidden => "Forbidden",
-            Self::NotFound => "Not Found",
-            Self::MethodNotAllowed => "Method Not Allowed",
-            Self::InternalError => "Internal Error",
-        };
-
-        write!(f, "{} {msg}", self.clone() as i32)
+        let (code, reason) = self.parts();
+        write!(f, "{code} {reason}")
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Method {
     Get,
+    Head,
     Post,
+    Put,
+    Patch,
+    Delete,
+    Options,
+}
+
+impl Method {
+    /// Whether the method only reads, as `GET` does and `HEAD` does without the body.
+    pub fn is_read(&self) -> bool {
+        matches!(self, Self::Get | Self::Head)
+    }
+
+    /// Whether a request with `method` is handled as one with this method, as `HEAD` is wherever
+    /// `GET` is.
+    pub fn admits(&self, method: &Method) -> bool {
+        self == method || (*self == Self::Get && *method == Self::Head)
+    }
 }
 
 impl fmt::Display for Method {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Get => write!(f, "GET"),
+            Self::Head => write!(f, "HEAD"),
             Self::Post => write!(f, "POST"),
+            Self::Put => write!(f, "PUT"),
+            Self::Patch => write!(f, "PATCH"),
+            Self::Delete => write!(f, "DELETE"),
+            Self::Options => write!(f, "OPTIONS"),
         }
     }
 }
@@ -74,59 +200,26 @@ impl TryFrom<&str> for Method {
     fn try_from(s: &str) -> Result<Self> {
         match s {
             "GET" => Ok(Self::Get),
+            "HEAD" => Ok(Self::Head),
             "POST" => Ok(Self::Post),
+            "PUT" => Ok(Self::Put),
+            "PATCH" => Ok(Self::Patch),
+            "DELETE" => Ok(Self::Delete),
+            "OPTIONS" => Ok(Self::Options),
             _ => Err(Error::Generic("Failed to parse request method.".into())),
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum MimeType {
-    PlainText,
-    Html,
-    OctetStream,
-}
-
-impl fmt::Display for MimeType {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::PlainText => write!(f, "text/plain"),
-            Self::Html => write!(f, "text/html"),
-            Self::OctetStream => write!(f, "application/octet-stream"),
-        }
-    }
-}
-
-impl TryFrom<&str> for MimeType {
-    type Error = Error;
-
-    fn try_from(s: &str) -> Result<Self> {
-        match s {
-            "text/plain" => Ok(Self::PlainText),
-            "text/html" => Ok(Self::Html),
-            "application/octet-stream" => Ok(Self::OctetStream),
-            _ => Err(Error::Generic("Failed to parse mime type.".into())),
-        }
-    }
-}
-
-impl TryFrom<&String> for MimeType {
-    type Error = Error;
-
-    fn try_from(s: &String) -> Result<Self> {
-        Self::try_from(s.as_str())
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct Content {
-    pub mime_type: MimeType,
-    pub body: String,
+    pub content_type: ContentType,
+    pub body: Vec<u8>,
 }
 
 impl fmt::Display for Content {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Content({}, {} B)", self.mime_type, self.body.len())
+        write!(f, "Content({}, {} B)", self.content_type, self.body.len())
     }
 }
 
@@ -134,18 +227,22 @@ impl From<&Content> for String {
     fn from(content: &Content) -> Self {
         format!(
             "Content-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
-            content.mime_type,
+            content.content_type,
             content.body.len(),
-            content.body
+            String::from_utf8_lossy(&content.body)
         )
     }
 }
 
 impl Content {
-    pub fn new(mime_type: MimeType, body: &str) -> Self {
+    pub fn new(content_type: impl Into<ContentType>, body: &str) -> Self {
+        Self::from_bytes(content_type, body.as_bytes().to_vec())
+    }
+
+    pub fn from_bytes(content_type: impl Into<ContentType>, body: Vec<u8>) -> Self {
         Self {
-            mime_type,
-            body: body.into(),
+            content_type: content_type.into(),
+            body,
         }
     }
 }
@@ -181,3 +278,40 @@ impl TryFrom<&String> for Encoding {
         Self::try_from(s.as_str())
     }
 }
+
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+pub fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
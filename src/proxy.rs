@@ -0,0 +1,526 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{
+    body::Framed, headers::Headers, prelude::*, response::ChunkedWriter, router::Context, url,
+};
+
+/// How this server names itself in `Via`.
+const PSEUDONYM: &str = env!("CARGO_PKG_NAME");
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_HEAD_SIZE: u64 = 1 << 16;
+
+/// Requests under a URL prefix, forwarded to an upstream HTTP/1.1 server.
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    pub prefix: String,
+    pub host: String,
+    pub port: u16,
+    /// What the prefix is replaced with upstream, without a trailing slash.
+    pub path: String,
+    /// How long connecting, or waiting on the upstream for anything, may take.
+    pub timeout: Duration,
+}
+
+impl Proxy {
+    /// Parses a `--proxy` value of the form `PREFIX=http://HOST[:PORT][/PATH][,OPTION...]`,
+    /// where the only option is `timeout=SECONDS`.
+    pub fn parse(s: &str) -> Result<Self> {
+        let (prefix, rest) = s
+            .split_once('=')
+            .ok_or(Error::Generic(format!("Failed to parse proxy '{s}'.")))?;
+
+        let mut options = rest.split(',');
+        let upstream = options
+            .next()
+            .and_then(|upstream| upstream.strip_prefix("http://"))
+            .ok_or(Error::Generic(format!(
+                "Proxy '{s}' needs an http:// upstream."
+            )))?;
+
+        let (authority, path) = upstream.split_at(upstream.find('/').unwrap_or(upstream.len()));
+
+        // Bracketed IPv6 addresses have colons of their own.
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (host, port.parse::<u16>()?),
+            _ => (authority, 80),
+        };
+
+        if host.is_empty() {
+            return Err(Error::Generic(format!("Proxy '{s}' has no upstream host.")));
+        }
+
+        let mut proxy = Self {
+            prefix: format!("/{}", prefix.trim_matches('/')),
+            host: host.into(),
+            port,
+            path: path.trim_end_matches('/').into(),
+            timeout: DEFAULT_TIMEOUT,
+        };
+
+        for option in options {
+            match option.split_once('=').unwrap_or((option, "")) {
+                ("timeout", secs) => proxy.timeout = Duration::from_secs(secs.parse::<u64>()?),
+                _ => {
+                    return Err(Error::Generic(format!(
+                        "Unknown option '{option}' for proxy '{s}'."
+                    )))
+                }
+            }
+        }
+
+        Ok(proxy)
+    }
+
+    /// The upstream as named in `Host`, which leaves out the default port.
+    fn authority(&self) -> String {
+        match self.port {
+            80 => self.host.clone(),
+            port => format!("{}:{port}", self.host),
+        }
+    }
+
+    // The upstream's request target: the prefix swapped for the upstream's path, followed by
+    // the rest of the path as the router resolved it.
+    fn target(&self, rq: &Request, cx: &Context) -> Result<String> {
+        let segments = cx
+            .get("path")
+            .map_or("", String::as_str)
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+
+        // Upstreams resolve dot-segments themselves, which could lead out of the path.
+        if segments
+            .iter()
+            .any(|segment| matches!(*segment, "." | ".."))
+        {
+            return Err(Error::Status(StatusCode::BadRequest));
+        }
+
+        let mut target = self.path.clone();
+
+        for segment in segments {
+            target.push('/');
+            target.push_str(&url::percent_encode(segment));
+        }
+
+        if target.is_empty() || (rq.path().ends_with('/') && !target.ends_with('/')) {
+            target.push('/');
+        }
+
+        Ok(match rq.uri.split_once('?') {
+            Some((_, query)) => format!("{target}?{query}"),
+            None => target,
+        })
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last = io::Error::new(io::ErrorKind::NotFound, "Upstream has no address.");
+
+        for addr in (self.host.trim_matches(['[', ']']), self.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(e) => last = e,
+            }
+        }
+
+        Err(last)
+    }
+}
+
+// Fields that only concern a single connection, which a proxy mustn't pass on, along with any
+// the message's `Connection` field names.
+fn is_hop_by_hop(name: &str, headers: &Headers) -> bool {
+    let listed = headers
+        .get_all("Connection")
+        .flat_map(|h| h.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case(name));
+
+    listed
+        || matches!(
+            name.to_ascii_lowercase().as_str(),
+            "connection"
+                | "keep-alive"
+                | "proxy-connection"
+                | "proxy-authenticate"
+                | "proxy-authorization"
+                | "te"
+                | "trailer"
+                | "transfer-encoding"
+                | "upgrade"
+        )
+}
+
+// Adds this hop to the `Via` field, after those it already lists.
+fn via(headers: &Headers, protocol: &Protocol) -> String {
+    let version = protocol.to_string();
+    let version = version.trim_start_matches("HTTP/");
+
+    headers
+        .get_all("Via")
+        .chain([format!("{version} {PSEUDONYM}").as_str()])
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// A failure to reach the upstream or make sense of its answer is the gateway's to report.
+fn gateway_error(e: io::Error) -> Error {
+    eprintln!("Upstream failed: {e}");
+
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+            Error::Status(StatusCode::GatewayTimeout)
+        }
+        _ => Error::Status(StatusCode::BadGateway),
+    }
+}
+
+fn invalid_response() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid upstream response.")
+}
+
+// Reads the head of the upstream's final response, skipping any interim ones.
+fn read_head(reader: &mut dyn BufRead) -> io::Result<(StatusCode, Headers)> {
+    loop {
+        let mut head = Vec::new();
+
+        while !head.ends_with(b"\r\n\r\n") && !head.ends_with(b"\n\n") {
+            let n = (&mut *reader)
+                .take(MAX_HEAD_SIZE - head.len() as u64)
+                .read_until(b'\n', &mut head)?;
+
+            if n == 0 {
+                return Err(invalid_response());
+            }
+        }
+
+        let head = String::from_utf8(head).map_err(|_| invalid_response())?;
+        let mut lines = head.lines();
+
+        // The status line is `HTTP/1.1 CODE REASON`, where the reason may have spaces or be empty.
+        let mut status = lines.next().unwrap_or_default().splitn(3, ' ').skip(1);
+        let code = status
+            .next()
+            .and_then(|code| code.parse::<u16>().ok())
+            .filter(|code| (100..=599).contains(code))
+            .ok_or_else(invalid_response)?;
+
+        match code {
+            // Upgrades aren't forwarded, so there's no protocol for the upstream to switch to.
+            101 => return Err(invalid_response()),
+            100..=199 => continue,
+            _ => {}
+        }
+
+        let reason = status.next().unwrap_or_default().trim_end();
+
+        let mut headers = Headers::new();
+
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':').ok_or_else(invalid_response)?;
+            headers.append(name.trim(), value.trim());
+        }
+
+        return Ok((StatusCode::Other(code, reason.into()), headers));
+    }
+}
+
+// Reading the body is the client's part and writing it the upstream's, so their failures are
+// told apart.
+fn send_body(body: &mut dyn Read, writer: &mut dyn Write) -> Result<()> {
+    let mut buffer = [0; 8192];
+
+    loop {
+        let n = body.read(&mut buffer)?;
+
+        if n == 0 {
+            return writer.flush().map_err(gateway_error);
+        }
+
+        writer.write_all(&buffer[..n]).map_err(gateway_error)?;
+    }
+}
+
+/// Forwards the request upstream and streams the answer back. Failing to reach the upstream is
+/// a `502 Bad Gateway`, and its taking too long a `504 Gateway Timeout`.
+pub fn forward(rq: &Request, cx: Context, proxy: &Proxy) -> Result<Response> {
+    let target = proxy.target(rq, &cx)?;
+    let mut upstream = proxy.connect().map_err(gateway_error)?;
+
+    let mut head = format!("{} {target} HTTP/1.1\r\n", rq.method);
+    head.push_str(&format!("Host: {}\r\n", proxy.authority()));
+
+    // The body is sent on in whatever framing suits it, and `100 Continue` is this server's to
+    // send, so none of the fields about either are passed on. Those naming the hops are
+    // rewritten below.
+    let forwarded = rq.headers.iter().filter(|(name, _)| {
+        !is_hop_by_hop(name, &rq.headers)
+            && !["Host", "Content-Length", "Expect", "Via"]
+                .iter()
+                .any(|n| n.eq_ignore_ascii_case(name))
+            && !name.to_ascii_lowercase().starts_with("x-forwarded-")
+    });
+
+    for (name, value) in forwarded {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+
+    if let Some(peer) = rq.peer {
+        let client = peer.addr.ip().to_string();
+        let chain = rq
+            .headers
+            .get_all("X-Forwarded-For")
+            .chain([client.as_str()])
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        head.push_str(&format!("X-Forwarded-For: {chain}\r\n"));
+        head.push_str(&format!(
+            "X-Forwarded-Proto: {}\r\n",
+            if peer.secure { "https" } else { "http" }
+        ));
+    }
+
+    if let Some(host) = rq.header("Host") {
+        head.push_str(&format!("X-Forwarded-Host: {host}\r\n"));
+    }
+
+    head.push_str(&format!("Via: {}\r\n", via(&rq.headers, &rq.protocol)));
+
+    let mut body = rq.body();
+    let length = body.declared_len();
+
+    match length {
+        Some(0) if !rq.headers.contains("Content-Length") => {}
+        Some(length) => head.push_str(&format!("Content-Length: {length}\r\n")),
+        None => head.push_str("Transfer-Encoding: chunked\r\n"),
+    }
+
+    head.push_str("Connection: close\r\n\r\n");
+    upstream.write_all(head.as_bytes()).map_err(gateway_error)?;
+
+    match length {
+        Some(_) => send_body(&mut *body, &mut upstream)?,
+        None => {
+            let mut chunked = ChunkedWriter::new(&mut upstream);
+            send_body(&mut *body, &mut chunked)?;
+            chunked.finish().map_err(gateway_error)?;
+        }
+    }
+
+    drop(body);
+
+    let mut reader = BufReader::new(upstream);
+    let (code, headers) = read_head(&mut reader).map_err(gateway_error)?;
+
+    let chunked = headers
+        .get("Transfer-Encoding")
+        .is_some_and(|te| te.to_ascii_lowercase().trim_end().ends_with("chunked"));
+    let length = match chunked {
+        true => None,
+        false => headers
+            .get("Content-Length")
+            .map(|length| length.trim().parse::<u64>())
+            .transpose()
+            .map_err(|_| gateway_error(invalid_response()))?,
+    };
+
+    // Answers to `HEAD` describe a body without sending it.
+    let has_body =
+        rq.method != Method::Head && !matches!(code.code(), 204 | 304) && length != Some(0);
+
+    // The upstream already encoded the body as the client accepts, so it's passed on as is.
+    let mut builder = Response::builder(rq).status(code).identity();
+
+    for (name, value) in headers.iter() {
+        // A streamed body's length is sent with it.
+        let skipped = ["Via"]
+            .iter()
+            .chain(has_body.then_some(&"Content-Length"))
+            .any(|n| n.eq_ignore_ascii_case(name));
+
+        if !skipped && !is_hop_by_hop(name, &headers) {
+            builder = builder.append_header(name, value);
+        }
+    }
+
+    builder = builder.header("Via", via(&headers, &Protocol::Http11));
+
+    if has_body {
+        // The type is only kept for the log, as the upstream's field is passed on unchanged.
+        let content_type = headers
+            .get("Content-Type")
+            .and_then(|ct| ContentType::try_from(ct.as_str()).ok())
+            .unwrap_or(MimeType::OctetStream.into());
+
+        let body = Framed::new(reader, chunked, length, true);
+        builder = builder.stream(content_type, body, length);
+    }
+
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::Arc, thread};
+
+    use super::*;
+    use crate::config::Config;
+
+    fn request(head: &str, test: impl FnOnce(&Request)) {
+        let mut reader = head.as_bytes();
+        let mut interim = Vec::new();
+        test(&Request::parse(&mut reader, &mut interim, 1024).unwrap());
+    }
+
+    fn context(path: &str) -> Context {
+        let mut cx = Context::new(Arc::new(Config::default()));
+        cx.params.insert("path".into(), path.into());
+        cx
+    }
+
+    // An upstream that answers one request with `reply`, and hands back the head it was sent.
+    fn upstream(reply: &'static str) -> (Proxy, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+
+            while !head.ends_with("\r\n\r\n") && reader.read_line(&mut head).unwrap() > 0 {}
+
+            reader.get_mut().write_all(reply.as_bytes()).unwrap();
+            head
+        });
+
+        let proxy = Proxy::parse(&format!("/api=http://127.0.0.1:{port}/v1")).unwrap();
+        (proxy, handle)
+    }
+
+    // What the client is sent, along with the head the upstream was sent.
+    fn exchange(head: &str, reply: &'static str) -> (Result<String>, String) {
+        let (proxy, upstream) = upstream(reply);
+        let mut output = Err(Error::Generic("No request.".into()));
+
+        request(head, |rq| {
+            output = forward(rq, context("a/b"), &proxy).map(|mut response| {
+                let mut output = Vec::new();
+                response.write_to(&mut output).unwrap();
+                String::from_utf8(output).unwrap()
+            });
+        });
+
+        (output, upstream.join().unwrap())
+    }
+
+    #[test]
+    fn swaps_the_prefix_for_the_upstream_path() {
+        let proxy = Proxy::parse("/api=http://localhost:8080/v1/").unwrap();
+        let root = Proxy::parse("api=http://localhost").unwrap();
+
+        let target = |proxy: &Proxy, uri: &str, path: &str| {
+            let mut target = None;
+            request(&format!("GET {uri} HTTP/1.1\r\n\r\n"), |rq| {
+                target = proxy.target(rq, &context(path)).ok()
+            });
+            target
+        };
+
+        assert_eq!(
+            target(&proxy, "/api/a/b?x=1", "a/b").unwrap(),
+            "/v1/a/b?x=1"
+        );
+        assert_eq!(target(&proxy, "/api", "").unwrap(), "/v1");
+        assert_eq!(target(&proxy, "/api/a/", "a").unwrap(), "/v1/a/");
+        assert_eq!(target(&proxy, "/%61pi/a%20b", "a b").unwrap(), "/v1/a%20b");
+        assert_eq!(target(&proxy, "/api/x/../a", "a").unwrap(), "/v1/a");
+        assert_eq!(target(&root, "/api", "").unwrap(), "/");
+        assert_eq!(target(&root, "/api?x", "").unwrap(), "/?x");
+
+        assert!(target(&proxy, "/api/a", "a/../..").is_none());
+    }
+
+    #[test]
+    fn drops_hop_by_hop_fields_both_ways() {
+        let (output, sent) = exchange(
+            "GET /api/a/b HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Connection: keep-alive, X-Private\r\n\
+            X-Private: 1\r\n\
+            Keep-Alive: timeout=5\r\n\
+            TE: trailers\r\n\
+            X-Kept: 1\r\n\r\n",
+            "HTTP/1.1 200 OK\r\n\
+            Connection: close, X-Upstream\r\n\
+            X-Upstream: 1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            5\r\nhello\r\n0\r\n\r\n",
+        );
+        let output = output.unwrap().to_ascii_lowercase();
+        let sent = sent.to_ascii_lowercase();
+
+        assert!(sent.starts_with("get /v1/a/b http/1.1\r\n"));
+        assert!(sent.contains("x-kept: 1\r\n"));
+        assert!(sent.contains("x-forwarded-host: example.com\r\n"));
+        assert!(sent.contains("connection: close\r\n"));
+
+        for field in ["x-private", "keep-alive", "te:", "connection: keep-alive"] {
+            assert!(!sent.contains(field), "{field}");
+        }
+
+        assert!(output.starts_with("http/1.1 200 ok\r\n"));
+        assert!(output.contains("content-type: text/plain\r\n"));
+        assert!(output.ends_with("5\r\nhello\r\n0\r\n\r\n"));
+        assert!(!output.contains("x-upstream"));
+    }
+
+    #[test]
+    fn passes_on_answers_to_head_without_a_body() {
+        let (output, sent) = exchange(
+            "HEAD /api/a/b HTTP/1.1\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nContent-Type: text/plain\r\n\r\n",
+        );
+        let output = output.unwrap();
+
+        assert!(sent.starts_with("HEAD /v1/a/b HTTP/1.1\r\n"));
+        assert!(output.contains("Content-Length: 5\r\n"));
+        assert!(output.ends_with("\r\n\r\n"));
+
+        let (output, _) = exchange(
+            "GET /api/a/b HTTP/1.1\r\n\r\n",
+            "HTTP/1.1 204 No Content\r\n\r\n",
+        );
+        assert!(output.unwrap().starts_with("HTTP/1.1 204 No Content\r\n"));
+    }
+
+    #[test]
+    fn skips_interim_answers_but_refuses_switching_protocols() {
+        let (output, _) = exchange(
+            "GET /api/a/b HTTP/1.1\r\n\r\n",
+            "HTTP/1.1 100 Continue\r\n\r\n\
+            HTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\n\
+            HTTP/1.1 299 Fine\r\nContent-Length: 2\r\n\r\nok",
+        );
+        let output = output.unwrap();
+        assert!(output.starts_with("HTTP/1.1 299 Fine\r\n"));
+        assert!(output.ends_with("\r\n\r\nok"));
+        assert!(!output.contains("Link"));
+
+        let (output, _) = exchange(
+            "GET /api/a/b HTTP/1.1\r\n\r\n",
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n",
+        );
+        assert!(matches!(output, Err(Error::Status(StatusCode::BadGateway))));
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    io::{self, Cursor, Read, Seek, SeekFrom, Take},
+};
+
+use crate::prelude::*;
+
+const MAX_RANGES: usize = 16;
+
+/// An inclusive byte range within a representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{total}", self.start, self.end)
+    }
+
+    /// The bytes of the range, read from `source` as they're wanted.
+    pub fn reader<R: Read + Seek>(&self, mut source: R) -> io::Result<Take<R>> {
+        source.seek(SeekFrom::Start(self.start))?;
+        Ok(source.take(self.len()))
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ranges {
+    Satisfiable(Vec<ByteRange>),
+    Unsatisfiable,
+}
+
+impl Ranges {
+    /// Parses a `Range` header against a representation of `length` bytes. Returns `None` when
+    /// the header should be ignored, e.g. for unknown units, malformed syntax or too many ranges.
+    pub fn parse(header: &str, length: u64) -> Option<Self> {
+        let specs = header.trim().strip_prefix("bytes=")?.split(',');
+        let mut ranges = Vec::new();
+        let mut count = 0;
+
+        for spec in specs {
+            let (first, last) = spec.trim().split_once('-')?;
+            count += 1;
+
+            if count > MAX_RANGES {
+                return None;
+            }
+
+            let range = match (first.trim(), last.trim()) {
+                ("", "") => return None,
+                ("", suffix) => {
+                    let suffix = suffix.parse::<u64>().ok()?;
+
+                    if suffix == 0 || length == 0 {
+                        continue;
+                    }
+
+                    ByteRange {
+                        start: length.saturating_sub(suffix),
+                        end: length - 1,
+                    }
+                }
+                (first, last) => {
+                    let start = first.parse::<u64>().ok()?;
+                    let end = match last {
+                        "" => u64::MAX,
+                        last => last.parse::<u64>().ok()?,
+                    };
+
+                    if end < start {
+                        return None;
+                    }
+
+                    if start >= length {
+                        continue;
+                    }
+
+                    ByteRange {
+                        start,
+                        end: end.min(length - 1),
+                    }
+                }
+            };
+
+            ranges.push(range);
+        }
+
+        if ranges.is_empty() {
+            Some(Self::Unsatisfiable)
+        } else {
+            Some(Self::Satisfiable(ranges))
+        }
+    }
+}
+
+enum Segment {
+    Literal(Cursor<Vec<u8>>),
+    /// The part of a range still to be read.
+    Range {
+        start: u64,
+        remaining: u64,
+    },
+}
+
+/// A `multipart/byteranges` body for several ranges of the same representation. Each range is
+/// read from the source only as the body is sent, so no more than a buffer's worth is held.
+pub struct Multipart<R> {
+    source: R,
+    /// Where the source was left, if it's known.
+    position: Option<u64>,
+    segments: VecDeque<Segment>,
+    boundary: String,
+    length: u64,
+}
+
+impl<R: Read + Seek> Multipart<R> {
+    pub fn new(source: R, ranges: &[ByteRange], content_type: &ContentType, total: u64) -> Self {
+        let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+        let mut segments = VecDeque::new();
+        let mut head = String::new();
+
+        for range in ranges {
+            head.push_str(&format!("--{boundary}\r\n"));
+            head.push_str(&format!("Content-Type: {content_type}\r\n"));
+            head.push_str(&format!(
+                "Content-Range: {}\r\n\r\n",
+                range.content_range(total)
+            ));
+
+            segments.push_back(Segment::Literal(Cursor::new(head.into_bytes())));
+            segments.push_back(Segment::Range {
+                start: range.start,
+                remaining: range.len(),
+            });
+            head = "\r\n".into();
+        }
+
+        head.push_str(&format!("--{boundary}--\r\n"));
+        segments.push_back(Segment::Literal(Cursor::new(head.into_bytes())));
+
+        let length = segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.get_ref().len() as u64,
+                Segment::Range { remaining, .. } => *remaining,
+            })
+            .sum();
+
+        Self {
+            source,
+            position: None,
+            segments,
+            boundary,
+            length,
+        }
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        ContentType::new(MimeType::MultipartByteRanges).with_param("boundary", &self.boundary)
+    }
+
+    /// The length of the whole body, known before any of it is read.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+}
+
+impl<R: Read + Seek> Read for Multipart<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let n = match self.segments.front_mut() {
+                None => return Ok(0),
+                Some(Segment::Literal(literal)) => literal.read(buf)?,
+                Some(Segment::Range { remaining: 0, .. }) => 0,
+                Some(Segment::Range { start, remaining }) => {
+                    if self.position != Some(*start) {
+                        self.source.seek(SeekFrom::Start(*start))?;
+                    }
+
+                    let max = buf.len().min((*remaining).try_into().unwrap_or(usize::MAX));
+                    let n = self.source.read(&mut buf[..max])?;
+
+                    // The source shrank since its length was taken.
+                    if n == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+
+                    *start += n as u64;
+                    *remaining -= n as u64;
+                    self.position = Some(*start);
+                    n
+                }
+            };
+
+            if n > 0 {
+                return Ok(n);
+            }
+
+            self.segments.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(
+            Ranges::parse("bytes=0-4", 10),
+            Some(Ranges::Satisfiable(vec![range(0, 4)]))
+        );
+        assert_eq!(
+            Ranges::parse("bytes=5-", 10),
+            Some(Ranges::Satisfiable(vec![range(5, 9)]))
+        );
+        assert_eq!(
+            Ranges::parse("bytes=-3", 10),
+            Some(Ranges::Satisfiable(vec![range(7, 9)]))
+        );
+        assert_eq!(
+            Ranges::parse("bytes=-30", 10),
+            Some(Ranges::Satisfiable(vec![range(0, 9)]))
+        );
+        assert_eq!(
+            Ranges::parse("bytes=8-20", 10),
+            Some(Ranges::Satisfiable(vec![range(8, 9)]))
+        );
+        assert_eq!(
+            Ranges::parse(" bytes=0-0, 2-3 ,-1", 10),
+            Some(Ranges::Satisfiable(vec![
+                range(0, 0),
+                range(2, 3),
+                range(9, 9)
+            ]))
+        );
+    }
+
+    #[test]
+    fn skips_unsatisfiable_ranges() {
+        assert_eq!(
+            Ranges::parse("bytes=20-30,0-1", 10),
+            Some(Ranges::Satisfiable(vec![range(0, 1)]))
+        );
+        assert_eq!(Ranges::parse("bytes=10-", 10), Some(Ranges::Unsatisfiable));
+        assert_eq!(Ranges::parse("bytes=-0", 10), Some(Ranges::Unsatisfiable));
+        assert_eq!(Ranges::parse("bytes=0-", 0), Some(Ranges::Unsatisfiable));
+        assert_eq!(Ranges::parse("bytes=-5", 0), Some(Ranges::Unsatisfiable));
+    }
+
+    #[test]
+    fn ignores_malformed_headers() {
+        for header in [
+            "",
+            "bytes=",
+            "bytes=-",
+            "bytes=5",
+            "bytes=5-2",
+            "bytes=a-b",
+            "bytes=0-1,",
+            "bytes=--1",
+            "bytes=-1-2",
+            "items=0-1",
+            "bytes 0-1",
+            "bytes=99999999999999999999-",
+        ] {
+            assert_eq!(Ranges::parse(header, 10), None, "{header:?}");
+        }
+    }
+
+    #[test]
+    fn ignores_too_many_ranges() {
+        let header = format!("bytes={}", vec!["0-0"; MAX_RANGES].join(","));
+        assert!(Ranges::parse(&header, 10).is_some());
+
+        let header = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(Ranges::parse(&header, 10), None);
+    }
+
+    #[test]
+    fn reads_a_range() {
+        let mut bytes = String::new();
+        range(3, 6)
+            .reader(Cursor::new("0123456789"))
+            .unwrap()
+            .read_to_string(&mut bytes)
+            .unwrap();
+
+        assert_eq!(bytes, "3456");
+        assert_eq!(range(3, 6).content_range(10), "bytes 3-6/10");
+    }
+
+    #[test]
+    fn streams_multipart_byteranges() {
+        let content_type = ContentType::new(MimeType::PlainText);
+        let ranges = [range(0, 1), range(8, 9)];
+        let mut multipart = Multipart::new(Cursor::new("0123456789"), &ranges, &content_type, 10);
+
+        let boundary = multipart.content_type().boundary().unwrap().to_string();
+        assert_eq!(
+            multipart.content_type().mime_type,
+            MimeType::MultipartByteRanges
+        );
+
+        // A one-byte buffer makes every segment boundary fall between reads.
+        let mut body = Vec::new();
+        let mut buf = [0; 1];
+        while multipart.read(&mut buf).unwrap() > 0 {
+            body.push(buf[0]);
+        }
+
+        let expected = format!(
+            "--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--{boundary}--\r\n"
+        );
+
+        assert_eq!(String::from_utf8(body).unwrap(), expected);
+        assert_eq!(multipart.length(), expected.len() as u64);
+    }
+
+    #[test]
+    fn fails_when_the_source_shrinks() {
+        let content_type = ContentType::new(MimeType::PlainText);
+        let mut multipart = Multipart::new(Cursor::new("0123"), &[range(2, 9)], &content_type, 10);
+
+        let error = io::copy(&mut multipart, &mut io::sink()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -1,73 +1,107 @@
 use std::{
-    collections::HashMap,
+    cell::{RefCell, RefMut},
     fmt,
-    io::{BufRead, BufReader, Read},
-    net::TcpStream,
+    io::{BufRead, Read, Write},
+    net::SocketAddr,
 };
 
-use crate::prelude::*;
+use crate::{
+    body::Body,
+    cookie::CookieJar,
+    form::{Form, FromForm},
+    headers::Headers,
+    multipart::{Limits, Multipart},
+    prelude::*,
+    url,
+};
 
 const MAX_REQUEST_SIZE: usize = 4096;
+/// Form bodies are read into memory whole, so they're held to less than other bodies.
+const MAX_FORM_SIZE: u64 = 1 << 20;
 
-fn read_until(reader: &mut BufReader<&mut TcpStream>, bytes: &[u8]) -> Result<String> {
+fn read_until(reader: &mut dyn BufRead, bytes: &[u8]) -> Result<String> {
     let mut buffer = Vec::new();
 
     loop {
-        match reader.read_until(bytes[bytes.len() - 1], &mut buffer) {
+        // Lines are read no further than the limit, however long they are.
+        let limit = (MAX_REQUEST_SIZE + 1 - buffer.len()) as u64;
+
+        match (&mut *reader)
+            .take(limit)
+            .read_until(bytes[bytes.len() - 1], &mut buffer)
+        {
             Ok(0) => break,
             Ok(_) if buffer.ends_with(bytes) => break,
             Err(e) => return Err(e.into()),
-            _ if buffer.len() > MAX_REQUEST_SIZE => {
-                return Err(Error::Generic(format!(
-                    "Request exceeds {MAX_REQUEST_SIZE} bytes."
-                )))
-            }
+            // The request line alone being too long means the URI is.
+            _ if buffer.len() > MAX_REQUEST_SIZE => match buffer.contains(&b'\n') {
+                true => return Err(Error::Status(StatusCode::RequestHeaderFieldsTooLarge)),
+                false => return Err(Error::Status(StatusCode::UriTooLong)),
+            },
             _ => {}
         }
     }
 
-    Ok(String::from_utf8(buffer)?)
+    String::from_utf8(buffer).map_err(|_| Error::Status(StatusCode::BadRequest))
+}
+
+/// The client end of the connection a request arrived on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    /// Whether the connection is encrypted.
+    pub secure: bool,
 }
 
 #[derive(Debug)]
-pub struct Request {
+pub struct Request<'a> {
     pub method: Method,
     pub uri: String,
     pub protocol: Protocol,
-    pub headers: HashMap<String, String>,
-    pub content: Option<Content>,
+    pub headers: Headers,
+    pub content_type: Option<ContentType>,
+    /// Set by the server once the request has been read off a connection.
+    pub peer: Option<Peer>,
+    /// The path's segments as resolved once, or `None` if it climbs above the root.
+    segments: Option<Vec<String>>,
+    body: RefCell<Body<'a>>,
 }
 
-impl Request {
-    pub fn parse(stream: &mut TcpStream) -> Result<Self> {
-        let mut reader = BufReader::new(stream);
-
-        let header = read_until(&mut reader, b"\r\n\r\n")?;
+impl<'a> Request<'a> {
+    /// Parses the request head. The body is left on the reader, to be streamed by the handler
+    /// through [`Request::body`] up to `max_body_size` bytes. Interim responses are sent on
+    /// `writer`. A head that can't be served fails with the status to refuse it with.
+    pub fn parse(
+        reader: &'a mut dyn BufRead,
+        writer: &'a mut dyn Write,
+        max_body_size: u64,
+    ) -> Result<Self> {
+        let header = read_until(reader, b"\r\n\r\n")?;
         let mut lines = header.trim().lines();
 
         let (method, uri, protocol) = {
-            let status_line = lines
-                .next()
-                .ok_or(Error::Generic("Failed to parse status line.".into()))?;
+            let status_line = lines.next().ok_or(Error::Status(StatusCode::BadRequest))?;
 
             match status_line.split(" ").collect::<Vec<_>>()[..] {
                 [method, uri, protocol] => (
-                    Method::try_from(method)?,
+                    Method::try_from(method)
+                        .map_err(|_| Error::Status(StatusCode::NotImplemented))?,
                     String::from(uri),
-                    Protocol::try_from(protocol)?,
+                    Protocol::try_from(protocol)
+                        .map_err(|_| Error::Status(StatusCode::BadRequest))?,
                 ),
                 _ => {
-                    return Err("Failed to parse status line.".into());
+                    return Err(Error::Status(StatusCode::BadRequest));
                 }
             }
         };
 
         let headers = {
-            let mut headers = HashMap::new();
+            let mut headers = Headers::new();
 
             for line in lines {
                 if let Some((k, v)) = line.split_once(": ") {
-                    headers.insert(String::from(k), String::from(v));
+                    headers.append(k, v);
                 } else {
                     break;
                 }
@@ -76,68 +110,382 @@ impl Request {
             headers
         };
 
-        let content = {
-            match (headers.get("Content-Type"), headers.get("Content-Length")) {
-                (Some(mime_type), Some(length)) => {
-                    let length = length.parse::<usize>()?;
-                    let mut buffer = [0; MAX_REQUEST_SIZE];
-                    let mut bytes = 0;
-
-                    while bytes < length {
-                        match reader.read(&mut buffer)? {
-                            0 => break,
-                            n => bytes += n,
-                        }
-                    }
-
-                    let body = String::from_utf8(buffer[0..bytes].to_vec())?;
-                    Some(Content::new(mime_type.try_into()?, &body))
-                }
-                _ => None,
+        let codings = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|te| te.split(','))
+            .map(|coding| coding.trim().to_ascii_lowercase())
+            .collect::<Vec<_>>();
+
+        let length = headers
+            .get("Content-Length")
+            .map(|length| length.trim().parse::<u64>())
+            .transpose()
+            .map_err(|_| Error::Status(StatusCode::BadRequest))?;
+
+        // Where a body ends must be beyond doubt, or a proxy in front of this server could see a
+        // different request in the same bytes. Only a final `chunked` says where it ends.
+        let chunked = match codings.last().map(String::as_str) {
+            None => false,
+            Some("chunked") if length.is_none() && codings.len() == 1 => true,
+            Some("chunked") if length.is_none() => {
+                return Err(Error::Status(StatusCode::NotImplemented))
             }
+            Some(_) => return Err(Error::Status(StatusCode::BadRequest)),
         };
 
-        Ok(Self {
+        let mut body = Body::new(reader, chunked, length, max_body_size);
+
+        // HTTP/1.0 has no interim responses, so its clients' expectations are ignored.
+        let expect = headers
+            .get("Expect")
+            .filter(|_| protocol != Protocol::Http10);
+
+        if expect.is_some_and(|e| e.eq_ignore_ascii_case("100-continue")) {
+            body.expect_continue(writer);
+        }
+
+        let request = Self::from_parts(method, uri, protocol, headers, body);
+
+        // A path that climbs above the root names nothing this server could serve.
+        match request.segments {
+            Some(_) => Ok(request),
+            None => Err(Error::Status(StatusCode::BadRequest)),
+        }
+    }
+
+    /// Assembles a request whose head was read by another protocol, such as HTTP/2.
+    pub fn from_parts(
+        method: Method,
+        uri: String,
+        protocol: Protocol,
+        headers: Headers,
+        body: Body<'a>,
+    ) -> Self {
+        let content_type = headers
+            .get("Content-Type")
+            .and_then(|s| ContentType::try_from(s).ok());
+
+        let path = uri.split_once('?').map_or(uri.as_str(), |(path, _)| path);
+        let segments = url::path_segments(path);
+
+        Self {
             method,
             uri,
             protocol,
             headers,
-            content,
-        })
+            content_type,
+            peer: None,
+            segments,
+            body: RefCell::new(body),
+        }
     }
 
-    pub fn response(&self, code: StatusCode, content: Option<Content>) -> Result<Response> {
-        let headers = HashMap::new();
-        let mut encoding = None;
+    /// Whether the request carries an expectation other than `100-continue`, which this server
+    /// cannot meet.
+    pub fn expectation_failed(&self) -> bool {
+        self.protocol != Protocol::Http10
+            && self
+                .header("Expect")
+                .is_some_and(|e| !e.eq_ignore_ascii_case("100-continue"))
+    }
+
+    /// Whether the client wants the connection kept open after the response, by default in
+    /// HTTP/1.1 and only on request in HTTP/1.0.
+    pub fn keep_alive(&self) -> bool {
+        match self.protocol.is_persistent() {
+            true => !self.has_token("Connection", "close"),
+            false => self.has_token("Connection", "keep-alive"),
+        }
+    }
 
-        if let Some(schemes) = self.headers.get("Accept-Encoding") {
-            let mut schemes = schemes
-                .split(", ")
-                .filter_map(|s| Encoding::try_from(s).ok())
-                .collect::<Vec<_>>();
+    /// Whether the comma-separated field `name` lists `token`, ignoring case.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.headers
+            .get_all(name)
+            .flat_map(|h| h.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// The request body as a stream. Each byte can only be read once.
+    pub fn body(&self) -> RefMut<'_, Body<'a>> {
+        self.body.borrow_mut()
+    }
+
+    /// Streams a `multipart/form-data` body part by part.
+    pub fn multipart(&self, limits: Limits) -> Result<Multipart<'_, 'a>> {
+        let content_type = self
+            .content_type
+            .as_ref()
+            .filter(|ct| ct.mime_type == MimeType::MultipartFormData)
+            .ok_or(Error::Status(StatusCode::UnsupportedMediaType))?;
+
+        let boundary = content_type
+            .boundary()
+            .filter(|b| (1..=70).contains(&b.len()))
+            .ok_or(Error::Status(StatusCode::BadRequest))?;
+
+        Ok(Multipart::new(self.body(), boundary, limits))
+    }
 
-            schemes.sort();
-            encoding = schemes.first().cloned();
+    /// Reads an `application/x-www-form-urlencoded` body into `T`, or responds with 415 if the
+    /// body is of another type, 413 if it's over 1 MiB and 400 if it isn't UTF-8.
+    pub fn form<T: FromForm>(&self) -> Result<T> {
+        let is_form = self
+            .content_type
+            .as_ref()
+            .is_some_and(|ct| ct.mime_type == MimeType::FormUrlEncoded);
+
+        if !is_form {
+            return Err(Error::Status(StatusCode::UnsupportedMediaType));
         }
 
-        Ok(Response {
-            protocol: self.protocol.clone(),
-            code,
-            content,
-            headers,
-            encoding,
-        })
+        let mut body = Vec::new();
+        let mut reader = self.body();
+        reader.restrict(MAX_FORM_SIZE);
+        reader.read_to_end(&mut body)?;
+
+        // Form bodies escape anything beyond ASCII, so raw bytes that aren't UTF-8 are malformed.
+        let body = String::from_utf8(body).map_err(|_| Error::Status(StatusCode::BadRequest))?;
+        T::from_form(&Form::parse(&body))
+    }
+
+    /// Reads the rest of the body into memory, or returns `None` if the request has no body.
+    pub fn read_content(&self) -> Result<Option<Content>> {
+        let mut body = self.body();
+
+        if body.is_empty() {
+            return Ok(None);
+        }
+
+        let mut buffer = Vec::new();
+        body.read_to_end(&mut buffer)?;
+
+        let content_type = self
+            .content_type
+            .clone()
+            .unwrap_or(MimeType::OctetStream.into());
+
+        Ok(Some(Content::from_bytes(content_type, buffer)))
+    }
+
+    pub fn path(&self) -> &str {
+        self.uri.split_once('?').map_or(&self.uri, |(path, _)| path)
+    }
+
+    /// The decoded segments of the path, with `.` and `..` resolved. `None` if a `..` climbs
+    /// above the root.
+    pub fn segments(&self) -> Option<&[String]> {
+        self.segments.as_deref()
+    }
+
+    pub fn query(&self) -> Form {
+        self.uri
+            .split_once('?')
+            .map_or_else(Form::default, |(_, query)| Form::parse(query))
+    }
+
+    pub fn query_param(&self, key: &str) -> Option<String> {
+        self.query().get(key).map(String::from)
+    }
+
+    pub fn cookies(&self) -> CookieJar {
+        CookieJar::parse(self.headers.get_all("Cookie"))
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies().get(name).map(String::from)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers.get(name)
+    }
+
+    pub fn accept(&self) -> Accept {
+        self.header("Accept")
+            .and_then(|s| Accept::try_from(s).ok())
+            .unwrap_or_else(Accept::any)
+    }
+
+    /// Responds with whichever of the offered representations the client prefers, or 406 if none
+    /// are acceptable. Offers are listed in order of server preference.
+    pub fn negotiate(&self, code: StatusCode, offers: Vec<Content>) -> Result<Response> {
+        match self.accept().best(&offers, |c| &c.content_type) {
+            Some(content) => self.response(code, Some(content.clone())),
+            None => self.response(StatusCode::NotAcceptable, None),
+        }
+    }
+
+    /// The most preferred of the client's accepted encodings that this server supports.
+    pub fn encoding(&self) -> Option<Encoding> {
+        let mut schemes = self
+            .header("Accept-Encoding")?
+            .split(", ")
+            .filter_map(|s| Encoding::try_from(s).ok())
+            .collect::<Vec<_>>();
+
+        schemes.sort();
+        schemes.first().cloned()
+    }
+
+    pub fn response(&self, code: StatusCode, content: Option<Content>) -> Result<Response> {
+        let builder = Response::builder(self).status(code);
+
+        match content {
+            Some(content) => builder.content(content).build(),
+            None => builder.build(),
+        }
     }
 }
 
-impl fmt::Display for Request {
+impl fmt::Display for Request<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Request: {} {} {}", self.method, self.uri, self.protocol)?;
 
-        if let Some(content) = &self.content {
-            write!(f, " -> {content}")?;
+        if let Some(content_type) = &self.content_type {
+            match self.body().declared_len() {
+                Some(length) => write!(f, " -> Content({content_type}, {length} B)")?,
+                None => write!(f, " -> Content({content_type}, chunked)")?,
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The status a head is refused with.
+    fn refusal(head: &[u8]) -> Option<u16> {
+        let (mut reader, mut interim) = (head, Vec::new());
+
+        match Request::parse(&mut reader, &mut interim, 1024) {
+            Err(Error::Status(code)) => Some(code.code()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_a_request() {
+        let mut reader: &[u8] = b"POST /echo?a=1&b=%20 HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Content-Type: application/x-www-form-urlencoded\r\n\
+            Content-Length: 9\r\n\
+            \r\n\
+            message=xGET";
+        let mut interim = Vec::new();
+        let rq = Request::parse(&mut reader, &mut interim, 1024).unwrap();
+
+        assert_eq!(rq.method, Method::Post);
+        assert_eq!(rq.path(), "/echo");
+        assert_eq!(rq.query_param("b").as_deref(), Some(" "));
+        assert_eq!(rq.protocol, Protocol::Http11);
+        assert_eq!(rq.header("host").map(String::as_str), Some("localhost"));
+        assert_eq!(
+            rq.form::<crate::form::Form>().unwrap().get("message"),
+            Some("x")
+        );
+        drop(rq);
+
+        assert_eq!(reader, b"GET");
+        assert!(interim.is_empty());
+    }
+
+    #[test]
+    fn refuses_form_bodies_that_are_not_utf8() {
+        let mut reader: &[u8] = b"POST /echo HTTP/1.1\r\n\
+            Content-Type: application/x-www-form-urlencoded\r\n\
+            Content-Length: 9\r\n\
+            \r\n\
+            message=\xff";
+        let mut interim = Vec::new();
+        let rq = Request::parse(&mut reader, &mut interim, 1024).unwrap();
+
+        assert!(matches!(
+            rq.form::<crate::form::Form>(),
+            Err(Error::Status(StatusCode::BadRequest))
+        ));
+    }
+
+    #[test]
+    fn defers_100_continue() {
+        let mut reader: &[u8] =
+            b"PUT /f HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\nhi";
+        let mut interim = Vec::new();
+
+        {
+            let rq = Request::parse(&mut reader, &mut interim, 1024).unwrap();
+            assert!(rq.body().awaiting_continue());
+            assert_eq!(rq.read_content().unwrap().unwrap().body, b"hi");
+        }
+
+        assert_eq!(interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+    }
+
+    #[test]
+    fn refuses_malformed_heads() {
+        for head in [
+            &b"\r\n\r\n"[..],
+            b"GET /\r\n\r\n",
+            b"GET / HTTP/1.1 extra\r\n\r\n",
+            b"GET  / HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/9.9\r\n\r\n",
+            b"GET / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nContent-Length: ten\r\n\r\n",
+            b"GET /\xff HTTP/1.1\r\n\r\n",
+        ] {
+            assert_eq!(
+                refusal(head),
+                Some(400),
+                "{:?}",
+                String::from_utf8_lossy(head)
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_ambiguous_framing() {
+        for head in [
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n"[..],
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: xchunked\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: identity\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n\r\n",
+        ] {
+            assert_eq!(
+                refusal(head),
+                Some(400),
+                "{:?}",
+                String::from_utf8_lossy(head)
+            );
+        }
+
+        assert_eq!(
+            refusal(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"),
+            Some(501)
+        );
+        assert_eq!(
+            refusal(b"POST / HTTP/1.1\r\nTransfer-Encoding: Chunked \r\n\r\n0\r\n\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn refuses_unknown_methods() {
+        assert_eq!(refusal(b"BREW /pot HTTP/1.1\r\n\r\n"), Some(501));
+    }
+
+    #[test]
+    fn refuses_oversized_heads() {
+        let long_uri = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_REQUEST_SIZE));
+        assert_eq!(refusal(long_uri.as_bytes()), Some(414));
+
+        let long_field = format!(
+            "GET / HTTP/1.1\r\nX: {}\r\n\r\n",
+            "a".repeat(MAX_REQUEST_SIZE)
+        );
+        assert_eq!(refusal(long_field.as_bytes()), Some(431));
+    }
+}
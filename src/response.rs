@@ -1,19 +1,141 @@
-use flate2::{write::GzEncoder, Compression};
-use std::{collections::HashMap, fmt, io::Write};
+use flate2::{read, write::GzEncoder, Compression};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+    time::SystemTime,
+};
 
-use crate::prelude::*;
+use crate::{
+    conditional::{ETag, Validators},
+    cookie::SetCookie,
+    date::format_http_date,
+    headers::Headers,
+    prelude::*,
+    websocket::Handoff,
+};
+
+/// A body that is read as the response is written, rather than held in memory.
+pub struct Stream {
+    pub content_type: ContentType,
+    pub reader: Box<dyn Read + Send>,
+    pub length: Option<u64>,
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stream")
+            .field("content_type", &self.content_type)
+            .field("length", &self.length)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum Payload {
+    Content(Content),
+    Stream(Stream),
+    /// Takes over the connection as a WebSocket once the response has been written.
+    WebSocket(Handoff),
+}
 
 #[derive(Debug)]
 pub struct Response {
     pub protocol: Protocol,
     pub code: StatusCode,
-    pub content: Option<Content>,
-    pub headers: HashMap<String, String>,
+    pub payload: Option<Payload>,
+    pub headers: Headers,
     pub encoding: Option<Encoding>,
+    /// Set for `HEAD`, whose answer describes the body `GET` would get without sending it.
+    pub omit_body: bool,
+}
+
+/// Frames whatever is written to it as `Transfer-Encoding: chunked`.
+pub struct ChunkedWriter<'w> {
+    writer: &'w mut dyn Write,
+}
+
+impl<'w> ChunkedWriter<'w> {
+    pub fn new(writer: &'w mut dyn Write) -> Self {
+        Self { writer }
+    }
+
+    /// Writes the last, empty chunk, which ends the body.
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.write_all(b"0\r\n\r\n")
+    }
+}
+
+impl Write for ChunkedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() {
+            write!(self.writer, "{:x}\r\n", buf.len())?;
+            self.writer.write_all(buf)?;
+            self.writer.write_all(b"\r\n")?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn gzip(body: &[u8], buffer: &mut Vec<u8>) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(buffer, Compression::default());
+    encoder.write_all(body)?;
+    encoder.try_finish()
+}
+
+// Copies the body on as the reader produces it, rather than once a buffer fills, so that
+// long-lived streams such as events reach the client as they're written.
+fn pump(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<()> {
+    let mut buffer = [0; 8192];
+    writer.flush()?;
+
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => {
+                writer.write_all(&buffer[..n])?;
+                writer.flush()?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
 }
 
 impl Response {
-    pub fn encode(&mut self) -> Vec<u8> {
+    /// Starts a `200 OK` response that inherits the protocol and encoding negotiated for `rq`.
+    pub fn builder(rq: &Request) -> ResponseBuilder {
+        ResponseBuilder {
+            invalid: None,
+            response: Response {
+                protocol: rq.protocol.clone(),
+                code: StatusCode::Ok,
+                payload: None,
+                headers: Headers::new(),
+                encoding: rq.encoding(),
+                omit_body: rq.method == Method::Head,
+            },
+        }
+    }
+
+    pub fn content(&self) -> Option<&Content> {
+        match &self.payload {
+            Some(Payload::Content(content)) => Some(content),
+            _ => None,
+        }
+    }
+
+    pub fn set_cookie(&mut self, cookie: &SetCookie) {
+        self.headers.append("Set-Cookie", cookie.to_string());
+    }
+
+    fn head(&self) -> Vec<u8> {
         let mut output: Vec<u8> = Vec::new();
 
         output.extend(format!("{} {}\r\n", self.protocol, self.code).as_bytes());
@@ -23,21 +145,20 @@ impl Response {
                 .flat_map(|(k, v)| format!("{k}: {v}\r\n").into_bytes()),
         );
 
-        if let Some(content) = &self.content {
-            let mut buffer = Vec::new();
-            let unencoded = content.body.as_bytes();
+        output
+    }
 
-            let body = if self.encoding == Some(Encoding::Gzip) {
-                let gzip_ok = {
-                    let mut encoder = GzEncoder::new(&mut buffer, Compression::default());
+    /// Encodes a response whose body is held in memory. A streamed body is left out, so use
+    /// `write_to` for those.
+    pub fn encode(&mut self) -> Vec<u8> {
+        let mut output = self.head();
 
-                    encoder
-                        .write_all(unencoded)
-                        .and_then(|_| encoder.try_finish())
-                        .is_ok()
-                };
+        if let Some(content) = self.content() {
+            let mut buffer = Vec::new();
+            let unencoded = content.body.as_slice();
 
-                if gzip_ok {
+            let body = if self.encoding == Some(Encoding::Gzip) {
+                if gzip(unencoded, &mut buffer).is_ok() {
                     output.extend(format!("Content-Encoding: {}\r\n", Encoding::Gzip).as_bytes());
                     &buffer
                 } else {
@@ -47,26 +168,407 @@ impl Response {
                 unencoded
             };
 
-            output.extend(format!("Content-Type: {}\r\n", content.mime_type).as_bytes());
+            output.extend(format!("Content-Type: {}\r\n", content.content_type).as_bytes());
             output.extend(format!("Content-Length: {}\r\n", body.len()).as_bytes());
             output.extend(b"\r\n");
-            output.extend(body);
+
+            if !self.omit_body {
+                output.extend(body);
+            }
         } else {
+            // An empty body still needs framing, or a client on a persistent connection would
+            // wait for one.
+            if self.has_body() && !self.headers.contains("Content-Length") {
+                output.extend(b"Content-Length: 0\r\n");
+            }
+
             output.extend(b"\r\n");
         }
 
         output
     }
+
+    // Informational, `204` and `304` responses never have a body, not even an empty one.
+    fn has_body(&self) -> bool {
+        !matches!(self.code.code(), 100..=199 | 204 | 304)
+    }
+
+    /// Whether the body ends when the connection closes, which it does for streams of unknown
+    /// length to clients that can't read chunks.
+    pub fn is_close_delimited(&self) -> bool {
+        match &self.payload {
+            Some(Payload::Stream(stream)) => {
+                let gzip = self.encoding == Some(Encoding::Gzip);
+                !self.protocol.supports_chunked() && (gzip || stream.length.is_none())
+            }
+            _ => false,
+        }
+    }
+
+    /// Splits the response into its header fields and body, for protocols that frame the body
+    /// themselves. The body is compressed if negotiated, and described by the content fields.
+    pub fn into_parts(self) -> (StatusCode, Headers, Option<Box<dyn Read + Send>>) {
+        let mut headers = self.headers;
+        let omit_body = self.omit_body;
+        let gzip_ok = self.encoding == Some(Encoding::Gzip);
+
+        let body: Option<Box<dyn Read + Send>> = match self.payload {
+            None | Some(Payload::WebSocket(_)) => None,
+            Some(Payload::Content(content)) => {
+                let mut body = content.body;
+                let mut buffer = Vec::new();
+
+                if gzip_ok && gzip(&body, &mut buffer).is_ok() {
+                    headers.insert("Content-Encoding", Encoding::Gzip.to_string());
+                    body = buffer;
+                }
+
+                headers.insert("Content-Type", content.content_type.to_string());
+                headers.insert("Content-Length", body.len().to_string());
+                Some(Box::new(io::Cursor::new(body)))
+            }
+            Some(Payload::Stream(stream)) => {
+                if !headers.contains("Content-Type") {
+                    headers.insert("Content-Type", stream.content_type.to_string());
+                }
+
+                match (gzip_ok, stream.length) {
+                    (true, _) => {
+                        headers.insert("Content-Encoding", Encoding::Gzip.to_string());
+                        let reader = read::GzEncoder::new(stream.reader, Compression::default());
+                        Some(Box::new(reader))
+                    }
+                    (false, Some(length)) => {
+                        headers.insert("Content-Length", length.to_string());
+                        Some(Box::new(stream.reader.take(length)))
+                    }
+                    (false, None) => Some(stream.reader),
+                }
+            }
+        };
+
+        (self.code, headers, body.filter(|_| !omit_body))
+    }
+
+    /// Takes the handoff of a response that accepted a WebSocket.
+    pub fn take_websocket(&mut self) -> Option<Handoff> {
+        match self.payload.take() {
+            Some(Payload::WebSocket(handoff)) => Some(handoff),
+            payload => {
+                self.payload = payload;
+                None
+            }
+        }
+    }
+
+    /// Writes the response, streaming its body if it has one.
+    pub fn write_to(&mut self, writer: &mut dyn Write) -> io::Result<()> {
+        match self.payload.take() {
+            Some(Payload::Stream(stream)) => self.write_stream(writer, stream)?,
+            payload => {
+                self.payload = payload;
+                writer.write_all(&self.encode())?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    fn write_stream(&self, writer: &mut dyn Write, stream: Stream) -> io::Result<()> {
+        let mut head = self.head();
+
+        // A type among the headers, such as one passed on from an upstream, is sent as it is.
+        if !self.headers.contains("Content-Type") {
+            head.extend(format!("Content-Type: {}\r\n", stream.content_type).as_bytes());
+        }
+
+        let gzip = self.encoding == Some(Encoding::Gzip);
+
+        if let (false, Some(length)) = (gzip, stream.length) {
+            head.extend(format!("Content-Length: {length}\r\n\r\n").as_bytes());
+            writer.write_all(&head)?;
+
+            if !self.omit_body {
+                io::copy(&mut stream.reader.take(length), writer)?;
+            }
+
+            return Ok(());
+        }
+
+        // Without a known length the body has to be chunked, and compressing it loses the length.
+        if gzip {
+            head.extend(format!("Content-Encoding: {}\r\n", Encoding::Gzip).as_bytes());
+        }
+
+        let mut reader = stream.reader;
+
+        // Older clients can't read chunks, so the body ends when the connection closes instead.
+        if !self.protocol.supports_chunked() {
+            writer.write_all(&head)?;
+            writer.write_all(b"\r\n")?;
+
+            if self.omit_body {
+                return Ok(());
+            }
+
+            if gzip {
+                let mut encoder = GzEncoder::new(writer, Compression::default());
+                io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()?;
+            } else {
+                pump(&mut reader, writer)?;
+            }
+
+            return Ok(());
+        }
+
+        head.extend(b"Transfer-Encoding: chunked\r\n\r\n");
+        writer.write_all(&head)?;
+
+        if self.omit_body {
+            return Ok(());
+        }
+
+        let mut chunked = ChunkedWriter::new(writer);
+
+        if gzip {
+            let mut encoder = GzEncoder::new(&mut chunked, Compression::default());
+            io::copy(&mut reader, &mut encoder)?;
+            encoder.finish()?;
+        } else {
+            pump(&mut reader, &mut chunked)?;
+        }
+
+        chunked.finish()
+    }
 }
 
 impl fmt::Display for Response {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Response: {} {}", self.protocol, self.code)?;
 
-        if let Some(content) = &self.content {
-            write!(f, " -> {content}")?;
+        match &self.payload {
+            Some(Payload::Content(content)) => write!(f, " -> {content}")?,
+            Some(Payload::Stream(stream)) => match stream.length {
+                Some(length) => write!(f, " -> Stream({}, {length} B)", stream.content_type)?,
+                None => write!(f, " -> Stream({})", stream.content_type)?,
+            },
+            Some(Payload::WebSocket(_)) => write!(f, " -> WebSocket")?,
+            None => {}
         }
 
         Ok(())
     }
 }
+
+/// Builds a response step by step, starting from `Response::builder`.
+#[derive(Debug)]
+pub struct ResponseBuilder {
+    response: Response,
+    /// The first header that couldn't be written as is, reported by `build`.
+    invalid: Option<String>,
+}
+
+// Names must be tokens, and values mustn't hold CR, LF or NUL, any of which would let a value
+// from elsewhere end its field, or the whole head, early.
+fn is_valid_field(name: &str, value: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+        && !value.bytes().any(|b| matches!(b, b'\r' | b'\n' | b'\0'))
+}
+
+impl ResponseBuilder {
+    pub fn status(mut self, code: StatusCode) -> Self {
+        self.response.code = code;
+        self
+    }
+
+    /// Sets a header, replacing any values it had. A name that isn't a token, or a value holding
+    /// CR, LF or NUL, makes `build` fail instead.
+    pub fn header(mut self, name: &str, value: impl fmt::Display) -> Self {
+        let value = value.to_string();
+
+        match is_valid_field(name, &value) {
+            true => self.response.headers.insert(name, value),
+            false => self.refuse(name),
+        }
+
+        self
+    }
+
+    /// Adds a header, keeping any values it already has. It's held to the same rules as `header`.
+    pub fn append_header(mut self, name: &str, value: impl fmt::Display) -> Self {
+        let value = value.to_string();
+
+        match is_valid_field(name, &value) {
+            true => self.response.headers.append(name, value),
+            false => self.refuse(name),
+        }
+
+        self
+    }
+
+    fn refuse(&mut self, name: &str) {
+        self.invalid.get_or_insert_with(|| name.into());
+    }
+
+    /// Adds a `Set-Cookie` header, keeping any cookies already set.
+    pub fn cookie(self, cookie: &SetCookie) -> Self {
+        self.append_header("Set-Cookie", cookie)
+    }
+
+    pub fn location(self, uri: &str) -> Self {
+        self.header("Location", uri)
+    }
+
+    pub fn cache_control(self, directives: &str) -> Self {
+        self.header("Cache-Control", directives)
+    }
+
+    pub fn etag(self, etag: &ETag) -> Self {
+        self.header("ETag", etag)
+    }
+
+    pub fn last_modified(self, time: SystemTime) -> Self {
+        self.header("Last-Modified", format_http_date(time))
+    }
+
+    pub fn validators(mut self, validators: &Validators) -> Self {
+        validators.apply(&mut self.response);
+        self
+    }
+
+    /// Sends the body as is, even if the client accepts a compressed encoding.
+    pub fn identity(mut self) -> Self {
+        self.response.encoding = None;
+        self
+    }
+
+    pub fn content(mut self, content: Content) -> Self {
+        self.response.payload = Some(Payload::Content(content));
+        self
+    }
+
+    pub fn text(self, body: &str) -> Self {
+        self.content(Content::new(MimeType::PlainText, body))
+    }
+
+    pub fn html(self, body: &str) -> Self {
+        self.content(Content::new(MimeType::Html, body))
+    }
+
+    pub fn bytes(self, content_type: impl Into<ContentType>, body: Vec<u8>) -> Self {
+        self.content(Content::from_bytes(content_type, body))
+    }
+
+    /// Streams the body from `reader`. Without a length, the body is sent chunked.
+    pub fn stream(
+        mut self,
+        content_type: impl Into<ContentType>,
+        reader: impl Read + Send + 'static,
+        length: Option<u64>,
+    ) -> Self {
+        self.response.payload = Some(Payload::Stream(Stream {
+            content_type: content_type.into(),
+            reader: Box::new(reader),
+            length,
+        }));
+        self
+    }
+
+    /// Streams the file at `path`, typed by its extension.
+    pub fn file(self, path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        Ok(self.stream(MimeType::from_path(path), file, Some(length)))
+    }
+
+    /// Hands the connection to `handoff` once the response has been written.
+    pub fn websocket(mut self, handoff: Handoff) -> Self {
+        self.response.payload = Some(Payload::WebSocket(handoff));
+        self
+    }
+
+    /// Redirects with `302 Found`.
+    pub fn redirect(self, uri: &str) -> Self {
+        self.status(StatusCode::Found).location(uri)
+    }
+
+    /// Redirects with `303 See Other`, which makes the client follow up with a `GET`.
+    pub fn see_other(self, uri: &str) -> Self {
+        self.status(StatusCode::SeeOther).location(uri)
+    }
+
+    /// Redirects with `301 Moved Permanently`.
+    pub fn moved_permanently(self, uri: &str) -> Self {
+        self.status(StatusCode::MovedPermanently).location(uri)
+    }
+
+    pub fn build(self) -> Result<Response> {
+        match self.invalid {
+            Some(name) => Err(Error::Generic(format!(
+                "Refused to send an invalid '{}' header.",
+                name.escape_debug()
+            ))),
+            None => Ok(self.response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(edit: impl FnOnce(ResponseBuilder) -> ResponseBuilder) -> Result<Response> {
+        let mut reader = "GET / HTTP/1.1\r\n\r\n".as_bytes();
+        let mut interim = Vec::new();
+        let rq = Request::parse(&mut reader, &mut interim, 0).unwrap();
+        edit(Response::builder(&rq)).build()
+    }
+
+    #[test]
+    fn writes_valid_headers() {
+        let response = build(|b| {
+            b.header("X-Note", "a \"quoted\"\tvalue")
+                .append_header("X-Note", "another")
+                .location("/next?a=b")
+        })
+        .unwrap();
+
+        let head = String::from_utf8(response.head()).unwrap();
+        assert!(head.contains("X-Note: a \"quoted\"\tvalue\r\n"));
+        assert!(head.contains("X-Note: another\r\n"));
+        assert!(head.contains("Location: /next?a=b\r\n"));
+    }
+
+    #[test]
+    fn refuses_line_breaks_in_values() {
+        for value in ["a\r\nSet-Cookie: x=y", "a\nb", "a\rb", "a\0b"] {
+            assert!(build(|b| b.header("X-Note", value)).is_err(), "{value:?}");
+            assert!(
+                build(|b| b.append_header("X-Note", value)).is_err(),
+                "{value:?}"
+            );
+            assert!(build(|b| b.location(value)).is_err(), "{value:?}");
+        }
+
+        let cookie = SetCookie::new("a", "b").path("/\r\nX-Injected: 1");
+        assert!(build(|b| b.cookie(&cookie)).is_err());
+    }
+
+    #[test]
+    fn refuses_names_that_are_not_tokens() {
+        for name in ["", "X Note", "X-Note:", "X\r\nNote", "X-Nöte"] {
+            assert!(build(|b| b.header(name, "a")).is_err(), "{name:?}");
+            assert!(build(|b| b.append_header(name, "a")).is_err(), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn keeps_refusing_once_a_header_is_invalid() {
+        let result = build(|b| b.header("X-Note", "a\r\nb").header("X-Note", "fine"));
+        assert!(result.is_err());
+    }
+}
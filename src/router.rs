@@ -1,83 +1,290 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use regex::Regex;
 
-use crate::{prelude::*, routes::ROUTES};
+use crate::{
+    config::Config,
+    files,
+    prelude::*,
+    proxy,
+    routes::{ROUTES, SOCKET_ROUTES},
+    session::{
+        FileStore, MemoryStore, Session, SessionBackend, SessionStore, Sessions, SESSION_DIRECTORY,
+    },
+    websocket::{self, WebSocket},
+};
 
-pub type Context = HashMap<String, String>;
 pub type RouteHandler = fn(&Request, Context) -> Result<Response>;
+pub type Endpoint = Arc<dyn Fn(&Request, Context) -> Result<Response> + Send + Sync>;
+pub type SocketHandler = fn(&mut WebSocket, Context) -> Result<()>;
+pub type SocketEndpoint = Arc<dyn Fn(&mut WebSocket, Context) -> Result<()> + Send + Sync>;
 
+/// An endpoint and the methods it accepts, which the router enforces.
 #[derive(Clone)]
-pub struct Router<'a> {
-    root: Node<'a>,
+pub struct Route {
+    pub methods: Vec<Method>,
+    pub endpoint: Endpoint,
 }
 
-impl<'a> Router<'a> {
-    pub fn build() -> Self {
-        let mut root = Node::new();
+pub struct Context {
+    pub params: HashMap<String, String>,
+    pub config: Arc<Config>,
+    pub session: Session,
+    /// The user or token that authenticated the request, on protected paths.
+    pub user: Option<String>,
+}
 
-        for (uri, handler) in ROUTES {
-            let sections = uri.split("/").filter(|s| !s.is_empty()).collect::<Vec<_>>();
-            root.apply(&sections, handler);
+impl Context {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            params: HashMap::new(),
+            config,
+            session: Session::default(),
+            user: None,
         }
+    }
 
-        Self { root }
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.params.get(key)
     }
+}
 
-    pub fn handle(&self, rq: &Request) -> Response {
-        if let Some((handler, context)) = self.get(&rq.uri) {
-            match handler(rq, context) {
-                Ok(response) => response,
-                Err(e) => {
-                    eprintln!("{e:?}");
-                    rq.response(StatusCode::InternalError, None).unwrap()
-                }
+/// The `Allow` header value for a route, which always answers `OPTIONS` itself, and `HEAD`
+/// wherever it answers `GET`.
+fn allow(methods: &[Method]) -> String {
+    let head = (methods.contains(&Method::Get) && !methods.contains(&Method::Head))
+        .then_some(&Method::Head);
+
+    methods
+        .iter()
+        .chain(head)
+        .chain([&Method::Options])
+        .map(Method::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Clone)]
+pub struct Router {
+    root: Node,
+    config: Arc<Config>,
+    sessions: Sessions,
+}
+
+impl Router {
+    pub fn build(config: Arc<Config>) -> Self {
+        let store: Arc<dyn SessionStore> = match config.session_store {
+            SessionBackend::Memory => Arc::new(MemoryStore::new()),
+            SessionBackend::File => {
+                Arc::new(FileStore::new(config.directory.join(SESSION_DIRECTORY)))
             }
-        } else {
-            rq.response(StatusCode::NotFound, None).unwrap()
+        };
+
+        let mut router = Self {
+            root: Node::new(),
+            config: config.clone(),
+            sessions: Sessions::new(store, Duration::from_secs(config.session_ttl)),
+        };
+
+        for (uri, methods, handler) in ROUTES {
+            router.add(uri, methods, Arc::new(handler));
+        }
+
+        for (uri, handler) in SOCKET_ROUTES {
+            router.add_websocket(uri, Arc::new(handler));
+        }
+
+        for mount in &config.mounts {
+            let uri = format!("{}/{{*filename}}", mount.prefix);
+            let methods = mount.methods();
+            let mount = mount.clone();
+            router.add(
+                &uri,
+                methods,
+                Arc::new(move |rq, cx| files::handle(rq, cx, &mount)),
+            );
+        }
+
+        for proxy in &config.proxies {
+            let uri = format!("{}/{{*path}}", proxy.prefix);
+            let proxy = proxy.clone();
+            router.add(
+                &uri,
+                &[
+                    Method::Get,
+                    Method::Head,
+                    Method::Post,
+                    Method::Put,
+                    Method::Patch,
+                    Method::Delete,
+                    Method::Options,
+                ],
+                Arc::new(move |rq, cx| proxy::forward(rq, cx, &proxy)),
+            );
         }
+
+        router
     }
 
-    fn get(&self, uri: &str) -> Option<(RouteHandler, Context)> {
+    pub fn add(&mut self, uri: &str, methods: &[Method], endpoint: Endpoint) {
         let sections = uri.split("/").filter(|s| !s.is_empty()).collect::<Vec<_>>();
-        let mut context = Context::new();
-        let handler = self.root.get(&sections, &mut context);
-        handler.map(|h| (h, context))
+        let route = Route {
+            methods: methods.to_vec(),
+            endpoint,
+        };
+
+        self.root.apply(&sections, route);
+    }
+
+    /// Adds a route that accepts WebSockets, handing each one to `endpoint` after the handshake.
+    pub fn add_websocket(&mut self, uri: &str, endpoint: SocketEndpoint) {
+        self.add(
+            uri,
+            &[Method::Get],
+            Arc::new(move |rq, cx| websocket::accept(rq, cx, endpoint.clone())),
+        );
+    }
+
+    pub fn handle(&self, rq: &Request) -> Response {
+        // Only HTTP/1 heads are refused for this as they're parsed.
+        let Some(segments) = rq.segments() else {
+            return rq.response(StatusCode::BadRequest, None).unwrap();
+        };
+
+        let route = self.get(segments);
+        let methods = route.as_ref().map(|(route, _)| route.methods.as_slice());
+        let cors = &self.config.cors;
+
+        let mut response = if cors.is_preflight(rq) {
+            let mut response = rq.response(StatusCode::NoContent, None).unwrap();
+            cors.preflight(rq, methods.unwrap_or_default(), &mut response.headers);
+            response
+        } else {
+            self.respond(rq, route)
+        };
+
+        cors.decorate(rq, &mut response.headers);
+        response
+    }
+
+    fn respond(&self, rq: &Request, route: Option<(Route, Context)>) -> Response {
+        if rq.expectation_failed() {
+            return rq.response(StatusCode::ExpectationFailed, None).unwrap();
+        }
+
+        let Some((route, mut context)) = route else {
+            return rq.response(StatusCode::NotFound, None).unwrap();
+        };
+
+        if !route.methods.iter().any(|m| m.admits(&rq.method)) {
+            let code = match rq.method {
+                Method::Options => StatusCode::NoContent,
+                _ => StatusCode::MethodNotAllowed,
+            };
+
+            let mut response = rq.response(code, None).unwrap();
+            response.headers.insert("Allow", allow(&route.methods));
+            return response;
+        }
+
+        let auth = &self.config.auth;
+
+        if auth.protects(rq) {
+            context.user = auth.authenticate(rq);
+
+            if context.user.is_none() {
+                let mut response = rq.response(StatusCode::Unauthorized, None).unwrap();
+                auth.challenge(rq, &mut response.headers);
+                return response;
+            }
+        }
+
+        if rq.body().exceeds_limit() {
+            return rq.response(StatusCode::PayloadTooLarge, None).unwrap();
+        }
+
+        match self.dispatch(rq, route.endpoint, context) {
+            Ok(response) => response,
+            Err(Error::Status(code)) => rq.response(code, None).unwrap(),
+            Err(Error::Rejected(code, message)) => {
+                let content = Content::new(MimeType::PlainText, &format!("{message}\n"));
+                rq.response(code, Some(content)).unwrap()
+            }
+            Err(e) => {
+                eprintln!("{e:?}");
+                rq.response(StatusCode::InternalError, None).unwrap()
+            }
+        }
+    }
+
+    // Runs the endpoint inside the request's session. A failed request leaves the session as it
+    // was.
+    fn dispatch(&self, rq: &Request, endpoint: Endpoint, mut context: Context) -> Result<Response> {
+        let session = self.sessions.load(rq)?;
+        context.session = session.clone();
+
+        let mut response = endpoint(rq, context)?;
+        self.sessions.commit(rq, &session, &mut response)?;
+        Ok(response)
+    }
+
+    fn get(&self, segments: &[String]) -> Option<(Route, Context)> {
+        let sections = segments.iter().map(String::as_str).collect::<Vec<_>>();
+        let mut context = Context::new(self.config.clone());
+        let route = self.root.get(&sections, &mut context);
+        route.map(|r| (r, context))
     }
 }
 
 #[derive(Clone)]
-struct Node<'a> {
-    endpoint: Option<RouteHandler>,
-    static_paths: HashMap<&'a str, Node<'a>>,
-    pattern_paths: HashMap<&'a str, (Regex, Node<'a>)>,
+struct Node {
+    route: Option<Route>,
+    static_paths: HashMap<String, Node>,
+    pattern_paths: HashMap<String, (Regex, Node)>,
+    catch_all: Option<(String, Route)>,
 }
 
-impl<'a> Node<'a> {
+impl Node {
     pub fn new() -> Self {
         Self {
-            endpoint: None,
+            route: None,
             static_paths: HashMap::new(),
             pattern_paths: HashMap::new(),
+            catch_all: None,
+        }
+    }
+
+    pub fn get(&self, sections: &[&str], context: &mut Context) -> Option<Route> {
+        if sections.is_empty() && self.route.is_some() {
+            return self.route.clone();
+        }
+
+        if let Some(route) = self.get_child(sections, context) {
+            return Some(route);
         }
+
+        self.catch_all.as_ref().map(|(name, route)| {
+            context.params.insert(name.clone(), sections.join("/"));
+            route.clone()
+        })
     }
 
-    pub fn get(&self, sections: &[&'a str], context: &mut Context) -> Option<RouteHandler> {
+    fn get_child(&self, sections: &[&str], context: &mut Context) -> Option<Route> {
         if sections.is_empty() {
-            self.endpoint
+            None
         } else if let Some(child) = self.static_paths.get(sections[0]) {
             child.get(&sections[1..], context)
         } else {
             for (re, child) in self.pattern_paths.values() {
                 if let Some(caps) = re.captures_iter(sections[0]).next() {
-                    let handler = child.get(&sections[1..], context);
+                    let route = child.get(&sections[1..], context);
 
-                    if handler.is_some() {
+                    if route.is_some() {
                         for group in re.capture_names().flatten() {
-                            context.insert(group.into(), caps[group].into());
+                            context.params.insert(group.into(), caps[group].into());
                         }
 
-                        return handler;
+                        return route;
                     }
                 }
             }
@@ -86,37 +293,170 @@ impl<'a> Node<'a> {
         }
     }
 
-    pub fn apply(&mut self, sections: &[&'a str], handler: RouteHandler) {
+    pub fn apply(&mut self, sections: &[&str], route: Route) {
         if sections.is_empty() {
-            self.endpoint = Some(handler);
+            self.route = Some(route);
+        } else if sections[0].starts_with("{*") && sections[0].ends_with("}") {
+            let name = &sections[0][2..sections[0].len() - 1];
+            self.catch_all = Some((name.into(), route));
         } else if sections[0].starts_with("{") && sections[0].ends_with("}") {
-            self.apply_pattern(sections, handler);
+            self.apply_pattern(sections, route);
         } else {
-            self.apply_static(sections, handler);
+            self.apply_static(sections, route);
         }
     }
 
-    fn apply_pattern(&mut self, sections: &[&'a str], handler: RouteHandler) {
+    fn apply_pattern(&mut self, sections: &[&str], route: Route) {
         if let Some((_, child)) = self.pattern_paths.get_mut(sections[0]) {
-            child.apply(&sections[1..], handler);
+            child.apply(&sections[1..], route);
         } else {
             let mut child = Node::new();
-            child.apply(&sections[1..], handler);
+            child.apply(&sections[1..], route);
 
             let pattern = &sections[0][1..sections[0].len() - 1];
             let re = Regex::new(pattern).unwrap();
 
-            self.pattern_paths.insert(sections[0], (re, child));
+            self.pattern_paths.insert(sections[0].into(), (re, child));
         }
     }
 
-    fn apply_static(&mut self, sections: &[&'a str], handler: RouteHandler) {
+    fn apply_static(&mut self, sections: &[&str], route: Route) {
         if let Some(child) = self.static_paths.get_mut(sections[0]) {
-            child.apply(&sections[1..], handler);
+            child.apply(&sections[1..], route);
         } else {
             let mut child = Node::new();
-            child.apply(&sections[1..], handler);
-            self.static_paths.insert(sections[0], child);
+            child.apply(&sections[1..], route);
+            self.static_paths.insert(sections[0].into(), child);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::AuthRule,
+        conditional::{self, ETag, Validators},
+    };
+
+    fn router(protect: &[&str]) -> Router {
+        let mut config = Config::default();
+
+        for rule in protect {
+            config.auth.rules.push(AuthRule::parse(rule).unwrap());
+        }
+
+        let mut router = Router::build(Arc::new(config));
+        router.add(
+            "/files/{*path}",
+            &[Method::Get],
+            Arc::new(|rq, cx| {
+                let path = cx.get("path").cloned().unwrap_or_default();
+                rq.response(
+                    StatusCode::Ok,
+                    Some(Content::new(MimeType::PlainText, &path)),
+                )
+            }),
+        );
+        router
+    }
+
+    // The response as written, for requests that are parsed.
+    fn written(router: &Router, head: &str) -> String {
+        let (mut reader, mut interim) = (head.as_bytes(), Vec::new());
+        let rq = Request::parse(&mut reader, &mut interim, 1024).unwrap();
+
+        let mut output = Vec::new();
+        router.handle(&rq).write_to(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    // The status a request gets, whether it's refused as it's parsed or by the router.
+    fn status(router: &Router, head: &str) -> u16 {
+        let (mut reader, mut interim) = (head.as_bytes(), Vec::new());
+
+        match Request::parse(&mut reader, &mut interim, 1024) {
+            Ok(rq) => router.handle(&rq).code.code(),
+            Err(Error::Status(code)) => code.code(),
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+
+    #[test]
+    fn routes_dot_segments_as_auth_sees_them() {
+        let protected = router(&["/files"]);
+
+        for path in [
+            "/api/../files/a",
+            "/api/%2e%2e/files/a",
+            "/api/%2E%2E/files/a",
+            "/./files/a",
+        ] {
+            assert_eq!(
+                status(&protected, &format!("GET {path} HTTP/1.1\r\n\r\n")),
+                401
+            );
+        }
+
+        let open = router(&[]);
+        assert_eq!(status(&open, "GET /api/../files/a HTTP/1.1\r\n\r\n"), 200);
+        assert_eq!(status(&open, "GET /files/a/../b HTTP/1.1\r\n\r\n"), 200);
+    }
+
+    #[test]
+    fn refuses_paths_above_the_root() {
+        let router = router(&[]);
+
+        for path in ["/..", "/../files/a", "/%2e%2e/files/a", "/files/../../a"] {
+            assert_eq!(
+                status(&router, &format!("GET {path} HTTP/1.1\r\n\r\n")),
+                400
+            );
+        }
+    }
+
+    #[test]
+    fn answers_head_wherever_get_is_answered() {
+        let router = router(&[]);
+
+        let output = written(&router, "HEAD /files/abc HTTP/1.1\r\n\r\n");
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.contains("Content-Length: 3\r\n"));
+        assert!(output.ends_with("\r\n\r\n"));
+
+        let output = written(&router, "HEAD /echo HTTP/1.1\r\n\r\n");
+        assert!(output.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(output.contains("Allow: POST, OPTIONS\r\n"));
+
+        let output = written(&router, "OPTIONS /files/abc HTTP/1.1\r\n\r\n");
+        assert!(output.contains("Allow: GET, HEAD, OPTIONS\r\n"));
+    }
+
+    #[test]
+    fn answers_head_preconditions_like_get() {
+        let mut router = router(&[]);
+        router.add(
+            "/tagged",
+            &[Method::Get, Method::Put],
+            Arc::new(|rq, _| {
+                let validators = Validators {
+                    etag: ETag {
+                        weak: false,
+                        tag: "abc".into(),
+                    },
+                    last_modified: None,
+                };
+
+                match conditional::evaluate(rq, Some(&validators)) {
+                    Some(code) => rq.response(code, None),
+                    None => rq.response(StatusCode::Ok, None),
+                }
+            }),
+        );
+
+        for (method, code) in [("GET", 304), ("HEAD", 304), ("PUT", 412)] {
+            let head = format!("{method} /tagged HTTP/1.1\r\nIf-None-Match: \"abc\"\r\n\r\n");
+            assert_eq!(status(&router, &head), code, "{method}");
         }
     }
 }
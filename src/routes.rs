@@ -1,51 +1,28 @@
-use std::{
-    env,
-    fs::{self, File},
-    io::Write,
-    path::{self, Path},
-};
+use std::{thread, time::Duration};
 
 use crate::{
+    form::{Form, FromForm},
     prelude::*,
-    router::{Context, RouteHandler},
+    router::{Context, RouteHandler, SocketHandler},
+    sse::{self, Event},
+    websocket::{Message, WebSocket},
 };
 
-pub const ROUTES: [(&str, RouteHandler); 4] = [
-    ("/", home),
-    (r#"/echo/{(?<message>\w+)}"#, echo),
-    ("/user-agent", user_agent),
-    (r#"/files/{(?<filename>[\w\-_\.]+)}"#, files),
+pub const ROUTES: [(&str, &[Method], RouteHandler); 5] = [
+    ("/", &[Method::Get], home),
+    ("/echo", &[Method::Post], echo_form),
+    (r#"/echo/{(?<message>\w+)}"#, &[Method::Get], echo),
+    ("/user-agent", &[Method::Get], user_agent),
+    ("/events", &[Method::Get], events),
 ];
 
-fn method_guard(rq: &Request, methods: &[Method]) -> Option<Result<Response>> {
-    if methods.contains(&rq.method) {
-        None
-    } else {
-        Some(rq.response(StatusCode::MethodNotAllowed, None))
-    }
-}
-
-fn path_guard(rq: &Request, path: &Path) -> Result<Option<Result<Response>>> {
-    if path::absolute(path)?.starts_with(env::current_dir()?) {
-        Ok(None)
-    } else {
-        Ok(Some(rq.response(StatusCode::Forbidden, None)))
-    }
-}
+pub const SOCKET_ROUTES: [(&str, SocketHandler); 1] = [("/ws", echo_socket)];
 
 fn home(rq: &Request, _: Context) -> Result<Response> {
-    if let Some(reponse) = method_guard(rq, &[Method::Get]) {
-        return reponse;
-    }
-
     rq.response(StatusCode::Ok, None)
 }
 
 fn echo(rq: &Request, cx: Context) -> Result<Response> {
-    if let Some(reponse) = method_guard(rq, &[Method::Get]) {
-        return reponse;
-    }
-
     if let Some(message) = cx.get("message") {
         let content = Content::new(MimeType::PlainText, message);
         rq.response(StatusCode::Ok, Some(content))
@@ -54,60 +31,99 @@ fn echo(rq: &Request, cx: Context) -> Result<Response> {
     }
 }
 
-fn user_agent(rq: &Request, _: Context) -> Result<Response> {
-    if let Some(reponse) = method_guard(rq, &[Method::Get]) {
-        return reponse;
-    }
+#[cfg_attr(feature = "json", derive(serde::Deserialize))]
+struct EchoForm {
+    message: String,
+    #[cfg_attr(feature = "json", serde(default = "EchoForm::default_repeat"))]
+    repeat: usize,
+}
 
-    if let Some(user_agent) = rq.headers.get("User-Agent") {
-        let content = Content::new(MimeType::PlainText, user_agent);
-        rq.response(StatusCode::Ok, Some(content))
-    } else {
-        Err("Failed to get user agent from request headers.".into())
+impl EchoForm {
+    fn default_repeat() -> usize {
+        1
     }
 }
 
-fn files(rq: &Request, cx: Context) -> Result<Response> {
-    match rq.method {
-        Method::Get => serve_file(rq, cx),
-        Method::Post => upload_file(rq, cx),
+impl FromForm for EchoForm {
+    fn from_form(form: &Form) -> Result<Self> {
+        Ok(Self {
+            message: form.require("message")?.into(),
+            repeat: match form.contains("repeat") {
+                true => form.parse_value("repeat")?,
+                false => Self::default_repeat(),
+            },
+        })
     }
 }
 
-fn serve_file(rq: &Request, cx: Context) -> Result<Response> {
-    let path = Path::new(cx.get("filename").ok_or(Error::Generic(
-        "Failed to get filename from context.".into(),
-    ))?);
-
-    if !path.exists() {
-        return rq.response(StatusCode::NotFound, None);
-    }
+fn echo_form(rq: &Request, _: Context) -> Result<Response> {
+    let form: EchoForm = match &rq.content_type {
+        #[cfg(feature = "json")]
+        Some(ct) if ct.mime_type.is_json() => rq.json()?,
+        _ => rq.form()?,
+    };
 
-    if let Some(reponse) = path_guard(rq, path)? {
-        return reponse;
+    if form.repeat > 100 {
+        return rq.response(StatusCode::BadRequest, None);
     }
 
-    let file = fs::read_to_string(path)?;
-    let content = Content::new(MimeType::OctetStream, &file);
+    let content = Content::new(MimeType::PlainText, &form.message.repeat(form.repeat));
     rq.response(StatusCode::Ok, Some(content))
 }
 
-fn upload_file(rq: &Request, cx: Context) -> Result<Response> {
-    let path = Path::new(cx.get("filename").ok_or(Error::Generic(
-        "Failed to get filename from context.".into(),
-    ))?);
+fn user_agent(rq: &Request, _: Context) -> Result<Response> {
+    if let Some(user_agent) = rq.header("User-Agent") {
+        let json = format!("{{\"user_agent\":{}}}", escape_json(user_agent));
+        let html = format!("<!DOCTYPE html>\n<p>{}</p>\n", escape_html(user_agent));
 
-    if let Some(reponse) = path_guard(rq, path)? {
-        return reponse;
-    }
+        let offers = vec![
+            Content::new(MimeType::PlainText, user_agent),
+            Content::new(MimeType::Json, &json),
+            Content::new(MimeType::Html, &html),
+        ];
 
-    let bytes = if let Some(content) = &rq.content {
-        content.body.as_bytes()
+        rq.negotiate(StatusCode::Ok, offers)
     } else {
-        return Err("Request did not contain content.".into());
+        Err("Failed to get user agent from request headers.".into())
+    }
+}
+
+// Counts once a second for as long as the client listens, carrying on from where a reconnecting
+// client left off.
+fn events(rq: &Request, cx: Context) -> Result<Response> {
+    let start = match rq.last_event_id() {
+        Some(id) => {
+            id.parse::<u64>()
+                .map_err(|_| Error::Status(StatusCode::BadRequest))?
+                + 1
+        }
+        None => 0,
     };
 
-    let mut file = File::create(path)?;
-    file.write_all(bytes)?;
-    rq.response(StatusCode::Created, None)
+    let (sender, stream) = sse::channel(Duration::from_secs(cx.config.heartbeat_interval));
+
+    thread::spawn(move || {
+        for n in start.. {
+            let event = Event::new(&n.to_string()).event("tick").id(&n.to_string());
+
+            if sender.send(event).is_err() {
+                break;
+            }
+
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
+
+    Response::builder(rq).events(stream).build()
+}
+
+fn echo_socket(socket: &mut WebSocket, _: Context) -> Result<()> {
+    while let Some(message) = socket.receive()? {
+        match message {
+            Message::Text(_) | Message::Binary(_) => socket.send(message)?,
+            _ => {}
+        }
+    }
+
+    Ok(())
 }
@@ -0,0 +1,300 @@
+use std::{
+    fs::{self, FileType, Metadata},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone)]
+pub struct SandboxOptions {
+    pub symlinks: bool,
+    pub dotfiles: bool,
+    pub special_files: bool,
+    /// Names that are never served, wherever they appear.
+    pub reserved: Vec<String>,
+}
+
+impl Default for SandboxOptions {
+    fn default() -> Self {
+        Self {
+            symlinks: true,
+            dotfiles: true,
+            special_files: false,
+            reserved: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Resolved {
+    Found { path: PathBuf, metadata: Metadata },
+    Missing { path: PathBuf },
+    Forbidden,
+}
+
+/// Confines file operations to a root directory. Paths are resolved one component at a time so
+/// that symlinks are followed (or refused) before anything beneath them is looked at, and nothing
+/// outside the root is ever probed for existence.
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    root: PathBuf,
+    options: SandboxOptions,
+}
+
+impl Sandbox {
+    /// Creates a sandbox around a root that has already been canonicalized.
+    pub fn new(root: &Path, options: SandboxOptions) -> Self {
+        Self {
+            root: root.into(),
+            options,
+        }
+    }
+
+    /// Creates the root directory if needed and canonicalizes it.
+    pub fn open(root: &Path, options: SandboxOptions) -> Result<Self> {
+        if !root.exists() {
+            fs::create_dir_all(root)?;
+        }
+
+        Ok(Self {
+            root: root.canonicalize()?,
+            options,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn permits_name(&self, name: &str) -> bool {
+        !name.is_empty()
+            && name != ".."
+            && !name.contains(['\\', '\0'])
+            && (self.options.dotfiles || !name.starts_with('.'))
+            && !self.options.reserved.iter().any(|r| r == name)
+    }
+
+    fn permits_type(&self, file_type: FileType) -> bool {
+        (self.options.symlinks || !file_type.is_symlink())
+            && (self.options.special_files
+                || file_type.is_file()
+                || file_type.is_dir()
+                || file_type.is_symlink())
+    }
+
+    /// Whether a directory entry should be visible, e.g. in a listing.
+    pub fn permits(&self, name: &str, file_type: FileType) -> bool {
+        self.permits_name(name) && self.permits_type(file_type)
+    }
+
+    /// Whether a canonical path lies within the root without passing through forbidden names.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.root).is_ok_and(|relative| {
+            relative
+                .iter()
+                .all(|name| name.to_str().is_some_and(|name| self.permits_name(name)))
+        })
+    }
+
+    /// Resolves a `/`-separated path relative to the root.
+    pub fn resolve(&self, relative: &str) -> Result<Resolved> {
+        let names = relative
+            .split('/')
+            .filter(|s| !s.is_empty() && *s != ".")
+            .collect::<Vec<_>>();
+
+        if !names.iter().all(|name| self.permits_name(name)) {
+            return Ok(Resolved::Forbidden);
+        }
+
+        let mut path = self.root.clone();
+
+        for (i, name) in names.iter().enumerate() {
+            path.push(name);
+
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    path.extend(&names[i + 1..]);
+                    return Ok(Resolved::Missing { path });
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            if !self.permits_type(metadata.file_type()) {
+                return Ok(Resolved::Forbidden);
+            }
+
+            if metadata.file_type().is_symlink() {
+                match path.canonicalize() {
+                    Ok(target) if self.contains(&target) => path = target,
+                    _ => return Ok(Resolved::Forbidden),
+                }
+            }
+
+            // Nothing can lie beneath a file, just as nothing can beneath a missing directory.
+            if i + 1 < names.len() && !path.is_dir() {
+                path.extend(&names[i + 1..]);
+                return Ok(Resolved::Missing { path });
+            }
+        }
+
+        let metadata = fs::metadata(&path)?;
+
+        if !self.permits_type(metadata.file_type()) {
+            return Ok(Resolved::Forbidden);
+        }
+
+        Ok(Resolved::Found { path, metadata })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::{fs::symlink, net::UnixListener};
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    // A root holding `a/b.txt` and `.env`, beside a `secret.txt` outside it.
+    fn fixture() -> (TempDir, Sandbox) {
+        let dir = TempDir::new();
+        dir.write("root/a/b.txt", "b");
+        dir.write("root/.env", "env");
+        dir.write("secret.txt", "secret");
+
+        let sandbox = Sandbox::open(&dir.path().join("root"), SandboxOptions::default()).unwrap();
+        (dir, sandbox)
+    }
+
+    fn is_forbidden(sandbox: &Sandbox, relative: &str) -> bool {
+        matches!(sandbox.resolve(relative).unwrap(), Resolved::Forbidden)
+    }
+
+    // Where a path resolves to, whether or not anything is there yet.
+    fn resolved(sandbox: &Sandbox, relative: &str) -> PathBuf {
+        match sandbox.resolve(relative).unwrap() {
+            Resolved::Found { path, .. } | Resolved::Missing { path } => path,
+            Resolved::Forbidden => panic!("{relative} is forbidden"),
+        }
+    }
+
+    #[test]
+    fn finds_files_beneath_the_root() {
+        let (dir, sandbox) = fixture();
+
+        assert!(matches!(
+            sandbox.resolve("a//./b.txt").unwrap(),
+            Resolved::Found { path, .. } if path == dir.path().join("root/a/b.txt")
+        ));
+        assert!(matches!(
+            sandbox.resolve("a/c.txt").unwrap(),
+            Resolved::Missing { .. }
+        ));
+        assert!(matches!(
+            sandbox.resolve("a/b.txt/c").unwrap(),
+            Resolved::Missing { .. }
+        ));
+    }
+
+    #[test]
+    fn refuses_parent_segments() {
+        let (_dir, sandbox) = fixture();
+
+        for relative in [
+            "..",
+            "../secret.txt",
+            "a/../../secret.txt",
+            "a/..",
+            "a\\..\\b",
+        ] {
+            assert!(is_forbidden(&sandbox, relative), "{relative}");
+        }
+    }
+
+    #[test]
+    fn keeps_encoded_and_absolute_paths_beneath_the_root() {
+        let (_dir, sandbox) = fixture();
+
+        // Decoding is the router's, so escapes that reach the sandbox are only ever names.
+        for relative in [
+            "%2e%2e/secret.txt",
+            "/etc/passwd",
+            "//etc/passwd",
+            "a/%2F..",
+        ] {
+            assert!(
+                resolved(&sandbox, relative).starts_with(sandbox.root()),
+                "{relative}"
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_symlinks_out_of_the_root() {
+        let (dir, sandbox) = fixture();
+        let root = sandbox.root();
+
+        symlink(dir.path().join("secret.txt"), root.join("secret")).unwrap();
+        symlink(dir.path(), root.join("up")).unwrap();
+        symlink("a/b.txt", root.join("inside")).unwrap();
+        symlink(".env", root.join("env")).unwrap();
+
+        assert!(is_forbidden(&sandbox, "secret"));
+        assert!(is_forbidden(&sandbox, "up/secret.txt"));
+        assert!(matches!(
+            sandbox.resolve("inside").unwrap(),
+            Resolved::Found { path, .. } if path == root.join("a/b.txt")
+        ));
+
+        let options = SandboxOptions {
+            symlinks: false,
+            dotfiles: false,
+            ..SandboxOptions::default()
+        };
+        let strict = Sandbox::new(root, options);
+        assert!(is_forbidden(&strict, "inside"));
+
+        // A link may not lead to a name that couldn't be asked for directly.
+        let options = SandboxOptions {
+            dotfiles: false,
+            ..SandboxOptions::default()
+        };
+        assert!(is_forbidden(&Sandbox::new(root, options), "env"));
+    }
+
+    #[test]
+    fn refuses_dotfiles_and_reserved_names_when_asked() {
+        let (_dir, sandbox) = fixture();
+        assert!(!is_forbidden(&sandbox, ".env"));
+
+        let options = SandboxOptions {
+            dotfiles: false,
+            reserved: vec!["a".into()],
+            ..SandboxOptions::default()
+        };
+        let strict = Sandbox::new(sandbox.root(), options);
+
+        for relative in [".env", ".git/config", "a/b.txt", "x/a"] {
+            assert!(is_forbidden(&strict, relative), "{relative}");
+        }
+    }
+
+    #[test]
+    fn refuses_files_that_are_not_regular() {
+        let (_dir, sandbox) = fixture();
+        let _socket = UnixListener::bind(sandbox.root().join("socket")).unwrap();
+
+        assert!(is_forbidden(&sandbox, "socket"));
+
+        let options = SandboxOptions {
+            special_files: true,
+            ..SandboxOptions::default()
+        };
+        assert!(!is_forbidden(
+            &Sandbox::new(sandbox.root(), options),
+            "socket"
+        ));
+    }
+}
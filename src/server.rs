@@ -1,70 +1,443 @@
-use std::{env, fs, io::Write, net::TcpListener, path::PathBuf, thread};
+use std::{
+    cell::RefCell,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
 
-use crate::{prelude::*, router::Router};
+use crate::{
+    config::Config, headers::Headers, http2, prelude::*, request::Peer, router::Router,
+    sandbox::Sandbox,
+};
+
+// A read that gave up waiting, which platforms report under different kinds.
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Lets a stream be read through a `BufReader` while interim and final responses are written to
+/// it, as `&TcpStream` allows but a TLS stream does not.
+struct Shared<S>(RefCell<S>);
+
+impl<S: Read> Read for &Shared<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+impl<S: Write> Write for &Shared<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
 
 pub struct Server {
-    pub cwd: Option<PathBuf>,
-    pub host: String,
-    pub port: u32,
+    pub config: Arc<Config>,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
 }
 
 impl Server {
-    pub fn new() -> Result<Self> {
-        let mut server = Self {
-            cwd: None,
-            host: "127.0.0.1".into(),
-            port: 4221,
+    pub fn new(mut config: Config) -> Result<Self> {
+        for mount in config.mounts.iter_mut() {
+            let sandbox = Sandbox::open(&mount.directory, mount.sandbox.clone())?;
+            mount.directory = sandbox.root().into();
+        }
+
+        #[cfg(feature = "tls")]
+        let tls = match config.tls.is_enabled() {
+            true => Some(crate::tls::server_config(&config.tls)?),
+            false => None,
         };
 
-        let args = env::args().zip(env::args().skip(1));
+        Ok(Self {
+            config: Arc::new(config),
+            #[cfg(feature = "tls")]
+            tls,
+        })
+    }
 
-        for (a, b) in args {
-            match (a.as_str(), b.as_str()) {
-                ("--directory", path) => {
-                    server.cwd = Some(path.into());
-                }
-                ("--host", host) => {
-                    server.host = host.to_string();
+    pub fn run(&self) -> Result<()> {
+        let router = Router::build(self.config.clone());
+
+        #[cfg(feature = "tls")]
+        if let Some(tls) = self.tls.clone() {
+            let listener =
+                TcpListener::bind(format!("{}:{}", self.config.host, self.config.tls.port))?;
+            let router = router.clone();
+            let config = self.config.clone();
+
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let router = router.clone();
+                    let config = config.clone();
+                    let tls = tls.clone();
+
+                    thread::spawn(move || {
+                        let result = stream
+                            .map_err(Error::from)
+                            .and_then(|stream| serve_tls(&router, &config, &tls, stream));
+
+                        if let Err(e) = result {
+                            eprintln!("{e}");
+                        }
+                    });
                 }
-                ("--port", port) => {
-                    server.port = port.parse::<u32>()?;
+            });
+        }
+
+        let listener = TcpListener::bind(format!("{}:{}", self.config.host, self.config.port))?;
+        let redirect = self.config.https_redirect && self.config.tls.is_enabled();
+
+        for stream in listener.incoming() {
+            let router = router.clone();
+            let config = self.config.clone();
+
+            thread::spawn(move || {
+                let result = stream
+                    .map_err(Error::from)
+                    .and_then(|stream| serve_plaintext(&router, &config, stream, redirect));
+
+                if let Err(e) = result {
+                    eprintln!("{e}");
                 }
-                _ => {}
-            }
+            });
         }
 
-        Ok(server)
+        Ok(())
     }
+}
 
-    pub fn run(&self) -> Result<()> {
-        if let Some(cwd) = &self.cwd {
-            if !cwd.exists() {
-                fs::create_dir_all(cwd)?;
-            }
+// Whether a `Host` field names only a host and port, so that nothing in it, such as `@` or `/`,
+// can point a redirect built from it somewhere else.
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._:[]".contains(&b))
+}
 
-            env::set_current_dir(cwd)?;
+/// Sends a plaintext request to the same host and path over HTTPS.
+fn redirect_to_https(rq: &Request, config: &Config) -> Result<Response> {
+    if rq.header("Host").is_some_and(|host| !is_valid_host(host)) {
+        return rq.response(StatusCode::BadRequest, None);
+    }
+
+    let host = rq.header("Host").map_or(config.host.as_str(), |host| {
+        // Strip the port, minding bracketed IPv6 addresses.
+        match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => name,
+            _ => host,
         }
+    });
 
-        let listener = TcpListener::bind(format!("{}:{}", self.host, self.port))?;
-        let router = Router::build();
+    let location = match config.tls.port {
+        443 => format!("https://{host}{}", rq.uri),
+        port => format!("https://{host}:{port}{}", rq.uri),
+    };
 
-        for stream in listener.incoming() {
-            let router = router.clone();
+    Response::builder(rq)
+        .status(StatusCode::PermanentRedirect)
+        .location(&location)
+        .build()
+}
 
-            thread::spawn(move || {
-                println!("{:-<30}", "");
-                let mut stream = stream.unwrap();
+fn serve_plaintext(
+    router: &Router,
+    config: &Config,
+    stream: TcpStream,
+    redirect: bool,
+) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(config.read_timeout)))?;
 
-                let request = Request::parse(&mut stream).unwrap();
-                println!("{request}");
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let peer = Peer {
+        addr: stream.peer_addr()?,
+        secure: false,
+    };
 
-                let mut response = router.handle(&request);
-                println!("{response}");
+    let preface = match http2::is_preface(&mut reader) {
+        Ok(preface) => preface,
+        Err(e) if is_timeout(&e) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
 
-                stream.write_all(&response.encode()).unwrap();
-            });
+    let mut upgrade = None;
+
+    // A listener that only redirects to HTTPS has nothing to serve over HTTP/2.
+    if redirect || !preface {
+        let (interim, writer) = (&mut &stream, &mut &stream);
+        upgrade = serve(
+            router,
+            config,
+            &stream,
+            &mut reader,
+            interim,
+            writer,
+            peer,
+            redirect,
+        )?;
+
+        if upgrade.is_none() {
+            return Ok(());
         }
+    }
 
-        Ok(())
+    // A client quiet for this long while nothing is being answered is sent GOAWAY.
+    stream.set_read_timeout(Some(Duration::from_secs(config.read_timeout)))?;
+    stream.set_nodelay(true)?;
+    http2::serve(router, config, &mut reader, Box::new(stream), upgrade, peer)
+}
+
+#[cfg(feature = "tls")]
+fn serve_tls(
+    router: &Router,
+    config: &Config,
+    tls: &Arc<rustls::ServerConfig>,
+    stream: TcpStream,
+) -> Result<()> {
+    let peer = Peer {
+        addr: stream.peer_addr()?,
+        secure: true,
+    };
+
+    // Timeouts set through a clone apply to the socket the TLS stream reads from.
+    let socket = stream.try_clone()?;
+    socket.set_read_timeout(Some(Duration::from_secs(config.read_timeout)))?;
+    let mut stream = crate::tls::accept(tls, stream)?;
+
+    // The read timeout stays, for HTTP/2 to send GOAWAY to clients that go quiet.
+    if crate::tls::is_http2(&stream) {
+        let (reader, writer) = crate::tls::split(stream)?;
+        let mut reader = BufReader::new(reader);
+        return http2::serve(router, config, &mut reader, Box::new(writer), None, peer);
+    }
+
+    {
+        let shared = Shared(RefCell::new(&mut stream));
+        let mut reader = BufReader::new(&shared);
+        let (interim, writer) = (&mut &shared, &mut &shared);
+        serve(
+            router,
+            config,
+            &socket,
+            &mut reader,
+            interim,
+            writer,
+            peer,
+            false,
+        )?;
+    }
+
+    crate::tls::close(&mut stream)
+}
+
+/// Answers a request that couldn't be read. Where it ends is unknown, so the connection is
+/// closed rather than read on from there.
+fn refuse(writer: &mut dyn Write, code: StatusCode) -> Result<Next> {
+    let mut response = Response {
+        protocol: Protocol::Http11,
+        code,
+        payload: None,
+        headers: Headers::new(),
+        encoding: None,
+        omit_body: false,
+    };
+
+    response.headers.insert("Connection", "close");
+    println!("{response}");
+    response.write_to(writer)?;
+    Ok(Next::Close)
+}
+
+/// What becomes of a connection once a request on it has been answered.
+enum Next {
+    Request,
+    Close,
+    Http2(http2::Upgrade),
+}
+
+/// Serves HTTP/1 requests in the order they arrive, writing interim responses to `interim` and
+/// final ones to `writer`, for as long as the connection stays open. If the client may and does
+/// ask to switch to `h2c`, the switch is returned instead. Timeouts are set on `socket`, which
+/// `reader` reads from.
+#[allow(clippy::too_many_arguments)]
+fn serve(
+    router: &Router,
+    config: &Config,
+    socket: &TcpStream,
+    reader: &mut dyn BufRead,
+    interim: &mut dyn Write,
+    writer: &mut dyn Write,
+    peer: Peer,
+    redirect: bool,
+) -> Result<Option<http2::Upgrade>> {
+    let read_timeout = Duration::from_secs(config.read_timeout);
+    let keep_alive_timeout = Duration::from_secs(config.keep_alive_timeout);
+
+    // Pipelined requests wait in `reader`, so the connection is only done once it's drained. One
+    // that stays idle for too long is closed.
+    loop {
+        match reader.fill_buf() {
+            Ok([]) => break,
+            Ok(_) => {}
+            Err(e) if is_timeout(&e) => break,
+            Err(e) => return Err(e.into()),
+        }
+
+        socket.set_read_timeout(Some(read_timeout))?;
+
+        match serve_request(
+            router, config, socket, reader, interim, writer, peer, redirect,
+        )? {
+            Next::Request => socket.set_read_timeout(Some(keep_alive_timeout))?,
+            Next::Close => break,
+            Next::Http2(upgrade) => return Ok(Some(upgrade)),
+        }
+    }
+
+    Ok(None)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn serve_request(
+    router: &Router,
+    config: &Config,
+    socket: &TcpStream,
+    reader: &mut dyn BufRead,
+    interim: &mut dyn Write,
+    writer: &mut dyn Write,
+    peer: Peer,
+    redirect: bool,
+) -> Result<Next> {
+    println!("{:-<30}", "");
+    let mut request = match Request::parse(reader, interim, config.max_body_size) {
+        Ok(request) => request,
+        Err(Error::Status(code)) => return refuse(writer, code),
+        Err(Error::IO(e)) if is_timeout(&e) => return refuse(writer, StatusCode::RequestTimeout),
+        Err(e) => return Err(e),
+    };
+    request.peer = Some(peer);
+    println!("{request}");
+
+    // Only plaintext connections can switch to `h2c`, and not if they're redirected to HTTPS.
+    let h2c = !peer.secure && !redirect;
+
+    if let Some(upgrade) = h2c.then(|| http2::Upgrade::offered(&request)).flatten() {
+        let mut response = Response::builder(&request)
+            .status(StatusCode::SwitchingProtocols)
+            .header("Connection", "Upgrade")
+            .header("Upgrade", "h2c")
+            .build()?;
+
+        println!("{response}");
+        response.write_to(writer)?;
+        return Ok(Next::Http2(upgrade));
+    }
+
+    let mut response = match redirect {
+        true => redirect_to_https(&request, config)?,
+        false => router.handle(&request),
+    };
+    println!("{response}");
+
+    let websocket = response.take_websocket();
+
+    // The next request starts after this one's body, which a client that was refused it, or is
+    // still waiting to be asked, won't send.
+    let body_skipped = {
+        let body = request.body();
+        body.awaiting_continue() || body.exceeds_limit()
+    };
+
+    let keep_alive = request.keep_alive() && !body_skipped && !response.is_close_delimited();
+
+    match (keep_alive, request.protocol.is_persistent()) {
+        (true, false) => response.headers.insert("Connection", "keep-alive"),
+        (false, true) if websocket.is_none() => response.headers.insert("Connection", "close"),
+        _ => {}
+    }
+
+    response.write_to(writer)?;
+
+    if let Some(websocket) = websocket {
+        drop(request);
+
+        // Either end of a WebSocket may stay quiet for as long as it likes.
+        socket.set_read_timeout(None)?;
+        websocket.run(reader, writer, config.max_message_size)?;
+        return Ok(Next::Close);
+    }
+
+    let mut body = request.body();
+
+    if keep_alive {
+        body.drain()?;
+        return Ok(Next::Request);
+    }
+
+    // Consume any unread body so closing the socket doesn't reset the connection.
+    if !body_skipped {
+        let _ = body.drain();
+    }
+
+    Ok(Next::Close)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redirect(fields: &str) -> Response {
+        let config = Config {
+            host: "0.0.0.0".into(),
+            ..Config::default()
+        };
+
+        let head = format!("GET /a/b?c=d HTTP/1.1\r\n{fields}\r\n");
+        let mut reader = head.as_bytes();
+        let mut interim = Vec::new();
+        let rq = Request::parse(&mut reader, &mut interim, 0).unwrap();
+        redirect_to_https(&rq, &config).unwrap()
+    }
+
+    #[test]
+    fn redirects_to_the_same_host_over_https() {
+        let response = redirect("Host: a.example:8080\r\n");
+        assert_eq!(response.code.code(), 308);
+        assert_eq!(
+            response.headers.get("Location").unwrap(),
+            "https://a.example:4443/a/b?c=d"
+        );
+
+        let response = redirect("Host: [::1]:8080\r\n");
+        assert_eq!(
+            response.headers.get("Location").unwrap(),
+            "https://[::1]:4443/a/b?c=d"
+        );
+
+        let response = redirect("");
+        assert_eq!(
+            response.headers.get("Location").unwrap(),
+            "https://0.0.0.0:4443/a/b?c=d"
+        );
+    }
+
+    #[test]
+    fn refuses_hosts_that_would_redirect_elsewhere() {
+        for host in ["a.example@b.example", "b.example/x", "a.example\\b", "a b"] {
+            let response = redirect(&format!("Host: {host}\r\n"));
+            assert_eq!(response.code.code(), 400, "{host:?}");
+            assert!(!response.headers.contains("Location"));
+        }
     }
 }
@@ -0,0 +1,599 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+use crate::{
+    cookie::{SameSite, SetCookie},
+    files::{self, WriteMode},
+    prelude::*,
+    url,
+};
+
+/// The directory under `--directory` that the file store keeps sessions in. It is reserved, so
+/// mounts never serve it.
+pub const SESSION_DIRECTORY: &str = ".sessions";
+
+const COOKIE_NAME: &str = "session";
+const ID_BYTES: usize = 32;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub type SessionData = HashMap<String, String>;
+
+/// Where session data is kept between requests.
+pub trait SessionStore: Send + Sync {
+    /// The data of a session that exists and hasn't expired.
+    fn load(&self, id: &str) -> Result<Option<SessionData>>;
+    fn save(&self, id: &str, data: &SessionData, expires: SystemTime) -> Result<()>;
+    fn remove(&self, id: &str) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionBackend {
+    Memory,
+    File,
+}
+
+impl fmt::Display for SessionBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Memory => write!(f, "memory"),
+            Self::File => write!(f, "file"),
+        }
+    }
+}
+
+impl TryFrom<&str> for SessionBackend {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        match s {
+            "memory" => Ok(Self::Memory),
+            "file" => Ok(Self::File),
+            _ => Err(Error::Generic("Failed to parse session store.".into())),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Whether it's time to sweep expired sessions, which is done at most once per interval.
+fn should_prune(last: &Mutex<SystemTime>, now: SystemTime) -> bool {
+    let mut last = lock(last);
+
+    if now.duration_since(*last).unwrap_or_default() < PRUNE_INTERVAL {
+        return false;
+    }
+
+    *last = now;
+    true
+}
+
+#[derive(Debug)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (SessionData, SystemTime)>>,
+    pruned: Mutex<SystemTime>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            pruned: Mutex::new(UNIX_EPOCH),
+        }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Result<Option<SessionData>> {
+        let sessions = lock(&self.sessions);
+
+        Ok(sessions
+            .get(id)
+            .filter(|(_, expires)| *expires > SystemTime::now())
+            .map(|(data, _)| data.clone()))
+    }
+
+    fn save(&self, id: &str, data: &SessionData, expires: SystemTime) -> Result<()> {
+        let now = SystemTime::now();
+        let mut sessions = lock(&self.sessions);
+
+        if should_prune(&self.pruned, now) {
+            sessions.retain(|_, (_, expires)| *expires > now);
+        }
+
+        sessions.insert(id.into(), (data.clone(), expires));
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        lock(&self.sessions).remove(id);
+        Ok(())
+    }
+}
+
+/// Keeps each session in a file named after its ID, holding the expiry time in seconds on the
+/// first line and the data, urlencoded, on the second.
+#[derive(Debug)]
+pub struct FileStore {
+    directory: PathBuf,
+    pruned: Mutex<SystemTime>,
+}
+
+impl FileStore {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            pruned: Mutex::new(UNIX_EPOCH),
+        }
+    }
+
+    /// Reads a session file. One that's corrupt reads as expired, so that it's removed like one.
+    fn read(&self, id: &str) -> Result<Option<(SessionData, SystemTime)>> {
+        let contents = match fs::read(self.directory.join(id)) {
+            Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let (expires, data) = contents.split_once('\n').unwrap_or((&contents, ""));
+        let expires = expires
+            .trim()
+            .parse()
+            .ok()
+            .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+            .unwrap_or(UNIX_EPOCH);
+        let data = url::parse_query(data.trim_end()).into_iter().collect();
+
+        Ok(Some((data, expires)))
+    }
+
+    fn prune(&self, now: SystemTime) -> Result<()> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(id) = name.to_str().filter(|id| is_valid_id(id)) else {
+                continue;
+            };
+
+            // Sessions that can't be read are left for the next request that uses them.
+            if let Ok(Some((_, expires))) = self.read(id) {
+                if expires <= now {
+                    self.remove(id)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> Result<Option<SessionData>> {
+        match self.read(id)? {
+            Some((data, expires)) if expires > SystemTime::now() => Ok(Some(data)),
+            Some(_) => {
+                self.remove(id)?;
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData, expires: SystemTime) -> Result<()> {
+        let now = SystemTime::now();
+
+        if should_prune(&self.pruned, now) {
+            self.prune(now)?;
+        }
+
+        let secs = expires
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let data = data
+            .iter()
+            .map(|(k, v)| format!("{}={}", url::percent_encode(k), url::percent_encode(v)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let contents = format!("{secs}\n{data}\n");
+        let path = self.directory.join(id);
+        files::write_atomically(&path, &mut contents.as_bytes(), WriteMode::CreateOrReplace)?;
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        match fs::remove_file(self.directory.join(id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// IDs come from clients, so anything that isn't shaped like one we issued is ignored before it
+// reaches a store, let alone the file system.
+fn is_valid_id(id: &str) -> bool {
+    id.len() == URL_SAFE_NO_PAD.encode([0; ID_BYTES]).len()
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn generate_id() -> Result<String> {
+    let mut bytes = [0; ID_BYTES];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| Error::Generic(format!("Failed to generate session ID: {e}.")))?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+#[derive(Debug, Default)]
+struct State {
+    id: Option<String>,
+    data: SessionData,
+    rotate: bool,
+    destroyed: bool,
+}
+
+/// The current request's session. Clones share the same data, so changes a handler makes are
+/// seen when the response is committed.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    state: Arc<Mutex<State>>,
+}
+
+impl Session {
+    pub fn get(&self, key: &str) -> Option<String> {
+        lock(&self.state).data.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: &str, value: &str) {
+        lock(&self.state).data.insert(key.into(), value.into());
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        lock(&self.state).data.remove(key)
+    }
+
+    /// Whether the client presented the ID of a stored session.
+    pub fn is_established(&self) -> bool {
+        lock(&self.state).id.is_some()
+    }
+
+    /// Moves the session to a fresh ID when the response is committed, keeping its data. Call
+    /// this whenever privileges change, such as on login, so that an ID planted before then is
+    /// of no use to an attacker.
+    pub fn rotate(&self) {
+        lock(&self.state).rotate = true;
+    }
+
+    /// Deletes the session and its cookie, such as on logout.
+    pub fn destroy(&self) {
+        let mut state = lock(&self.state);
+        state.data.clear();
+        state.destroyed = true;
+    }
+}
+
+/// Loads the session for each request and stores it again once the handler has responded.
+#[derive(Clone)]
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    ttl: Duration,
+}
+
+impl Sessions {
+    pub fn new(store: Arc<dyn SessionStore>, ttl: Duration) -> Self {
+        Self { store, ttl }
+    }
+
+    /// The session named by the request's cookie. IDs the store doesn't know are not adopted,
+    /// so a session always starts out with an ID the server chose.
+    pub fn load(&self, rq: &Request) -> Result<Session> {
+        let id = rq.cookie(COOKIE_NAME).filter(|id| is_valid_id(id));
+
+        let state = match id {
+            Some(id) => match self.store.load(&id)? {
+                Some(data) => State {
+                    id: Some(id),
+                    data,
+                    ..State::default()
+                },
+                None => State::default(),
+            },
+            None => State::default(),
+        };
+
+        Ok(Session {
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    /// Saves the session, renewing its expiry, and sets the cookie if the ID is new. The cookie
+    /// is only sent back over HTTPS if that's how `rq` arrived.
+    pub fn commit(&self, rq: &Request, session: &Session, response: &mut Response) -> Result<()> {
+        let state = lock(&session.state);
+        let secure = rq.peer.is_some_and(|peer| peer.secure);
+
+        let cookie = |cookie: SetCookie| match secure {
+            true => cookie.secure(),
+            false => cookie,
+        };
+
+        // A session is only worth storing once there is something in it.
+        if state.destroyed || state.data.is_empty() {
+            if let Some(id) = &state.id {
+                self.store.remove(id)?;
+                response.set_cookie(&cookie(SetCookie::removal(COOKIE_NAME).path("/")));
+            }
+
+            return Ok(());
+        }
+
+        let id = match &state.id {
+            Some(id) if !state.rotate => id.clone(),
+            old => {
+                if let Some(old) = old {
+                    self.store.remove(old)?;
+                }
+
+                let id = generate_id()?;
+                let cookie = cookie(
+                    SetCookie::new(COOKIE_NAME, &id)
+                        .path("/")
+                        .http_only()
+                        .same_site(SameSite::Lax),
+                );
+
+                response.set_cookie(&cookie);
+                id
+            }
+        };
+
+        self.store
+            .save(&id, &state.data, SystemTime::now() + self.ttl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn data(pairs: &[(&str, &str)]) -> SessionData {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn later() -> SystemTime {
+        SystemTime::now() + Duration::from_secs(60)
+    }
+
+    fn earlier() -> SystemTime {
+        SystemTime::now() - Duration::from_secs(60)
+    }
+
+    // Loads the session for a request presenting `cookie`, lets `handle` change it, and commits
+    // it, returning the Set-Cookie fields sent back.
+    fn exchange(
+        sessions: &Sessions,
+        cookie: Option<&str>,
+        handle: impl FnOnce(&Session),
+    ) -> Vec<String> {
+        let head = match cookie {
+            Some(id) => format!("GET / HTTP/1.1\r\nCookie: {COOKIE_NAME}={id}\r\n\r\n"),
+            None => "GET / HTTP/1.1\r\n\r\n".into(),
+        };
+
+        let mut reader = head.as_bytes();
+        let mut interim = Vec::new();
+        let rq = Request::parse(&mut reader, &mut interim, 0).unwrap();
+
+        let session = sessions.load(&rq).unwrap();
+        handle(&session);
+
+        let mut response = Response::builder(&rq).build().unwrap();
+        sessions.commit(&rq, &session, &mut response).unwrap();
+        response
+            .headers
+            .get_all("Set-Cookie")
+            .map(String::from)
+            .collect()
+    }
+
+    // The ID a Set-Cookie field issues.
+    fn issued(set_cookie: &str) -> String {
+        let (pair, _) = set_cookie.split_once(';').unwrap();
+        let (name, id) = pair.split_once('=').unwrap();
+        assert_eq!(name, COOKIE_NAME);
+        id.into()
+    }
+
+    #[test]
+    fn memory_store_keeps_sessions_until_they_expire() {
+        let store = MemoryStore::new();
+        let fresh = data(&[("user", "ann")]);
+
+        store.save("a", &fresh, later()).unwrap();
+        store.save("b", &fresh, earlier()).unwrap();
+
+        assert_eq!(store.load("a").unwrap(), Some(fresh));
+        assert_eq!(store.load("b").unwrap(), None);
+        assert_eq!(store.load("c").unwrap(), None);
+
+        store.remove("a").unwrap();
+        assert_eq!(store.load("a").unwrap(), None);
+    }
+
+    #[test]
+    fn file_store_round_trips_data() {
+        let dir = TempDir::new();
+        let store = FileStore::new(dir.path().into());
+        let odd = data(&[
+            ("user", "ann"),
+            ("next", "/a?b=c&d=e"),
+            ("note", "line\nbreak"),
+        ]);
+
+        store.save("a", &odd, later()).unwrap();
+        assert_eq!(store.load("a").unwrap(), Some(odd));
+
+        store.remove("a").unwrap();
+        assert_eq!(store.load("a").unwrap(), None);
+        assert!(!dir.path().join("a").exists());
+        store.remove("a").unwrap();
+    }
+
+    #[test]
+    fn file_store_removes_expired_sessions() {
+        let dir = TempDir::new();
+        let store = FileStore::new(dir.path().into());
+
+        store
+            .save("a", &data(&[("user", "ann")]), earlier())
+            .unwrap();
+        assert_eq!(store.load("a").unwrap(), None);
+        assert!(!dir.path().join("a").exists());
+    }
+
+    #[test]
+    fn file_store_treats_corrupt_files_as_expired() {
+        let dir = TempDir::new();
+        let store = FileStore::new(dir.path().into());
+
+        for contents in [
+            "",
+            "soon\nuser=ann\n",
+            "-1\nuser=ann\n",
+            "99999999999999999999\n",
+        ] {
+            let path = dir.write("a", contents);
+            assert_eq!(store.load("a").unwrap(), None, "{contents:?}");
+            assert!(!path.exists());
+        }
+
+        let path = dir.path().join("a");
+        fs::write(&path, b"\xff\xfe\nuser=ann\n").unwrap();
+        assert_eq!(store.load("a").unwrap(), None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn accepts_only_ids_shaped_like_ours() {
+        assert!(is_valid_id(&generate_id().unwrap()));
+        assert_ne!(generate_id().unwrap(), generate_id().unwrap());
+
+        assert!(!is_valid_id(""));
+        assert!(!is_valid_id("abc"));
+        assert!(!is_valid_id(&format!(
+            "../{}",
+            &generate_id().unwrap()[3..]
+        )));
+        assert!(!is_valid_id(&format!("{}=", &generate_id().unwrap()[1..])));
+    }
+
+    #[test]
+    fn issues_ids_only_for_sessions_with_data() {
+        let sessions = Sessions::new(Arc::new(MemoryStore::new()), Duration::from_secs(60));
+
+        assert!(exchange(&sessions, None, |_| {}).is_empty());
+
+        let cookies = exchange(&sessions, None, |session| {
+            assert!(!session.is_established());
+            session.insert("user", "ann");
+        });
+        assert_eq!(cookies.len(), 1);
+        assert!(cookies[0].contains("HttpOnly"));
+        assert!(cookies[0].contains("SameSite=Lax"));
+        assert!(!cookies[0].contains("Secure"));
+
+        let id = issued(&cookies[0]);
+        let cookies = exchange(&sessions, Some(&id), |session| {
+            assert!(session.is_established());
+            assert_eq!(session.get("user").as_deref(), Some("ann"));
+        });
+        assert!(cookies.is_empty());
+    }
+
+    #[test]
+    fn ignores_ids_the_store_does_not_know() {
+        let sessions = Sessions::new(Arc::new(MemoryStore::new()), Duration::from_secs(60));
+        let planted = generate_id().unwrap();
+
+        let cookies = exchange(&sessions, Some(&planted), |session| {
+            assert!(!session.is_established());
+            session.insert("user", "ann");
+        });
+        assert_ne!(issued(&cookies[0]), planted);
+    }
+
+    #[test]
+    fn rotation_moves_data_to_a_new_id() {
+        let store = Arc::new(MemoryStore::new());
+        let sessions = Sessions::new(store.clone(), Duration::from_secs(60));
+
+        let old = issued(&exchange(&sessions, None, |session| session.insert("user", "ann"))[0]);
+
+        let cookies = exchange(&sessions, Some(&old), |session| {
+            session.insert("role", "admin");
+            session.rotate();
+        });
+        let new = issued(&cookies[0]);
+
+        assert_ne!(new, old);
+        assert_eq!(store.load(&old).unwrap(), None);
+        assert_eq!(
+            store.load(&new).unwrap(),
+            Some(data(&[("user", "ann"), ("role", "admin")]))
+        );
+    }
+
+    #[test]
+    fn destroying_removes_the_session_and_its_cookie() {
+        let dir = TempDir::new();
+        let store = Arc::new(FileStore::new(dir.path().into()));
+        let sessions = Sessions::new(store.clone(), Duration::from_secs(60));
+
+        let id = issued(&exchange(&sessions, None, |session| session.insert("user", "ann"))[0]);
+        assert!(dir.path().join(&id).exists());
+
+        let cookies = exchange(&sessions, Some(&id), |session| session.destroy());
+        assert_eq!(cookies.len(), 1);
+        assert!(cookies[0].starts_with(&format!("{COOKIE_NAME}=;")));
+        assert!(!dir.path().join(&id).exists());
+    }
+
+    #[test]
+    fn corrupt_sessions_get_a_new_id() {
+        let dir = TempDir::new();
+        let sessions = Sessions::new(
+            Arc::new(FileStore::new(dir.path().into())),
+            Duration::from_secs(60),
+        );
+
+        let id = issued(&exchange(&sessions, None, |session| session.insert("user", "ann"))[0]);
+        dir.write(&id, "garbage\nuser=mallory\n");
+
+        let cookies = exchange(&sessions, Some(&id), |session| {
+            assert!(!session.is_established());
+            assert_eq!(session.get("user"), None);
+            session.insert("user", "ann");
+        });
+        assert_ne!(issued(&cookies[0]), id);
+        assert!(!dir.path().join(&id).exists());
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    fmt,
+    io::{self, Cursor, Read},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+use crate::{prelude::*, response::ResponseBuilder};
+
+/// Sent when no event has been produced for a while, so proxies keep the connection open and a
+/// client that has gone away is noticed.
+const HEARTBEAT: &[u8] = b": heartbeat\n\n";
+
+/// One event of a `text/event-stream` response.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: &str) -> Self {
+        Self {
+            data: data.into(),
+            ..Self::default()
+        }
+    }
+
+    /// What the client sends back as `Last-Event-ID` when it reconnects.
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// The event's type, which clients listen for by name. Untyped events are `message`s.
+    pub fn event(mut self, name: &str) -> Self {
+        self.event = Some(name.into());
+        self
+    }
+
+    /// How long the client should wait before reconnecting.
+    pub fn retry(mut self, delay: Duration) -> Self {
+        self.retry = Some(delay);
+        self
+    }
+}
+
+// A line break would end the field early and start another.
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n', '\0'], "")
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", single_line(id))?;
+        }
+
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+
+        // Each line of the data is a field of its own, which the client joins up again.
+        for line in self.data.split("\r\n").flat_map(|l| l.split(['\r', '\n'])) {
+            writeln!(f, "data: {line}")?;
+        }
+
+        writeln!(f)
+    }
+}
+
+/// Produces the events of a stream, from any thread. Sending fails once the client has gone.
+#[derive(Debug, Clone)]
+pub struct EventSender(mpsc::Sender<Event>);
+
+impl EventSender {
+    pub fn send(&self, event: Event) -> Result<()> {
+        self.0
+            .send(event)
+            .map_err(|_| Error::Generic("The event stream is closed.".into()))
+    }
+}
+
+/// The body of a `text/event-stream` response, which ends once every sender is dropped.
+#[derive(Debug)]
+pub struct EventStream {
+    events: mpsc::Receiver<Event>,
+    heartbeat: Duration,
+    pending: Cursor<Vec<u8>>,
+}
+
+impl Read for EventStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.pending.read(buf)?;
+
+            if n > 0 {
+                return Ok(n);
+            }
+
+            let next = match self.events.recv_timeout(self.heartbeat) {
+                Ok(event) => event.to_string().into_bytes(),
+                Err(RecvTimeoutError::Timeout) => HEARTBEAT.to_vec(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+
+            self.pending = Cursor::new(next);
+        }
+    }
+}
+
+/// A stream for a handler to respond with, and the sender it produces events through, sending
+/// a heartbeat whenever `heartbeat` passes without one.
+pub fn channel(heartbeat: Duration) -> (EventSender, EventStream) {
+    let (sender, events) = mpsc::channel();
+    let stream = EventStream {
+        events,
+        heartbeat,
+        pending: Cursor::new(Vec::new()),
+    };
+
+    (EventSender(sender), stream)
+}
+
+impl ResponseBuilder {
+    /// Streams events as they're sent. Compressing them would hold them back, so they never are.
+    pub fn events(self, stream: EventStream) -> Self {
+        self.identity()
+            .cache_control("no-cache")
+            .stream(MimeType::EventStream, stream, None)
+    }
+}
+
+impl Request<'_> {
+    /// The ID of the last event a reconnecting client received, to resume the stream after.
+    pub fn last_event_id(&self) -> Option<&String> {
+        self.header("Last-Event-ID")
+    }
+}
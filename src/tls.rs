@@ -0,0 +1,276 @@
+use std::path::PathBuf;
+
+use crate::prelude::*;
+
+/// Where to find the certificate and key for the HTTPS listener. HTTPS is off unless both are
+/// set.
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub port: u32,
+    /// CA certificates that client certificates must chain to. Clients without one are refused.
+    pub client_ca: Option<PathBuf>,
+}
+
+impl Default for TlsOptions {
+    fn default() -> Self {
+        Self {
+            cert: None,
+            key: None,
+            port: 4443,
+            client_ca: None,
+        }
+    }
+}
+
+impl TlsOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.cert.is_some() || self.key.is_some()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match (&self.cert, &self.key) {
+            (Some(_), None) | (None, Some(_)) => Err(Error::Generic(
+                "HTTPS needs both --tls-cert and --tls-key.".into(),
+            )),
+            (None, None) if self.client_ca.is_some() => Err(Error::Generic(
+                "--tls-client-ca needs --tls-cert and --tls-key.".into(),
+            )),
+            #[cfg(not(feature = "tls"))]
+            (Some(_), Some(_)) => Err(Error::Generic(
+                "HTTPS support was not compiled in; enable the tls feature.".into(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+pub use acceptor::{accept, close, is_http2, server_config, split};
+
+#[cfg(feature = "tls")]
+mod acceptor {
+    use std::{
+        io::{self, Read, Write},
+        net::TcpStream,
+        path::Path,
+        sync::{Arc, Mutex, MutexGuard},
+    };
+
+    use rustls::{
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+    };
+
+    use super::TlsOptions;
+    use crate::prelude::*;
+
+    pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+    /// Protocols offered through ALPN, most preferred first.
+    const ALPN_PROTOCOLS: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+    /// The size of reads from the socket once a stream is split.
+    const READ_BUFFER: usize = 16 * 1024;
+
+    fn tls_error(e: impl std::fmt::Display) -> Error {
+        Error::Generic(format!("TLS error: {e}."))
+    }
+
+    fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+        let certs = CertificateDer::pem_file_iter(path)
+            .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+            .map_err(|e| {
+                Error::Generic(format!(
+                    "Failed to read certificates from '{}': {e}.",
+                    path.display()
+                ))
+            })?;
+
+        if certs.is_empty() {
+            return Err(Error::Generic(format!(
+                "No certificates in '{}'.",
+                path.display()
+            )));
+        }
+
+        Ok(certs)
+    }
+
+    pub fn server_config(options: &TlsOptions) -> Result<Arc<ServerConfig>> {
+        let (Some(cert), Some(key)) = (&options.cert, &options.key) else {
+            return Err(Error::Generic(
+                "HTTPS needs both --tls-cert and --tls-key.".into(),
+            ));
+        };
+
+        let certs = read_certs(cert)?;
+        let key = PrivateKeyDer::from_pem_file(key).map_err(|e| {
+            Error::Generic(format!(
+                "Failed to read private key from '{}': {e}.",
+                key.display()
+            ))
+        })?;
+
+        let builder = ServerConfig::builder();
+
+        let builder = match &options.client_ca {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+
+                for cert in read_certs(path)? {
+                    roots.add(cert).map_err(tls_error)?;
+                }
+
+                let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                    .build()
+                    .map_err(tls_error)?;
+
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder.with_single_cert(certs, key).map_err(tls_error)?;
+
+        config.alpn_protocols = ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect();
+        Ok(Arc::new(config))
+    }
+
+    /// Tells the client the response is complete, so that closing the socket isn't mistaken for
+    /// a truncation attack.
+    pub fn close(stream: &mut TlsStream) -> Result<()> {
+        stream.conn.send_close_notify();
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Wraps an accepted connection and completes the handshake, which settles the protocol.
+    pub fn accept(config: &Arc<ServerConfig>, stream: TcpStream) -> Result<TlsStream> {
+        let connection = ServerConnection::new(config.clone()).map_err(tls_error)?;
+        let mut stream = StreamOwned::new(connection, stream);
+
+        while stream.conn.is_handshaking() {
+            if stream.conn.complete_io(&mut stream.sock)? == (0, 0) {
+                return Err(tls_error("connection closed during handshake"));
+            }
+        }
+
+        Ok(stream)
+    }
+
+    /// Whether the client chose HTTP/2 through ALPN.
+    pub fn is_http2(stream: &TlsStream) -> bool {
+        stream.conn.alpn_protocol() == Some(b"h2")
+    }
+
+    fn lock(conn: &Mutex<ServerConnection>) -> MutexGuard<'_, ServerConnection> {
+        conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Splits a stream so that one thread can read from it while others write, as HTTP/2 needs.
+    /// Each half holds its own handle to the socket and locks the connection only while it
+    /// encrypts or decrypts. Frames are sent as soon as they're written, rather than held back
+    /// for acknowledgements.
+    pub fn split(stream: TlsStream) -> Result<(TlsReader, TlsWriter)> {
+        let StreamOwned { conn, sock } = stream;
+        sock.set_nodelay(true)?;
+        let conn = Arc::new(Mutex::new(conn));
+
+        let reader = TlsReader {
+            conn: conn.clone(),
+            sock: sock.try_clone()?,
+            buffer: vec![0; READ_BUFFER],
+            start: 0,
+            end: 0,
+        };
+
+        Ok((reader, TlsWriter { conn, sock }))
+    }
+
+    pub struct TlsReader {
+        conn: Arc<Mutex<ServerConnection>>,
+        sock: TcpStream,
+        /// Records read from the socket, of which `start..end` are yet to be decrypted.
+        buffer: Vec<u8>,
+        start: usize,
+        end: usize,
+    }
+
+    impl Read for TlsReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                match lock(&self.conn).reader().read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    result => return result,
+                }
+
+                // The socket is read without the lock, so writers aren't held up meanwhile.
+                if self.start == self.end {
+                    self.end = self.sock.read(&mut self.buffer)?;
+                    self.start = 0;
+
+                    if self.end == 0 {
+                        return Ok(0);
+                    }
+                }
+
+                // Records are decrypted a little at a time, as the connection only buffers so
+                // much plaintext.
+                let mut conn = lock(&self.conn);
+                self.start += conn.read_tls(&mut &self.buffer[self.start..self.end])?;
+                conn.process_new_packets().map_err(io::Error::other)?;
+
+                // Alerts and key updates have to be answered.
+                while conn.wants_write() {
+                    conn.write_tls(&mut self.sock)?;
+                }
+            }
+        }
+    }
+
+    pub struct TlsWriter {
+        conn: Arc<Mutex<ServerConnection>>,
+        sock: TcpStream,
+    }
+
+    impl Write for TlsWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut conn = lock(&self.conn);
+            let n = conn.writer().write(buf)?;
+
+            while conn.wants_write() {
+                conn.write_tls(&mut self.sock)?;
+            }
+
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            let mut conn = lock(&self.conn);
+            conn.writer().flush()?;
+
+            while conn.wants_write() {
+                conn.write_tls(&mut self.sock)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    // Like `close`, so that the client can tell the connection ended on purpose.
+    impl Drop for TlsWriter {
+        fn drop(&mut self) {
+            let mut conn = lock(&self.conn);
+            conn.send_close_notify();
+
+            while conn.wants_write() {
+                if conn.write_tls(&mut self.sock).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
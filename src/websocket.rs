@@ -0,0 +1,754 @@
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    prelude::*,
+    router::{Context, SocketEndpoint},
+};
+
+/// Appended to the client's key before hashing, so the answer proves the server speaks
+/// WebSocket rather than echoing whatever it was sent.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const VERSION: &str = "13";
+const KEY_LEN: usize = 16;
+/// Control frames can't be fragmented, so their payload has to fit the short length form.
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+const FIN: u8 = 0x80;
+const RESERVED: u8 = 0x70;
+const MASKED: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Opcode {
+    Continuation = 0x0,
+    Text = 0x1,
+    Binary = 0x2,
+    Close = 0x8,
+    Ping = 0x9,
+    Pong = 0xa,
+}
+
+impl Opcode {
+    fn is_control(self) -> bool {
+        self as u8 & 0x8 != 0
+    }
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = Error;
+
+    fn try_from(n: u8) -> Result<Self> {
+        match n {
+            0x0 => Ok(Self::Continuation),
+            0x1 => Ok(Self::Text),
+            0x2 => Ok(Self::Binary),
+            0x8 => Ok(Self::Close),
+            0x9 => Ok(Self::Ping),
+            0xa => Ok(Self::Pong),
+            _ => Err(Error::Generic("Failed to parse opcode.".into())),
+        }
+    }
+}
+
+/// Why a WebSocket was closed, as sent in close frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CloseCode {
+    Normal = 1000,
+    GoingAway = 1001,
+    ProtocolError = 1002,
+    InvalidData = 1007,
+    MessageTooBig = 1009,
+    InternalError = 1011,
+}
+
+impl fmt::Display for CloseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Self::Normal => "Normal Closure",
+            Self::GoingAway => "Going Away",
+            Self::ProtocolError => "Protocol Error",
+            Self::InvalidData => "Invalid Frame Payload Data",
+            Self::MessageTooBig => "Message Too Big",
+            Self::InternalError => "Internal Error",
+        };
+
+        write!(f, "{} {msg}", *self as u16)
+    }
+}
+
+// The codes a peer may put on the wire: the defined ones, and those for libraries and
+// applications.
+fn is_valid_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The status code and reason the peer closed with, if it gave one.
+    Close(Option<(u16, String)>),
+}
+
+/// Ends a connection: either the client broke the protocol, which it's told about, or the
+/// transport failed.
+enum Failure {
+    Protocol(CloseCode),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<Failure> for Error {
+    fn from(failure: Failure) -> Self {
+        match failure {
+            Failure::Protocol(code) => Error::Generic(format!("WebSocket closed: {code}.")),
+            Failure::Io(e) => e.into(),
+        }
+    }
+}
+
+type Outcome<T> = core::result::Result<T, Failure>;
+
+fn protocol_error<T>() -> Outcome<T> {
+    Err(Failure::Protocol(CloseCode::ProtocolError))
+}
+
+struct Frame {
+    fin: bool,
+    opcode: Opcode,
+    payload: Vec<u8>,
+}
+
+/// A WebSocket on the connection that accepted it. Messages are read whole, however the client
+/// fragments them, and pings are answered as they arrive.
+pub struct WebSocket<'c> {
+    reader: &'c mut dyn BufRead,
+    writer: &'c mut dyn Write,
+    max_message_size: u64,
+    /// The opcode and data of a fragmented message still being received.
+    partial: Option<(Opcode, Vec<u8>)>,
+    sent_close: bool,
+    received_close: bool,
+}
+
+impl<'c> WebSocket<'c> {
+    fn new(reader: &'c mut dyn BufRead, writer: &'c mut dyn Write, max_message_size: u64) -> Self {
+        Self {
+            reader,
+            writer,
+            max_message_size,
+            partial: None,
+            sent_close: false,
+            received_close: false,
+        }
+    }
+
+    /// The next message, or `None` once the client has closed the connection. A client that
+    /// breaks the protocol is sent a close frame saying why, and the error is returned.
+    pub fn receive(&mut self) -> Result<Option<Message>> {
+        if self.received_close {
+            return Ok(None);
+        }
+
+        match self.read_message() {
+            Ok(message) => Ok(message),
+            Err(Failure::Protocol(code)) => {
+                self.received_close = true;
+
+                if !self.sent_close {
+                    let _ = self.close(code as u16, "");
+                }
+
+                Err(Failure::Protocol(code).into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn send(&mut self, message: Message) -> Result<()> {
+        match message {
+            Message::Text(text) => self.send_text(&text),
+            Message::Binary(data) => self.send_binary(&data),
+            Message::Ping(data) => self.send_control(Opcode::Ping, &data),
+            Message::Pong(data) => self.send_control(Opcode::Pong, &data),
+            Message::Close(Some((code, reason))) => self.close(code, &reason),
+            Message::Close(None) => self.send_control(Opcode::Close, &[]),
+        }
+    }
+
+    pub fn send_text(&mut self, text: &str) -> Result<()> {
+        Ok(self.write_frame(Opcode::Text, text.as_bytes())?)
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> Result<()> {
+        Ok(self.write_frame(Opcode::Binary, data)?)
+    }
+
+    /// Starts the closing handshake. Messages may still arrive until the client answers with a
+    /// close of its own, but none can be sent.
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<()> {
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend(reason.as_bytes());
+        self.send_control(Opcode::Close, &payload)
+    }
+
+    fn send_control(&mut self, opcode: Opcode, payload: &[u8]) -> Result<()> {
+        if payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(Error::Generic(
+                "Control frames carry at most 125 bytes.".into(),
+            ));
+        }
+
+        Ok(self.write_frame(opcode, payload)?)
+    }
+
+    fn write_frame(&mut self, opcode: Opcode, payload: &[u8]) -> io::Result<()> {
+        if self.sent_close {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The WebSocket is closed.",
+            ));
+        }
+
+        // Only clients mask their frames.
+        let mut head = vec![FIN | opcode as u8];
+
+        match payload.len() {
+            len @ 0..=125 => head.push(len as u8),
+            len @ 126..=0xffff => {
+                head.push(126);
+                head.extend((len as u16).to_be_bytes());
+            }
+            len => {
+                head.push(127);
+                head.extend((len as u64).to_be_bytes());
+            }
+        }
+
+        self.writer.write_all(&head)?;
+        self.writer.write_all(payload)?;
+        self.writer.flush()?;
+        self.sent_close = opcode == Opcode::Close;
+        Ok(())
+    }
+
+    fn read_message(&mut self) -> Outcome<Option<Message>> {
+        loop {
+            let Some(frame) = self.read_frame()? else {
+                return Ok(None);
+            };
+
+            let (opcode, data) = match (frame.opcode, self.partial.take()) {
+                (Opcode::Ping, partial) => {
+                    self.partial = partial;
+
+                    // A ping that crosses our close needs no answer.
+                    if !self.sent_close {
+                        self.write_frame(Opcode::Pong, &frame.payload)?;
+                    }
+
+                    return Ok(Some(Message::Ping(frame.payload)));
+                }
+                (Opcode::Pong, partial) => {
+                    self.partial = partial;
+                    return Ok(Some(Message::Pong(frame.payload)));
+                }
+                (Opcode::Close, _) => return self.closed(&frame.payload).map(Some),
+                (Opcode::Text | Opcode::Binary, None) => (frame.opcode, frame.payload),
+                (Opcode::Continuation, Some((opcode, mut data))) => {
+                    data.extend(frame.payload);
+                    (opcode, data)
+                }
+                // A new message can't start inside another, nor a continuation outside one.
+                _ => return protocol_error(),
+            };
+
+            if !frame.fin {
+                self.partial = Some((opcode, data));
+                continue;
+            }
+
+            return match opcode {
+                Opcode::Text => match String::from_utf8(data) {
+                    Ok(text) => Ok(Some(Message::Text(text))),
+                    Err(_) => Err(Failure::Protocol(CloseCode::InvalidData)),
+                },
+                _ => Ok(Some(Message::Binary(data))),
+            };
+        }
+    }
+
+    // Answers the client's close, completing the closing handshake.
+    fn closed(&mut self, payload: &[u8]) -> Outcome<Message> {
+        let close = match payload {
+            [] => None,
+            [_] => return protocol_error(),
+            [high, low, reason @ ..] => {
+                let code = u16::from_be_bytes([*high, *low]);
+
+                if !is_valid_code(code) {
+                    return protocol_error();
+                }
+
+                let Ok(reason) = String::from_utf8(reason.to_vec()) else {
+                    return Err(Failure::Protocol(CloseCode::InvalidData));
+                };
+
+                Some((code, reason))
+            }
+        };
+
+        self.received_close = true;
+
+        if !self.sent_close {
+            let payload = match &close {
+                Some((code, _)) => code.to_be_bytes().to_vec(),
+                None => Vec::new(),
+            };
+
+            self.write_frame(Opcode::Close, &payload)?;
+        }
+
+        Ok(Message::Close(close))
+    }
+
+    // The next frame, unmasked, or `None` if the connection ended between frames.
+    fn read_frame(&mut self) -> Outcome<Option<Frame>> {
+        let mut head = [0; 2];
+
+        match self.reader.read_exact(&mut head) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        // No extensions are negotiated, so none of the bits they'd use may be set.
+        if head[0] & RESERVED != 0 || head[1] & MASKED == 0 {
+            return protocol_error();
+        }
+
+        let fin = head[0] & FIN != 0;
+        let Ok(opcode) = Opcode::try_from(head[0] & 0xf) else {
+            return protocol_error();
+        };
+
+        let len = match head[1] & !MASKED {
+            126 => {
+                let mut len = [0; 2];
+                self.reader.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0; 8];
+                self.reader.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+
+        if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+            return protocol_error();
+        }
+
+        let received = self
+            .partial
+            .as_ref()
+            .map_or(0, |(_, data)| data.len() as u64);
+
+        if !opcode.is_control() && received.saturating_add(len) > self.max_message_size {
+            return Err(Failure::Protocol(CloseCode::MessageTooBig));
+        }
+
+        let mut mask = [0; 4];
+        self.reader.read_exact(&mut mask)?;
+
+        let mut payload = Vec::with_capacity(len as usize);
+        (&mut self.reader).take(len).read_to_end(&mut payload)?;
+
+        if payload.len() as u64 != len {
+            return Err(Failure::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+}
+
+type Takeover = Box<dyn FnOnce(&mut WebSocket) -> Result<()> + Send>;
+
+/// What runs on the connection once the handshake response has been written.
+pub struct Handoff(Takeover);
+
+impl fmt::Debug for Handoff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handoff").finish_non_exhaustive()
+    }
+}
+
+impl Handoff {
+    /// Runs the endpoint on the connection, then closes the WebSocket if it hasn't been already.
+    pub fn run(
+        self,
+        reader: &mut dyn BufRead,
+        writer: &mut dyn Write,
+        max_message_size: u64,
+    ) -> Result<()> {
+        let mut socket = WebSocket::new(reader, writer, max_message_size);
+        let result = (self.0)(&mut socket);
+
+        if !socket.sent_close {
+            let code = match result {
+                Ok(()) => CloseCode::Normal,
+                Err(_) => CloseCode::InternalError,
+            };
+
+            let _ = socket.close(code as u16, "");
+        }
+
+        result
+    }
+}
+
+/// Completes the opening handshake, after which `endpoint` takes over the connection. Clients
+/// that haven't asked to upgrade, or speak another version, are told what's required.
+pub fn accept(rq: &Request, cx: Context, endpoint: SocketEndpoint) -> Result<Response> {
+    if !rq.has_token("Upgrade", "websocket") || !rq.has_token("Connection", "Upgrade") {
+        return Response::builder(rq)
+            .status(StatusCode::UpgradeRequired)
+            .header("Connection", "Upgrade")
+            .header("Upgrade", "websocket")
+            .build();
+    }
+
+    // Upgrading is an HTTP/1.1 mechanism.
+    if rq.protocol != Protocol::Http11 {
+        return Err(Error::Status(StatusCode::BadRequest));
+    }
+
+    if rq.header("Sec-WebSocket-Version").map(|v| v.trim()) != Some(VERSION) {
+        return Response::builder(rq)
+            .status(StatusCode::UpgradeRequired)
+            .header("Sec-WebSocket-Version", VERSION)
+            .build();
+    }
+
+    let key = rq
+        .header("Sec-WebSocket-Key")
+        .map(|v| v.trim())
+        .filter(|key| STANDARD.decode(key).is_ok_and(|key| key.len() == KEY_LEN))
+        .ok_or(Error::Status(StatusCode::BadRequest))?;
+
+    let digest = Sha1::digest(format!("{key}{ACCEPT_GUID}"));
+
+    Response::builder(rq)
+        .status(StatusCode::SwitchingProtocols)
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Accept", STANDARD.encode(digest))
+        .websocket(Handoff(Box::new(move |socket| endpoint(socket, cx))))
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::config::Config;
+
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    // A frame as a client sends it, masked.
+    fn frame(head: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![head];
+
+        match payload.len() {
+            len @ 0..=125 => frame.push(MASKED | len as u8),
+            len @ 126..=0xffff => {
+                frame.push(MASKED | 126);
+                frame.extend((len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(MASKED | 127);
+                frame.extend((len as u64).to_be_bytes());
+            }
+        }
+
+        frame.extend(MASK);
+        frame.extend(payload.iter().zip(MASK.iter().cycle()).map(|(b, m)| b ^ m));
+        frame
+    }
+
+    // Receives every message the client sent, then what the server wrote back.
+    fn exchange(input: &[u8], max_message_size: u64) -> (Vec<Result<Option<Message>>>, Vec<u8>) {
+        let (mut reader, mut writer) = (input, Vec::new());
+        let mut results = Vec::new();
+
+        {
+            let mut socket = WebSocket::new(&mut reader, &mut writer, max_message_size);
+
+            loop {
+                let result = socket.receive();
+                let done = !matches!(result, Ok(Some(_)));
+                results.push(result);
+
+                if done {
+                    break;
+                }
+            }
+        }
+
+        (results, writer)
+    }
+
+    fn messages(input: &[u8]) -> (Vec<Message>, Vec<u8>) {
+        let (results, written) = exchange(input, 1 << 20);
+        let messages = results.into_iter().map_while(|r| r.unwrap()).collect();
+        (messages, written)
+    }
+
+    // The close frame the server answers a broken protocol with.
+    fn refusal(input: &[u8], max_message_size: u64) -> Vec<u8> {
+        let (results, written) = exchange(input, max_message_size);
+        assert!(results.last().unwrap().is_err());
+        written
+    }
+
+    fn close_frame(code: CloseCode) -> Vec<u8> {
+        [
+            &[FIN | Opcode::Close as u8, 2][..],
+            &(code as u16).to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn unmasks_frames() {
+        let input = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let (messages, written) = messages(&input);
+
+        assert_eq!(messages, vec![Message::Text("Hello".into())]);
+        assert!(written.is_empty());
+        assert_eq!(frame(0x81, b"Hello"), input);
+    }
+
+    #[test]
+    fn reads_extended_lengths() {
+        let (short, long) = (vec![7; 300], vec![9; 70_000]);
+        let input = [frame(0x82, &short), frame(0x82, &long)].concat();
+
+        assert_eq!(
+            messages(&input).0,
+            vec![Message::Binary(short), Message::Binary(long)]
+        );
+    }
+
+    #[test]
+    fn joins_fragments_around_control_frames() {
+        let input = [
+            frame(0x01, b"Hel"),
+            frame(0x89, b"hi"),
+            frame(0x00, b""),
+            frame(0x80, b"lo"),
+        ]
+        .concat();
+        let (messages, written) = messages(&input);
+
+        assert_eq!(
+            messages,
+            vec![Message::Ping(b"hi".to_vec()), Message::Text("Hello".into())]
+        );
+        assert_eq!(written, [0x8a, 0x02, b'h', b'i']);
+    }
+
+    #[test]
+    fn completes_the_closing_handshake() {
+        let mut payload = 1000u16.to_be_bytes().to_vec();
+        payload.extend(b"bye");
+
+        let input = [frame(0x88, &payload), frame(0x81, b"late")].concat();
+        let (results, written) = exchange(&input, 1 << 20);
+
+        assert_eq!(
+            results.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![Some(Message::Close(Some((1000, "bye".into())))), None]
+        );
+        assert_eq!(written, close_frame(CloseCode::Normal));
+    }
+
+    #[test]
+    fn answers_an_empty_close_in_kind() {
+        let (messages, written) = messages(&frame(0x88, b""));
+
+        assert_eq!(messages, vec![Message::Close(None)]);
+        assert_eq!(written, [0x88, 0x00]);
+    }
+
+    #[test]
+    fn ends_when_the_connection_does() {
+        let (results, _) = exchange(b"", 1 << 20);
+        assert!(matches!(results[..], [Ok(None)]));
+
+        // A frame cut off partway is an error rather than an end.
+        let input = frame(0x81, b"Hello");
+        let (results, written) = exchange(&input[..8], 1 << 20);
+        assert!(matches!(results[..], [Err(Error::IO(_))]));
+        assert!(written.is_empty());
+    }
+
+    #[test]
+    fn refuses_protocol_errors() {
+        let protocol_error = close_frame(CloseCode::ProtocolError);
+
+        for input in [
+            // Unmasked, with a reserved bit set, and with an unknown opcode.
+            vec![0x81, 0x00],
+            frame(0x91, b""),
+            frame(0x83, b""),
+            // A fragmented control frame, and one that's too long.
+            frame(0x09, b""),
+            frame(0x89, &[0; 126]),
+            // A continuation outside a message, and a message inside another.
+            frame(0x80, b"x"),
+            [frame(0x01, b"x"), frame(0x81, b"y")].concat(),
+            // Close payloads with half a code, and with one that can't be sent.
+            frame(0x88, &[0x03]),
+            frame(0x88, &1005u16.to_be_bytes()),
+            frame(0x88, &2000u16.to_be_bytes()),
+        ] {
+            assert_eq!(refusal(&input, 1 << 20), protocol_error, "{input:02x?}");
+        }
+    }
+
+    #[test]
+    fn refuses_text_that_is_not_utf8() {
+        let invalid_data = close_frame(CloseCode::InvalidData);
+        let close = [&1000u16.to_be_bytes()[..], &[0xff]].concat();
+
+        assert_eq!(refusal(&frame(0x81, &[0xff]), 1 << 20), invalid_data);
+        assert_eq!(refusal(&frame(0x88, &close), 1 << 20), invalid_data);
+    }
+
+    #[test]
+    fn refuses_messages_over_the_limit() {
+        let too_big = close_frame(CloseCode::MessageTooBig);
+        let fragments = [frame(0x02, &[0; 6]), frame(0x80, &[0; 6])].concat();
+
+        assert!(exchange(&frame(0x82, &[0; 10]), 10).0[0].is_ok());
+        assert_eq!(refusal(&frame(0x82, &[0; 11]), 10), too_big);
+        assert_eq!(refusal(&fragments, 10), too_big);
+    }
+
+    #[test]
+    fn writes_unmasked_frames() {
+        let mut writer = Vec::new();
+        let mut reader: &[u8] = b"";
+        let mut socket = WebSocket::new(&mut reader, &mut writer, 1 << 20);
+
+        socket.send_text("Hello").unwrap();
+        socket.send_binary(&[1; 256]).unwrap();
+        socket.send_binary(&[2; 65536]).unwrap();
+        assert!(socket.send(Message::Ping(vec![0; 126])).is_err());
+        socket.close(1001, "").unwrap();
+        assert!(socket.send_text("late").is_err());
+
+        let expected = [
+            &[0x81, 0x05][..],
+            b"Hello",
+            &[0x82, 0x7e, 0x01, 0x00],
+            &[1; 256],
+            &[0x82, 0x7f, 0, 0, 0, 0, 0, 1, 0, 0],
+            &[2; 65536],
+            &close_frame(CloseCode::GoingAway),
+        ]
+        .concat();
+
+        assert_eq!(writer, expected);
+    }
+
+    fn handshake(head: &str) -> Result<Response> {
+        let mut reader = head.as_bytes();
+        let mut interim = Vec::new();
+        let rq = Request::parse(&mut reader, &mut interim, 0).unwrap();
+        let cx = Context::new(Arc::new(Config::default()));
+
+        accept(&rq, cx, Arc::new(|_, _| Ok(())))
+    }
+
+    #[test]
+    fn accepts_the_opening_handshake() {
+        let mut response = handshake(
+            "GET /ws HTTP/1.1\r\n\
+             Upgrade: websocket\r\n\
+             Connection: keep-alive, Upgrade\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(response.code.code(), 101);
+        assert_eq!(
+            response.headers.get("Sec-WebSocket-Accept").unwrap(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert!(response.take_websocket().is_some());
+    }
+
+    #[test]
+    fn refuses_other_handshakes() {
+        let no_upgrade = handshake("GET /ws HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(no_upgrade.code.code(), 426);
+
+        let old_version = handshake(
+            "GET /ws HTTP/1.1\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Version: 8\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(old_version.code.code(), 426);
+        assert_eq!(
+            old_version.headers.get("Sec-WebSocket-Version").unwrap(),
+            VERSION
+        );
+    }
+
+    #[test]
+    fn refuses_malformed_keys() {
+        for key in ["", "dGhlIHNhbXBsZQ==", "not base64!"] {
+            let head = format!(
+                "GET /ws HTTP/1.1\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Version: 13\r\n\
+                 Sec-WebSocket-Key: {key}\r\n\r\n"
+            );
+
+            assert!(matches!(
+                handshake(&head),
+                Err(Error::Status(StatusCode::BadRequest))
+            ));
+        }
+    }
+}